@@ -2,7 +2,8 @@ use std::path::PathBuf;
 use std::{fs::File, io};
 
 use vibrato_rkyv::{
-    dictionary::{DictionaryInner, SystemDictionaryBuilder},
+    Dictionary,
+    dictionary::{DictionaryInner, FeatureSchema, SubWords, SystemDictionaryBuilder},
     errors::VibratoError,
 };
 
@@ -51,6 +52,20 @@ pub struct Args {
     /// This option is enabled when bi-gram information is specified.
     #[clap(long)]
     dual_connector: bool,
+
+    /// Schema of the feature columns stored in the dictionary.
+    ///
+    /// Either `ipadic`, `unidic`, or comma-separated column names, where the columns
+    /// named `lemma` and `reading` are used for lemmas and readings.
+    #[clap(long)]
+    feature_schema: Option<String>,
+
+    /// Index of the feature column listing the word ids of sub-words, such as `12/34`.
+    ///
+    /// The word id of an entry is its index in the lexicon file.
+    /// The column of a word without sub-words is empty or `*`.
+    #[clap(long)]
+    sub_word_column: Option<usize>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -119,7 +134,14 @@ pub fn run(args: Args) -> Result<(), BuildError> {
     let source = get_source_from_args(&args)?;
 
     println!("Compiling the system dictionary...");
-    let dict = build_dictionary(&source)?;
+    let mut dict = Dictionary::from_inner(build_dictionary(&source)?);
+    if let Some(schema) = args.feature_schema.as_deref().map(parse_feature_schema) {
+        dict = dict.with_feature_schema(schema);
+    }
+    if let Some(column) = args.sub_word_column {
+        let sub_words = SubWords::from_reader(File::open(&args.lexicon_in)?, column)?;
+        dict = dict.with_sub_words(sub_words)?;
+    }
 
     println!("Writing the system dictionary...");
     let file = File::create(&args.sysdic_out)?;
@@ -134,6 +156,14 @@ pub fn run(args: Args) -> Result<(), BuildError> {
     Ok(())
 }
 
+fn parse_feature_schema(schema: &str) -> FeatureSchema {
+    match schema {
+        "ipadic" => FeatureSchema::ipadic(),
+        "unidic" => FeatureSchema::unidic(),
+        _ => FeatureSchema::new(schema.split(',')),
+    }
+}
+
 /// Builds a dictionary from the specified source files.
 /// This is the core build logic, independent of the CLI.
 pub fn build_dictionary(source: &BuildSource) -> Result<DictionaryInner, BuildError> {
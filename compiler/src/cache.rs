@@ -0,0 +1,163 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use clap::{Parser, Subcommand};
+use vibrato_rkyv::dictionary::{CacheKey, CacheManager, CacheStatus, PruneOptions};
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "cache",
+    about = "Manage dictionaries cached by decompressing Zstandard-compressed files."
+)]
+pub struct Args {
+    /// Cache directory. Defaults to the global cache directory.
+    #[clap(long, global = true)]
+    dir: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    action: Action,
+}
+
+#[derive(Subcommand, Debug)]
+enum Action {
+    /// List the cached dictionaries in ascending order of their last use.
+    List,
+
+    /// Fully validate the cached dictionaries.
+    Verify,
+
+    /// Remove stale caches, orphaned files, and caches exceeding the limits.
+    Prune {
+        /// Maximum total size of the cached dictionaries, e.g., 500M or 2G.
+        #[clap(long, value_parser = parse_size)]
+        max_size: Option<u64>,
+
+        /// Maximum number of days since the last use.
+        #[clap(long)]
+        max_age_days: Option<u64>,
+
+        /// Also remove corrupted caches by fully validating them.
+        #[clap(long)]
+        verify: bool,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CacheError {
+    #[error(transparent)]
+    VibratoRkyv(#[from] vibrato_rkyv::errors::VibratoError),
+
+    #[error("The global cache directory cannot be determined. Specify --dir.")]
+    NoCacheDir,
+
+    #[error("{0} of the cached dictionaries are stale or corrupted.")]
+    Invalid(usize),
+}
+
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, unit) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => s.split_at(i),
+        None => (s, ""),
+    };
+    let unit = match unit.to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(format!("Unknown unit of size: {unit}")),
+    };
+    let n: u64 = digits.parse().map_err(|e| format!("{e}"))?;
+    n.checked_mul(unit)
+        .ok_or_else(|| format!("Too large size: {s}"))
+}
+
+fn format_age(time: SystemTime) -> String {
+    let secs = SystemTime::now()
+        .duration_since(time)
+        .unwrap_or_default()
+        .as_secs();
+    match secs {
+        0..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+pub fn run(args: Args) -> Result<(), CacheError> {
+    let manager = match args.dir {
+        Some(dir) => CacheManager::new(dir),
+        None => CacheManager::global_cache().ok_or(CacheError::NoCacheDir)?,
+    };
+    println!("Cache directory: {}", manager.dir().display());
+
+    match args.action {
+        Action::List => {
+            let entries = manager.entries()?;
+            for entry in &entries {
+                let source = entry
+                    .source()
+                    .map_or_else(|| "(unknown)".to_string(), |p| p.display().to_string());
+                let stale = if entry.is_stale() { " (stale)" } else { "" };
+                let key = match entry.key() {
+                    CacheKey::Metadata => "metadata",
+                    CacheKey::Content => "content",
+                    CacheKey::Explicit(_) => "explicit",
+                };
+                println!(
+                    "{}\t{}\t{} bytes\t{}\t{}{}",
+                    entry.hash(),
+                    key,
+                    entry.size(),
+                    format_age(entry.last_used()),
+                    source,
+                    stale,
+                );
+            }
+            let total_size: u64 = entries.iter().map(|e| e.size()).sum();
+            println!("{} entries, {} bytes in total", entries.len(), total_size);
+        }
+        Action::Verify => {
+            let mut n_invalid = 0;
+            for entry in manager.entries()? {
+                let status = manager.verify(&entry);
+                match &status {
+                    CacheStatus::Valid => println!("{}\tvalid", entry.hash()),
+                    CacheStatus::Stale => println!("{}\tstale", entry.hash()),
+                    CacheStatus::Corrupted(reason) => {
+                        println!("{}\tcorrupted: {}", entry.hash(), reason)
+                    }
+                }
+                if status != CacheStatus::Valid {
+                    n_invalid += 1;
+                }
+            }
+            if n_invalid != 0 {
+                return Err(CacheError::Invalid(n_invalid));
+            }
+        }
+        Action::Prune {
+            max_size,
+            max_age_days,
+            verify,
+        } => {
+            let mut options = PruneOptions::new().verify(verify);
+            if let Some(max_size) = max_size {
+                options = options.max_total_size(max_size);
+            }
+            if let Some(days) = max_age_days {
+                options = options.max_age(Duration::from_secs(days * 24 * 60 * 60));
+            }
+            let report = manager.prune(&options)?;
+            for path in report.removed() {
+                println!("Removed {}", path.display());
+            }
+            println!(
+                "Removed {} files, freed {} bytes",
+                report.removed().len(),
+                report.freed_bytes()
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -1,4 +1,5 @@
 mod build;
+mod cache;
 mod dictgen;
 mod full_build;
 mod train;
@@ -8,8 +9,8 @@ use clap::Parser;
 use thiserror::Error;
 
 use crate::{
-    build::BuildError, dictgen::DictgenError, full_build::FullBuildError, train::TrainError,
-    transmute_legacy::TransmuteLegacyError,
+    build::BuildError, cache::CacheError, dictgen::DictgenError, full_build::FullBuildError,
+    train::TrainError, transmute_legacy::TransmuteLegacyError,
 };
 
 #[derive(Parser, Debug)]
@@ -35,6 +36,9 @@ enum Command {
 
     /// Convert a legacy vibrato dictionary from bincode format to rkyv format.
     Transmute(transmute_legacy::Args),
+
+    /// Manage dictionaries cached by decompressing Zstandard-compressed files.
+    Cache(cache::Args),
 }
 
 #[derive(Debug, Error)]
@@ -49,6 +53,8 @@ pub enum CompileError {
     BuildError(#[from] BuildError),
     #[error(transparent)]
     TransmuteLegacy(#[from] TransmuteLegacyError),
+    #[error(transparent)]
+    Cache(#[from] CacheError),
 }
 
 fn main() -> Result<(), CompileError> {
@@ -59,5 +65,6 @@ fn main() -> Result<(), CompileError> {
         Command::Dictgen(args) => Ok(dictgen::run(args)?),
         Command::Build(args) => Ok(build::run(args)?),
         Command::Transmute(args) => Ok(transmute_legacy::run(args)?),
+        Command::Cache(args) => Ok(cache::run(args)?),
     }
 }
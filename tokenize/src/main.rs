@@ -4,6 +4,8 @@ use std::path::PathBuf;
 use std::str::FromStr;
 
 use vibrato_rkyv::dictionary::Dictionary;
+use vibrato_rkyv::tokenizer::search::{SearchPenalty, TokenizeMode};
+use vibrato_rkyv::tokenizer::worker::Granularity;
 use vibrato_rkyv::{CacheStrategy, Tokenizer};
 
 use clap::Parser;
@@ -27,6 +29,42 @@ impl FromStr for OutputMode {
     }
 }
 
+#[derive(Clone, Debug)]
+enum SegmentationMode {
+    Normal,
+    Search,
+    Extended,
+}
+
+impl FromStr for SegmentationMode {
+    type Err = &'static str;
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "normal" => Ok(Self::Normal),
+            "search" => Ok(Self::Search),
+            "extended" => Ok(Self::Extended),
+            _ => Err("Could not parse a segmentation mode"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum LatticeFormat {
+    Dot,
+    Json,
+}
+
+impl FromStr for LatticeFormat {
+    type Err = &'static str;
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "dot" => Ok(Self::Dot),
+            "json" => Ok(Self::Json),
+            _ => Err("Could not parse a lattice format"),
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[clap(name = "tokenize", about = "Predicts morphemes")]
 struct Args {
@@ -45,6 +83,21 @@ struct Args {
     /// Maximum length of unknown words.
     #[clap(short = 'M', long)]
     max_grouping_len: Option<usize>,
+
+    /// Segmentation mode. Choices are normal, search, and extended.
+    /// The search modes split long compounds in the same manner as Kuromoji.
+    #[clap(long, default_value = "normal")]
+    segmentation: SegmentationMode,
+
+    /// Outputs the sub-words of words decomposed in the dictionary,
+    /// which is compiled with `--sub-word-column`.
+    #[clap(long)]
+    fine: bool,
+
+    /// Outputs the lattice of each line instead of tokens, for debugging.
+    /// Choices are dot and json.
+    #[clap(short = 'L', long)]
+    lattice: Option<LatticeFormat>,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -55,8 +108,16 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let tokenizer = Tokenizer::new(dict)
         .ignore_space(args.ignore_space)?
-        .max_grouping_len(args.max_grouping_len.unwrap_or(0));
+        .max_grouping_len(args.max_grouping_len.unwrap_or(0))
+        .mode(match args.segmentation {
+            SegmentationMode::Normal => TokenizeMode::Normal,
+            SegmentationMode::Search => TokenizeMode::Search(SearchPenalty::kuromoji()),
+            SegmentationMode::Extended => TokenizeMode::Extended(SearchPenalty::kuromoji()),
+        })?;
     let mut worker = tokenizer.new_worker();
+    if args.fine {
+        worker.set_granularity(Granularity::Fine);
+    }
 
     eprintln!("Ready to tokenize");
 
@@ -69,6 +130,16 @@ fn main() -> Result<(), Box<dyn Error>> {
         let line = line?;
         worker.reset_sentence(line);
         worker.tokenize();
+        if let Some(format) = &args.lattice {
+            match format {
+                LatticeFormat::Dot => worker.write_lattice_dot(&mut out)?,
+                LatticeFormat::Json => worker.write_lattice_json(&mut out)?,
+            }
+            if is_tty {
+                out.flush()?;
+            }
+            continue;
+        }
         match args.output_mode {
             OutputMode::Mecab => {
                 for i in 0..worker.num_tokens() {
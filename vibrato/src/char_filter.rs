@@ -0,0 +1,427 @@
+//! Character filters applied to input sentences before tokenization.
+//!
+//! Filters are registered with [`Tokenizer::char_filter()`](crate::Tokenizer::char_filter)
+//! and applied in order by [`Worker::reset_sentence()`](crate::tokenizer::worker::Worker::reset_sentence).
+//! Positions of tokens refer to the filtered text, and the corresponding positions in
+//! the original text are available through
+//! [`Token::original_range_byte()`](crate::token::Token::original_range_byte) and
+//! [`Token::original_range_char()`](crate::token::Token::original_range_char).
+use std::ops::Range;
+use std::sync::Arc;
+
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::{
+    canonical_combining_class, compose, decompose_canonical, decompose_compatible,
+};
+
+use crate::utils::FromU32;
+
+/// Filter rewriting an input sentence before tokenization.
+pub trait CharFilter: Send + Sync {
+    /// Filters `input`, appending the result to `output` from the beginning of `input`.
+    ///
+    /// Parts of `input` not appended to `output` are kept unchanged.
+    fn filter(&self, input: &str, output: &mut FilterOutput);
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+    // Start byte position in the input
+    input: usize,
+    // Start byte position in the output
+    text: usize,
+    // If true, the segment is copied from the input as it is.
+    unchanged: bool,
+}
+
+/// Output of a [`CharFilter`] with the alignment to its input.
+///
+/// The output is built by appending segments, each of which replaces a part of the input.
+/// A position inside a replaced segment is mapped to the boundaries of the segment.
+#[derive(Default, Clone, Debug)]
+pub struct FilterOutput {
+    text: String,
+    segments: Vec<Segment>,
+    input_len: usize,
+}
+
+impl FilterOutput {
+    /// Appends `replacement` as the substitute for the next `input_len` bytes of the input.
+    ///
+    /// `input_len` must end at a character boundary of the input.
+    pub fn push(&mut self, input_len: usize, replacement: &str) {
+        if input_len == 0 && replacement.is_empty() {
+            return;
+        }
+        self.segments.push(Segment {
+            input: self.input_len,
+            text: self.text.len(),
+            unchanged: false,
+        });
+        self.input_len += input_len;
+        self.text.push_str(replacement);
+    }
+
+    /// Appends the next part of the input without changes.
+    pub fn push_unchanged(&mut self, input: &str) {
+        if input.is_empty() {
+            return;
+        }
+        if !self.segments.last().is_some_and(|s| s.unchanged) {
+            self.segments.push(Segment {
+                input: self.input_len,
+                text: self.text.len(),
+                unchanged: true,
+            });
+        }
+        self.input_len += input.len();
+        self.text.push_str(input);
+    }
+
+    /// Gets the filtered text.
+    #[inline(always)]
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn clear(&mut self) {
+        self.text.clear();
+        self.segments.clear();
+        self.input_len = 0;
+    }
+
+    /// Maps a start position in the output to the one in the input.
+    fn input_start(&self, pos: usize) -> usize {
+        if pos >= self.text.len() {
+            return self.input_len;
+        }
+        let seg = &self.segments[self.segments.partition_point(|s| s.text <= pos) - 1];
+        if seg.unchanged {
+            seg.input + (pos - seg.text)
+        } else {
+            seg.input
+        }
+    }
+
+    /// Maps an end position in the output to the one in the input.
+    fn input_end(&self, pos: usize) -> usize {
+        if pos == 0 {
+            return 0;
+        }
+        let i = self.segments.partition_point(|s| s.text < pos) - 1;
+        let seg = &self.segments[i];
+        if seg.unchanged {
+            seg.input + (pos - seg.text)
+        } else {
+            self.segments.get(i + 1).map_or(self.input_len, |s| s.input)
+        }
+    }
+}
+
+/// Original sentence and the outputs of the filters applied to it.
+#[derive(Default, Clone)]
+pub(crate) struct FilteredSentence {
+    original: String,
+    c2b: Vec<usize>,
+    outputs: Vec<FilterOutput>,
+}
+
+impl FilteredSentence {
+    /// Applies `filters` to `input` in order, returning the filtered text.
+    pub fn apply(&mut self, input: &str, filters: &[Arc<dyn CharFilter>]) -> &str {
+        self.original.clear();
+        self.original.push_str(input);
+        self.c2b.clear();
+        self.c2b.extend(input.char_indices().map(|(bi, _)| bi));
+        self.c2b.push(input.len());
+
+        self.outputs
+            .resize_with(filters.len(), FilterOutput::default);
+        for (i, filter) in filters.iter().enumerate() {
+            let (prev, next) = self.outputs.split_at_mut(i);
+            let input = prev.last().map_or(input, |o| o.text());
+            let output = &mut next[0];
+            output.clear();
+            filter.filter(input, output);
+            debug_assert!(output.input_len <= input.len());
+            if output.input_len < input.len() {
+                output.push_unchanged(&input[output.input_len..]);
+            }
+        }
+        self.outputs.last().map_or(&self.original, |o| o.text())
+    }
+
+    /// Gets the original sentence.
+    #[inline(always)]
+    pub fn original(&self) -> &str {
+        &self.original
+    }
+
+    /// Maps a byte range in the filtered text to the one in the original sentence.
+    pub fn original_range_byte(&self, range_byte: Range<usize>) -> Range<usize> {
+        let mut start = range_byte.start;
+        let mut end = range_byte.end;
+        for output in self.outputs.iter().rev() {
+            start = output.input_start(start);
+            end = output.input_end(end);
+        }
+        start..end.max(start)
+    }
+
+    /// Maps a byte range in the original sentence to the character range.
+    pub fn original_range_char(&self, range_byte: Range<usize>) -> Range<usize> {
+        let start = self.c2b.partition_point(|&b| b < range_byte.start);
+        let end = self.c2b.partition_point(|&b| b < range_byte.end);
+        start..end
+    }
+}
+
+/// Unicode normalization forms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormalizationForm {
+    /// Canonical decomposition followed by canonical composition.
+    Nfc,
+    /// Canonical decomposition.
+    Nfd,
+    /// Compatibility decomposition followed by canonical composition.
+    Nfkc,
+    /// Compatibility decomposition.
+    Nfkd,
+}
+
+/// Filter applying Unicode normalization.
+///
+/// The input is divided into segments that are normalized independently,
+/// each starting with a character that is not combined with preceding ones,
+/// so that a token can be aligned with the original text.
+///
+/// # Examples
+///
+/// ```
+/// use vibrato_rkyv::char_filter::{CharFilter, FilterOutput, UnicodeNormalizer};
+///
+/// let mut output = FilterOutput::default();
+/// UnicodeNormalizer::nfkc().filter("ｶﾞｸｾｲ①", &mut output);
+/// assert_eq!(output.text(), "ガクセイ1");
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct UnicodeNormalizer {
+    form: NormalizationForm,
+}
+
+impl UnicodeNormalizer {
+    /// Creates a new filter with the normalization form.
+    pub const fn new(form: NormalizationForm) -> Self {
+        Self { form }
+    }
+
+    /// Creates a new filter applying NFKC.
+    pub const fn nfkc() -> Self {
+        Self::new(NormalizationForm::Nfkc)
+    }
+
+    /// Checks if `c` can start a segment, i.e., it is not combined with the previous character.
+    fn is_segment_start(&self, c: char) -> bool {
+        let mut first = None;
+        let mut f = |d| {
+            first.get_or_insert(d);
+        };
+        match self.form {
+            NormalizationForm::Nfc | NormalizationForm::Nfd => decompose_canonical(c, &mut f),
+            NormalizationForm::Nfkc | NormalizationForm::Nfkd => decompose_compatible(c, &mut f),
+        }
+        let d = first.unwrap_or(c);
+        // Hangul vowels and trailing consonants are composed with the preceding syllable.
+        canonical_combining_class(d) == 0 && !('\u{1160}'..='\u{11FF}').contains(&d)
+    }
+
+    fn push_segment(&self, segment: &str, buf: &mut String, output: &mut FilterOutput) {
+        buf.clear();
+        match self.form {
+            NormalizationForm::Nfc => buf.extend(segment.nfc()),
+            NormalizationForm::Nfd => buf.extend(segment.nfd()),
+            NormalizationForm::Nfkc => buf.extend(segment.nfkc()),
+            NormalizationForm::Nfkd => buf.extend(segment.nfkd()),
+        }
+        if buf == segment {
+            output.push_unchanged(segment);
+        } else {
+            output.push(segment.len(), buf);
+        }
+    }
+}
+
+impl CharFilter for UnicodeNormalizer {
+    fn filter(&self, input: &str, output: &mut FilterOutput) {
+        let mut buf = String::new();
+        let mut start = 0;
+        for (i, c) in input.char_indices() {
+            if i != 0 && self.is_segment_start(c) {
+                self.push_segment(&input[start..i], &mut buf, output);
+                start = i;
+            }
+        }
+        self.push_segment(&input[start..], &mut buf, output);
+    }
+}
+
+/// Filter folding the width of characters.
+///
+/// By default, it converts
+///  - full-width ASCII characters and the ideographic space into half-width ones, and
+///  - half-width katakana into full-width ones, combining the following voiced sound marks.
+///
+/// # Examples
+///
+/// ```
+/// use vibrato_rkyv::char_filter::{CharFilter, FilterOutput, WidthFolding};
+///
+/// let mut output = FilterOutput::default();
+/// WidthFolding::new().filter("Ｖｉｂｒａｔｏ　ﾊﾞｲﾌﾞﾗｰﾄ", &mut output);
+/// assert_eq!(output.text(), "Vibrato バイブラート");
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct WidthFolding {
+    ascii: bool,
+    katakana: bool,
+}
+
+impl Default for WidthFolding {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WidthFolding {
+    /// Creates a new filter folding both ASCII characters and katakana.
+    pub const fn new() -> Self {
+        Self {
+            ascii: true,
+            katakana: true,
+        }
+    }
+
+    /// Specifies whether to convert full-width ASCII characters into half-width ones.
+    pub const fn ascii(mut self, yes: bool) -> Self {
+        self.ascii = yes;
+        self
+    }
+
+    /// Specifies whether to convert half-width katakana into full-width ones.
+    pub const fn katakana(mut self, yes: bool) -> Self {
+        self.katakana = yes;
+        self
+    }
+}
+
+/// Full-width characters of U+FF61..=U+FF9F.
+const HALFWIDTH_KATAKANA: [char; 63] = [
+    '。', '「', '」', '、', '・', 'ヲ', 'ァ', 'ィ', 'ゥ', 'ェ', 'ォ', 'ャ', 'ュ', 'ョ', 'ッ', 'ー',
+    'ア', 'イ', 'ウ', 'エ', 'オ', 'カ', 'キ', 'ク', 'ケ', 'コ', 'サ', 'シ', 'ス', 'セ', 'ソ', 'タ',
+    'チ', 'ツ', 'テ', 'ト', 'ナ', 'ニ', 'ヌ', 'ネ', 'ノ', 'ハ', 'ヒ', 'フ', 'ヘ', 'ホ', 'マ', 'ミ',
+    'ム', 'メ', 'モ', 'ヤ', 'ユ', 'ヨ', 'ラ', 'リ', 'ル', 'レ', 'ロ', 'ワ', 'ン', '゛', '゜',
+];
+
+impl CharFilter for WidthFolding {
+    fn filter(&self, input: &str, output: &mut FilterOutput) {
+        let mut chars = input.chars().peekable();
+        let mut buf = [0; 4];
+        while let Some(c) = chars.next() {
+            let len = c.len_utf8();
+            match c {
+                '\u{FF01}'..='\u{FF5E}' if self.ascii => {
+                    let folded = char::from_u32(u32::from(c) - 0xFEE0).unwrap();
+                    output.push(len, folded.encode_utf8(&mut buf));
+                }
+                '\u{3000}' if self.ascii => output.push(len, " "),
+                '\u{FF61}'..='\u{FF9F}' if self.katakana => {
+                    let base = HALFWIDTH_KATAKANA[usize::from_u32(u32::from(c) - 0xFF61)];
+                    let mark = match chars.peek() {
+                        Some('\u{FF9E}') => Some('\u{3099}'),
+                        Some('\u{FF9F}') => Some('\u{309A}'),
+                        _ => None,
+                    };
+                    if let Some(composed) = mark.and_then(|m| compose(base, m)) {
+                        let mark_len = chars.next().unwrap().len_utf8();
+                        output.push(len + mark_len, composed.encode_utf8(&mut buf));
+                    } else {
+                        output.push(len, base.encode_utf8(&mut buf));
+                    }
+                }
+                _ => output.push_unchanged(c.encode_utf8(&mut buf)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_output_mapping() {
+        let mut output = FilterOutput::default();
+        output.push_unchanged("ab");
+        output.push(3, "XYZW");
+        output.push(1, "");
+        output.push_unchanged("cd");
+
+        assert_eq!(output.text(), "abXYZWcd");
+        assert_eq!(output.input_start(0), 0);
+        assert_eq!(output.input_start(1), 1);
+        assert_eq!(output.input_start(2), 2);
+        assert_eq!(output.input_start(4), 2);
+        assert_eq!(output.input_start(6), 6);
+        assert_eq!(output.input_end(2), 2);
+        assert_eq!(output.input_end(4), 5);
+        assert_eq!(output.input_end(6), 5);
+        assert_eq!(output.input_end(7), 7);
+        assert_eq!(output.input_end(8), 8);
+    }
+
+    #[test]
+    fn test_filtered_sentence() {
+        let filters: Vec<Arc<dyn CharFilter>> = vec![
+            Arc::new(WidthFolding::new()),
+            Arc::new(UnicodeNormalizer::nfkc()),
+        ];
+        let mut sent = FilteredSentence::default();
+        let text = sent.apply("ﾄｳｷｮｳ㍿ＡＢ", &filters);
+        assert_eq!(text, "トウキョウ株式会社AB");
+
+        // "キョウ"
+        assert_eq!(sent.original_range_byte(6..15), 6..15);
+        assert_eq!(sent.original_range_char(6..15), 2..5);
+        // "株式"
+        assert_eq!(sent.original_range_byte(15..21), 15..18);
+        // "会社AB"
+        assert_eq!(sent.original_range_byte(21..29), 15..24);
+        assert_eq!(sent.original_range_char(15..24), 5..8);
+    }
+
+    #[test]
+    fn test_unicode_normalizer() {
+        let mut output = FilterOutput::default();
+        UnicodeNormalizer::nfkc().filter("ｶﾞｷﾞ", &mut output);
+        assert_eq!(output.text(), "ガギ");
+        assert_eq!(output.input_end(3), 6);
+
+        let mut output = FilterOutput::default();
+        UnicodeNormalizer::new(NormalizationForm::Nfd).filter("ガa", &mut output);
+        assert_eq!(output.text(), "カ\u{3099}a");
+        assert_eq!(output.input_end(3), 3);
+    }
+
+    #[test]
+    fn test_width_folding() {
+        let mut output = FilterOutput::default();
+        WidthFolding::new()
+            .katakana(false)
+            .filter("ＡﾊﾟＢ", &mut output);
+        assert_eq!(output.text(), "AﾊﾟB");
+
+        let mut output = FilterOutput::default();
+        WidthFolding::new().ascii(false).filter("Ａﾊﾟｯﾞ", &mut output);
+        assert_eq!(output.text(), "Ａパッ゛");
+    }
+}
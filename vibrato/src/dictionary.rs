@@ -1,11 +1,16 @@
 //! Dictionary for tokenization.
 pub mod builder;
+pub(crate) mod cache;
 pub(crate) mod character;
 pub(crate) mod config;
 pub(crate) mod connector;
 pub(crate) mod fetch;
+pub(crate) mod handle;
 pub(crate) mod lexicon;
 pub(crate) mod mapper;
+pub(crate) mod schema;
+pub(crate) mod section;
+pub(crate) mod sub_words;
 pub(crate) mod unknown;
 pub(crate) mod word_idx;
 
@@ -13,8 +18,9 @@ use std::fs::{self, File, Metadata, create_dir_all};
 use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::ops::Deref;
 
+use std::collections::HashSet;
 use std::path::PathBuf;
-use std::sync::{Arc, LazyLock};
+use std::sync::{Arc, LazyLock, Mutex};
 
 use memmap2::Mmap;
 use rkyv::{
@@ -29,6 +35,9 @@ use rkyv::{
 };
 use sha2::{Digest, Sha256};
 
+use crate::dictionary::cache::{
+    content_hash, explicit_key_hash, indexed_content_hash, lock_cache, touch_meta, write_meta,
+};
 use crate::dictionary::character::{ArchivedCharProperty, CharProperty};
 use crate::dictionary::connector::{ArchivedConnectorWrapper, Connector, ConnectorWrapper};
 use crate::dictionary::lexicon::{ArchivedLexicon, Lexicon};
@@ -37,9 +46,16 @@ use crate::dictionary::unknown::{ArchivedUnkHandler, UnkHandler};
 use crate::errors::{Result, VibratoError};
 
 pub use crate::dictionary::builder::SystemDictionaryBuilder;
+pub use crate::dictionary::cache::{
+    CacheEntry, CacheKey, CacheManager, CacheStatus, PruneOptions, PruneReport,
+};
+pub use crate::dictionary::handle::DictionaryHandle;
+pub use crate::dictionary::schema::FeatureSchema;
+pub use crate::dictionary::section::Sections;
+pub use crate::dictionary::sub_words::SubWords;
 pub use crate::dictionary::word_idx::WordIdx;
 
-pub(crate) use crate::dictionary::lexicon::WordParam;
+pub use crate::dictionary::lexicon::WordParam;
 
 #[cfg(feature = "download")]
 pub use crate::dictionary::config::PresetDictionaryKind;
@@ -57,6 +73,9 @@ const RKYV_ALIGNMENT: usize = 16;
 const PADDING_LEN: usize = (RKYV_ALIGNMENT - (MODEL_MAGIC_LEN % RKYV_ALIGNMENT)) % RKYV_ALIGNMENT;
 const DATA_START: usize = MODEL_MAGIC_LEN + PADDING_LEN;
 
+/// Upper bound of the decompressed size reserved in advance from a Zstandard frame header.
+const MAX_RESERVED_CONTENT_SIZE: u64 = 1 << 32;
+
 /// Prefix of magic bytes for legacy bincode-based models.
 pub const LEGACY_MODEL_MAGIC_PREFIX: &[u8] = b"VibratoTokenizer 0.";
 
@@ -128,11 +147,19 @@ pub struct DictionaryInner {
     unk_handler: UnkHandler,
 }
 
+/// Ranges of static bytes validated in this process, identified by their addresses and lengths.
+static VALIDATED_STATIC_BYTES: LazyLock<Mutex<HashSet<(usize, usize)>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+
 // Wrapper to own the memory buffer (mmap or heap) and provide access to the archived dictionary.
 #[allow(dead_code)]
 enum DictBuffer {
     Mmap(Mmap),
     Aligned(AlignedVec<16>),
+    // Bytes borrowed for the lifetime of the program, e.g., embedded with include_bytes!
+    Static(&'static [u8]),
+    // Bytes owned by an arbitrary buffer shared with the caller
+    Shared(Arc<dyn AsRef<[u8]> + Send + Sync>),
 }
 
 /// A read-only dictionary for tokenization, loaded via zero-copy deserialization.
@@ -141,14 +168,17 @@ pub enum Dictionary {
     Owned {
         dict: Arc<DictionaryInner>,
         _caching_handle: Option<Arc<std::thread::JoinHandle<Result<()>>>>,
+        sections: Sections,
     },
 }
 
 pub struct ArchivedDictionary {
     _buffer: DictBuffer,
     data: &'static ArchivedDictionaryInner,
+    sections: Sections,
 }
 
+#[derive(Clone, Copy)]
 pub(crate) enum DictionaryInnerRef<'a> {
     Archived(&'a ArchivedDictionaryInner),
     Owned(&'a DictionaryInner),
@@ -166,6 +196,8 @@ impl Deref for ArchivedDictionary {
     }
 }
 
+const CUSTOM_WORD_MSG: &str = "Words of custom lexicons are not stored in the dictionary.";
+
 /// Type of a lexicon that contains the word.
 #[derive(Clone, Copy, Eq, PartialEq, Debug, Hash, Archive, Serialize, Deserialize)]
 #[rkyv(compare(PartialEq), derive(Debug, Eq, PartialEq, Hash, Clone, Copy))]
@@ -179,6 +211,10 @@ pub enum LexType {
     User,
     /// Unknown words.
     Unknown,
+    /// Custom lexicon attached to a tokenizer.
+    ///
+    /// See [`CustomLexicon`](crate::tokenizer::custom_lexicon::CustomLexicon).
+    Custom,
 }
 
 impl ArchivedLexType {
@@ -188,6 +224,7 @@ impl ArchivedLexType {
             ArchivedLexType::System => LexType::System,
             ArchivedLexType::User => LexType::User,
             ArchivedLexType::Unknown => LexType::Unknown,
+            ArchivedLexType::Custom => LexType::Custom,
         }
     }
 }
@@ -220,7 +257,6 @@ impl DictionaryInner {
     }
 
     /// Gets the reference to the mapper for connection ids.
-    #[allow(dead_code)]
     #[inline(always)]
     pub(crate) const fn mapper(&self) -> Option<&ConnIdMapper> {
         self.mapper.as_ref()
@@ -239,12 +275,17 @@ impl DictionaryInner {
     }
 
     /// Gets the reference to the feature string.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the word is from a custom lexicon attached to a tokenizer.
     #[inline(always)]
     pub fn word_feature(&self, word_idx: WordIdx) -> &str {
         match word_idx.lex_type {
             LexType::System => self.system_lexicon().word_feature(word_idx),
             LexType::User => self.user_lexicon().unwrap().word_feature(word_idx),
             LexType::Unknown => self.unk_handler().word_feature(word_idx),
+            LexType::Custom => panic!("{CUSTOM_WORD_MSG}"),
         }
     }
 
@@ -259,6 +300,7 @@ impl DictionaryInner {
             LexType::System => self.system_lexicon().word_param(word_idx),
             LexType::User => self.user_lexicon().as_ref().unwrap().word_param(word_idx),
             LexType::Unknown => self.unk_handler().word_param(word_idx),
+            LexType::Custom => panic!("{CUSTOM_WORD_MSG}"),
         }
     }
 
@@ -298,7 +340,34 @@ impl DictionaryInner {
     /// This function will return an error if:
     /// - Writing to the underlying `writer` fails (e.g., an I/O error).
     /// - The `rkyv` serialization process encounters an error.
-    pub fn write<W>(&self, mut wtr: W) -> Result<()>
+    pub fn write<W>(&self, wtr: W) -> Result<()>
+    where
+        W: Write,
+    {
+        self.write_impl(&Sections::default(), wtr)
+    }
+
+    /// Serializes the dictionary data with the feature schema to a writer.
+    ///
+    /// The schema is restored by [`Dictionary::feature_schema()`] when the output is loaded.
+    /// The output can also be loaded by versions without the support of feature schemas,
+    /// which ignore the schema.
+    ///
+    /// # Errors
+    ///
+    /// See [`Self::write()`].
+    pub fn write_with_feature_schema<W>(&self, schema: &FeatureSchema, wtr: W) -> Result<()>
+    where
+        W: Write,
+    {
+        let sections = Sections {
+            feature_schema: Some(schema.clone()),
+            ..Sections::default()
+        };
+        self.write_impl(&sections, wtr)
+    }
+
+    fn write_impl<W>(&self, sections: &Sections, mut wtr: W) -> Result<()>
     where
         W: Write,
     {
@@ -307,6 +376,10 @@ impl DictionaryInner {
         let padding_bytes = vec![0xFF; PADDING_LEN];
         wtr.write_all(&padding_bytes)?;
 
+        // The archived data is accessed from its end, so the preceding sections are
+        // invisible to it.
+        sections.write(&mut wtr)?;
+
         with_arena(|arena: &mut Arena| {
             let writer = IoWriter::new(&mut wtr);
             let mut serializer = Serializer::new(writer, arena.acquire(), Share::new());
@@ -368,9 +441,70 @@ impl Dictionary {
         Self::Owned {
             dict: Arc::new(dict),
             _caching_handle: None,
+            sections: Sections::default(),
+        }
+    }
+
+    fn sections(&self) -> &Sections {
+        match self {
+            Dictionary::Archived(archived_dict) => &archived_dict.sections,
+            Dictionary::Owned { sections, .. } => sections,
         }
     }
 
+    fn sections_mut(&mut self) -> &mut Sections {
+        match self {
+            Dictionary::Archived(archived_dict) => &mut archived_dict.sections,
+            Dictionary::Owned { sections, .. } => sections,
+        }
+    }
+
+    /// Attaches the schema of feature strings, replacing the one stored in the dictionary.
+    pub fn with_feature_schema(mut self, schema: FeatureSchema) -> Self {
+        self.sections_mut().feature_schema = Some(schema);
+        self
+    }
+
+    /// Gets the schema of feature strings.
+    ///
+    /// Returns `None` if the dictionary was compiled without a schema and
+    /// no schema has been attached with [`Self::with_feature_schema()`].
+    pub fn feature_schema(&self) -> Option<&FeatureSchema> {
+        self.sections().feature_schema.as_ref()
+    }
+
+    /// Attaches the decomposition of words into sub-words, replacing the one stored
+    /// in the dictionary.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when the number of words mismatches the system lexicon.
+    pub fn with_sub_words(mut self, sub_words: SubWords) -> Result<Self> {
+        let num_words = match &self {
+            Dictionary::Archived(archived_dict) => archived_dict.system_lexicon().num_words(),
+            Dictionary::Owned { dict, .. } => dict.system_lexicon().num_words(),
+        };
+        if sub_words.num_words() != num_words {
+            return Err(VibratoError::invalid_argument(
+                "sub_words",
+                format!(
+                    "has {} words, but the system lexicon has {num_words} words.",
+                    sub_words.num_words()
+                ),
+            ));
+        }
+        self.sections_mut().sub_words = Some(sub_words);
+        Ok(self)
+    }
+
+    /// Gets the decomposition of words into sub-words.
+    ///
+    /// Returns `None` if the dictionary was compiled without sub-words and
+    /// none has been attached with [`Self::with_sub_words()`].
+    pub fn sub_words(&self) -> Option<&SubWords> {
+        self.sections().sub_words.as_ref()
+    }
+
     /// Serializes the dictionary data to a writer using the `rkyv` format.
     ///
     /// The output binary from this function is the format that `vibrato-rkyv`'s
@@ -418,7 +552,7 @@ impl Dictionary {
         W: Write,
     {
         match self {
-            Dictionary::Owned { dict, .. } => dict.write(wtr),
+            Dictionary::Owned { dict, sections, .. } => dict.write_impl(sections, wtr),
             Dictionary::Archived(_) => unreachable!(),
         }
     }
@@ -472,9 +606,207 @@ impl Dictionary {
         // SAFETY: AlignedVec ensures correct alignment for ArchivedDictionaryInner
         let data: &'static ArchivedDictionaryInner = unsafe { &*(archived as *const _) };
 
+        let sections = Sections::read(&aligned_bytes, data.system_lexicon().num_words())?;
         Ok(Self::Archived(ArchivedDictionary {
             _buffer: DictBuffer::Aligned(aligned_bytes),
             data,
+            sections,
+        }))
+    }
+
+    /// Creates a dictionary from compiled bytes living for the whole program, such as ones
+    /// embedded with [`include_bytes!`].
+    ///
+    /// The bytes are accessed without copying if the archived data is aligned to 16 bytes,
+    /// which holds when the bytes themselves start at a 16-byte boundary. Otherwise, they
+    /// are copied into an aligned heap buffer.
+    ///
+    /// # Arguments
+    ///
+    /// - `bytes` - The bytes of a compiled dictionary.
+    /// - `mode` - A [`LoadMode`] that specifies the validation strategy:
+    ///   - `LoadMode::Validate`: Performs a full validation on every load.
+    ///   - `LoadMode::TrustCache`: Performs a full validation only on the first load of the
+    ///     same bytes in the process. Since static bytes cannot be modified, later loads
+    ///     skip it safely.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bytes are corrupted, have an invalid format, or have a
+    /// mismatched magic number, including legacy bincode-based dictionaries.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// use vibrato_rkyv::{Dictionary, LoadMode};
+    ///
+    /// // Aligns the embedded bytes so that they are accessed without copying.
+    /// #[repr(C, align(16))]
+    /// struct Aligned<T: ?Sized>(T);
+    ///
+    /// static SYSTEM_DIC: &Aligned<[u8]> = &Aligned(*include_bytes!("system.dic"));
+    ///
+    /// let dict = Dictionary::from_static_slice(&SYSTEM_DIC.0, LoadMode::TrustCache)?;
+    /// # Ok::<(), vibrato_rkyv::errors::VibratoError>(())
+    /// ```
+    pub fn from_static_slice(bytes: &'static [u8], mode: LoadMode) -> Result<Self> {
+        let key = (bytes.as_ptr() as usize, bytes.len());
+        let is_validated = mode == LoadMode::TrustCache
+            && VALIDATED_STATIC_BYTES
+                .lock()
+                .is_ok_and(|validated| validated.contains(&key));
+        // SAFETY: The bytes are borrowed for the lifetime of the program, and validated
+        // unless they have already been validated in this process.
+        let dict =
+            unsafe { Self::from_bytes_inner(bytes, DictBuffer::Static(bytes), !is_validated)? };
+        if mode == LoadMode::TrustCache
+            && !is_validated
+            && let Ok(mut validated) = VALIDATED_STATIC_BYTES.lock()
+        {
+            validated.insert(key);
+        }
+        Ok(dict)
+    }
+
+    /// Creates a dictionary from compiled bytes living for the whole program without
+    /// validation.
+    ///
+    /// This function is a version of [`Self::from_static_slice()`] that skips data
+    /// validation, intended for bytes embedded at build time from a trusted dictionary.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bytes are too small or have an incorrect magic number.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the bytes are a valid and uncorrupted representation
+    /// of a dictionary. Otherwise, this function may lead to out-of-bounds memory access,
+    /// panics, or other forms of undefined behavior.
+    pub unsafe fn from_static_slice_unchecked(bytes: &'static [u8]) -> Result<Self> {
+        // SAFETY: The bytes are borrowed for the lifetime of the program, and the caller
+        // ensures that they are valid.
+        unsafe { Self::from_bytes_inner(bytes, DictBuffer::Static(bytes), false) }
+    }
+
+    /// Creates a dictionary from compiled bytes in a buffer, such as `Vec<u8>`, `Arc<[u8]>`,
+    /// or `bytes::Bytes` received from an object store.
+    ///
+    /// The buffer is moved into the dictionary, and its bytes are accessed without copying
+    /// if the archived data is aligned to 16 bytes. Otherwise, they are copied into an
+    /// aligned heap buffer.
+    ///
+    /// Unlike [`Self::from_static_slice()`], this function takes no [`LoadMode`] and always
+    /// performs a full validation, because a buffer may be freed and its memory reused for
+    /// other bytes, so that bytes validated once cannot be identified in later loads.
+    /// Use [`Self::from_shared_bytes_unchecked()`] to skip the validation of trusted bytes.
+    ///
+    /// The buffer must return the same bytes from [`AsRef::as_ref()`] while it is alive,
+    /// which holds for the standard containers.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bytes are corrupted, have an invalid format, or have a
+    /// mismatched magic number, including legacy bincode-based dictionaries.
+    pub fn from_shared_bytes<B>(bytes: B) -> Result<Self>
+    where
+        B: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        let buffer: Arc<dyn AsRef<[u8]> + Send + Sync> = Arc::new(bytes);
+        // SAFETY: The buffer is never moved in the Arc, and owned by the dictionary.
+        let data: &'static [u8] = unsafe { &*((*buffer).as_ref() as *const [u8]) };
+        // SAFETY: The bytes are valid while the buffer is alive, and validated.
+        unsafe { Self::from_bytes_inner(data, DictBuffer::Shared(buffer), true) }
+    }
+
+    /// Creates a dictionary from compiled bytes in a buffer without validation.
+    ///
+    /// This function is a version of [`Self::from_shared_bytes()`] that skips data
+    /// validation, intended for bytes from a trusted source, e.g., ones whose checksum
+    /// has been verified.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bytes are too small or have an incorrect magic number.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the bytes are a valid and uncorrupted representation
+    /// of a dictionary. Otherwise, this function may lead to out-of-bounds memory access,
+    /// panics, or other forms of undefined behavior.
+    pub unsafe fn from_shared_bytes_unchecked<B>(bytes: B) -> Result<Self>
+    where
+        B: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        let buffer: Arc<dyn AsRef<[u8]> + Send + Sync> = Arc::new(bytes);
+        // SAFETY: The buffer is never moved in the Arc, and owned by the dictionary.
+        let data: &'static [u8] = unsafe { &*((*buffer).as_ref() as *const [u8]) };
+        // SAFETY: The bytes are valid while the buffer is alive, and the caller ensures
+        // that they are a valid dictionary.
+        unsafe { Self::from_bytes_inner(data, DictBuffer::Shared(buffer), false) }
+    }
+
+    /// Creates an archived dictionary from compiled bytes owned by `buffer`,
+    /// accessing them without copying if the archived data is aligned.
+    ///
+    /// # Safety
+    ///
+    /// The bytes must be valid while `buffer` is alive. If `validate` is `false`,
+    /// they must be a valid representation of a dictionary.
+    unsafe fn from_bytes_inner(
+        bytes: &'static [u8],
+        buffer: DictBuffer,
+        validate: bool,
+    ) -> Result<Self> {
+        let magic = bytes.get(..MODEL_MAGIC_LEN).unwrap_or(bytes);
+        if magic.starts_with(LEGACY_MODEL_MAGIC_PREFIX) {
+            return Err(VibratoError::invalid_argument(
+                "bytes",
+                "This appears to be a legacy bincode-based dictionary file. Please use a dictionary compiled for the rkyv version of vibrato.",
+            ));
+        } else if !magic.starts_with(MODEL_MAGIC) {
+            return Err(VibratoError::invalid_argument(
+                "bytes",
+                "The magic number of the input model mismatches.",
+            ));
+        }
+
+        let Some(data_bytes) = bytes.get(DATA_START..) else {
+            return Err(VibratoError::invalid_argument(
+                "bytes",
+                "Dictionary file too small or corrupted.",
+            ));
+        };
+
+        let (buffer, data_bytes): (DictBuffer, &'static [u8]) =
+            if (data_bytes.as_ptr() as usize).is_multiple_of(RKYV_ALIGNMENT) {
+                (buffer, data_bytes)
+            } else {
+                let mut aligned_bytes = AlignedVec::with_capacity(data_bytes.len());
+                aligned_bytes.extend_from_slice(data_bytes);
+                // SAFETY: The heap buffer of AlignedVec is never moved, and owned by the dictionary.
+                let data_bytes = unsafe { &*(aligned_bytes.as_slice() as *const [u8]) };
+                (DictBuffer::Aligned(aligned_bytes), data_bytes)
+            };
+
+        let data: &'static ArchivedDictionaryInner = if validate {
+            access::<ArchivedDictionaryInner, Error>(data_bytes).map_err(|e| {
+                VibratoError::invalid_state(
+                    "rkyv validation failed. The dictionary file may be corrupted or incompatible."
+                        .to_string(),
+                    e.to_string(),
+                )
+            })?
+        } else {
+            // SAFETY: The caller ensures that the bytes are valid.
+            unsafe { access_unchecked::<ArchivedDictionaryInner>(data_bytes) }
+        };
+
+        let sections = Sections::read(data_bytes, data.system_lexicon().num_words())?;
+        Ok(Self::Archived(ArchivedDictionary {
+            _buffer: buffer,
+            data,
+            sections,
         }))
     }
 
@@ -526,7 +858,8 @@ impl Dictionary {
     ///     **Warning: This mode trusts file metadata for validation to achieve high performance.
     ///     It is vulnerable to time-of-check to time-of-use (TOCTOU) attacks if the dictionary
     ///     file can be replaced by a malicious actor. Use `LoadMode::Validate` in environments
-    ///     where file integrity cannot be guaranteed.**
+    ///     where file integrity cannot be guaranteed.** To identify files by their contents
+    ///     instead of metadata, use [`from_path_with_key`](Self::from_path_with_key).
     ///
     /// # Errors
     ///
@@ -536,7 +869,30 @@ impl Dictionary {
     /// - The file was created with an incompatible version of vibrato.
     /// - (`legacy` feature disabled) A legacy bincode-based dictionary is provided.
     pub fn from_path<P: AsRef<std::path::Path>>(path: P, mode: LoadMode) -> Result<Self> {
-        let path = path.as_ref();
+        Self::from_path_inner(path.as_ref(), mode, &CacheKey::Metadata)
+    }
+
+    /// Creates a dictionary from a file path with [`LoadMode::TrustCache`], naming the proof
+    /// file of its validity after the given [`CacheKey`] instead of the metadata of the file.
+    ///
+    /// With [`CacheKey::Content`], the proof is named after the hash of the whole file, so a
+    /// file replaced in place with the same size and modification time is validated again.
+    /// The hash is indexed in the global cache directory, so the whole file is read only
+    /// when it has changed since the last load. With [`CacheKey::Explicit`], the
+    /// proof is named after the key and the path of the file, trusting the caller to change
+    /// the key whenever the file changes.
+    ///
+    /// See [`from_path`](Self::from_path) for the locations of proof files.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key cannot be computed, in addition to the errors from
+    /// [`from_path`](Self::from_path).
+    pub fn from_path_with_key<P: AsRef<std::path::Path>>(path: P, key: CacheKey) -> Result<Self> {
+        Self::from_path_inner(path.as_ref(), LoadMode::TrustCache, &key)
+    }
+
+    fn from_path_inner(path: &std::path::Path, mode: LoadMode, key: &CacheKey) -> Result<Self> {
         let mut file = File::open(path).map_err(|e| {
             VibratoError::invalid_argument("path", format!("Failed to open dictionary file: {}", e))
         })?;
@@ -572,6 +928,7 @@ impl Dictionary {
                 return Ok(Self::Owned {
                     dict,
                     _caching_handle: None,
+                    sections: Sections::default(),
                 });
             }
         } else if !magic.starts_with(MODEL_MAGIC) {
@@ -590,17 +947,26 @@ impl Dictionary {
             ));
         };
 
-        let current_hash = compute_metadata_hash(meta);
+        let current_hash = match key {
+            CacheKey::Metadata => compute_metadata_hash(meta),
+            CacheKey::Content => match GLOBAL_CACHE_DIR.as_ref() {
+                Some(index_dir) => indexed_content_hash(meta, &mmap[..], index_dir)?,
+                None => content_hash(&mmap[..])?,
+            },
+            CacheKey::Explicit(key) => explicit_key_hash(path, key)?,
+        };
         let hash_name = format!("{}.sha256", current_hash);
         let hash_path = path.parent().unwrap().join(".cache").join(&hash_name);
 
         if mode == LoadMode::TrustCache && hash_path.exists() {
             let archived = unsafe { access_unchecked::<ArchivedDictionaryInner>(data_bytes) };
             let data: &'static ArchivedDictionaryInner = unsafe { &*(archived as *const _) };
+            let sections = Sections::read(data_bytes, data.system_lexicon().num_words())?;
             return {
                 Ok(Dictionary::Archived(ArchivedDictionary {
                     _buffer: DictBuffer::Mmap(mmap),
                     data,
+                    sections,
                 }))
             };
         }
@@ -614,10 +980,12 @@ impl Dictionary {
         if mode == LoadMode::TrustCache && hash_path.exists() {
             let archived = unsafe { access_unchecked::<ArchivedDictionaryInner>(data_bytes) };
             let data: &'static ArchivedDictionaryInner = unsafe { &*(archived as *const _) };
+            let sections = Sections::read(data_bytes, data.system_lexicon().num_words())?;
             return {
                 Ok(Dictionary::Archived(ArchivedDictionary {
                     _buffer: DictBuffer::Mmap(mmap),
                     data,
+                    sections,
                 }))
             };
         }
@@ -630,9 +998,11 @@ impl Dictionary {
                 }
 
                 let data: &'static ArchivedDictionaryInner = unsafe { &*(archived as *const _) };
+                let sections = Sections::read(data_bytes, data.system_lexicon().num_words())?;
                 Ok(Self::Archived(ArchivedDictionary {
                     _buffer: DictBuffer::Mmap(mmap),
                     data,
+                    sections,
                 }))
             }
             Err(_) => {
@@ -647,9 +1017,11 @@ impl Dictionary {
                 })?;
 
                 let data: &'static ArchivedDictionaryInner = unsafe { &*(archived as *const _) };
+                let sections = Sections::read(&aligned_bytes, data.system_lexicon().num_words())?;
                 Ok(Self::Archived(ArchivedDictionary {
                     _buffer: DictBuffer::Aligned(aligned_bytes),
                     data,
+                    sections,
                 }))
             }
         }
@@ -722,6 +1094,7 @@ impl Dictionary {
                 return Ok(Self::Owned {
                     dict,
                     _caching_handle: None,
+                    sections: Sections::default(),
                 });
             }
         } else if !magic.starts_with(MODEL_MAGIC) {
@@ -742,9 +1115,11 @@ impl Dictionary {
 
         let archived = unsafe { access_unchecked::<ArchivedDictionaryInner>(data_bytes) };
         let data: &'static ArchivedDictionaryInner = unsafe { &*(archived as *const _) };
+        let sections = Sections::read(data_bytes, data.system_lexicon().num_words())?;
         Ok(Self::Archived(ArchivedDictionary {
             _buffer: DictBuffer::Mmap(mmap),
             data,
+            sections,
         }))
     }
 
@@ -764,12 +1139,56 @@ impl Dictionary {
     happens in the background, providing a responsive user experience."
     )]
     ///
+    ///
+    /// If the cache directory (determined by the `strategy`) cannot be determined or written
+    /// to, e.g., on read-only containers, the dictionary is decompressed into memory with
+    /// [`from_zstd_in_memory`](Self::from_zstd_in_memory) instead, unless a cache of the
+    /// file already exists there.
+    ///
     /// # Errors
     ///
-    /// Returns an error if the specified `cache_dir` (determined by the `strategy`)
-    /// cannot be created or written to, in addition to the errors from
-    /// [`from_zstd_with_options`].
+    /// Returns an error if the strategy is `Local` and `path` has no parent directory,
+    /// in addition to the errors from [`from_zstd_with_options`].
     pub fn from_zstd<P: AsRef<std::path::Path>>(path: P, strategy: CacheStrategy) -> Result<Self> {
+        Self::from_zstd_with_key(path, strategy, CacheKey::Metadata)
+    }
+
+    /// Loads a dictionary from a Zstandard-compressed file using a specified caching strategy,
+    /// identifying the cache with the given [`CacheKey`].
+    ///
+    /// [`from_zstd`](Self::from_zstd) identifies caches by the metadata of the file, so a file
+    /// replaced in place with the same size and modification time, e.g., by container image
+    /// layers normalizing timestamps, silently reuses the cache of the original one.
+    /// [`CacheKey::Content`] identifies caches by the hash of the compressed contents instead,
+    /// and [`CacheKey::Explicit`] by a key such as the version of the dictionary along with
+    /// the path of the file.
+    ///
+    /// The key is recorded in the sidecar of the cache, so that [`CacheManager`] can tell
+    /// stale caches.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use vibrato_rkyv::Dictionary;
+    /// use vibrato_rkyv::dictionary::{CacheKey, CacheStrategy};
+    ///
+    /// let dict = Dictionary::from_zstd_with_key(
+    ///     "path/to/system.dic.zst",
+    ///     CacheStrategy::GlobalCache,
+    ///     CacheKey::Content,
+    /// )?;
+    /// # Ok::<(), vibrato_rkyv::errors::VibratoError>(())
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key cannot be computed, in addition to the errors from
+    /// [`from_zstd`](Self::from_zstd).
+    pub fn from_zstd_with_key<P: AsRef<std::path::Path>>(
+        path: P,
+        strategy: CacheStrategy,
+        key: CacheKey,
+    ) -> Result<Self> {
         let path = path.as_ref();
 
         let cache_dir = match strategy {
@@ -780,32 +1199,111 @@ impl Dictionary {
                         "Input path must have a parent directory for the Local cache strategy.",
                     )
                 })?;
-                let local_cache = parent.join(".cache");
-                std::fs::create_dir_all(&local_cache)?;
-                local_cache
+                Some(parent.join(".cache"))
             }
-            CacheStrategy::GlobalCache => {
-                let global_cache = GLOBAL_CACHE_DIR.as_ref().ok_or_else(|| {
-                    VibratoError::invalid_state("Could not determine system cache directory.", "")
-                })?;
-                global_cache.to_path_buf()
-            }
-            CacheStrategy::GlobalData => {
-                let local_data = GLOBAL_DATA_DIR.as_ref().ok_or_else(|| {
-                    VibratoError::invalid_state("Could not determine local data directory.", "")
-                })?;
-                local_data.to_path_buf()
+            CacheStrategy::GlobalCache => GLOBAL_CACHE_DIR.clone(),
+            CacheStrategy::GlobalData => GLOBAL_DATA_DIR.clone(),
+        };
+
+        let dict_hash = key.resolve_in(path, cache_dir.as_deref())?;
+        let cache_dir = match cache_dir {
+            Some(cache_dir) if is_cache_usable(&dict_hash, &cache_dir) => cache_dir,
+            _ => {
+                log::warn!(
+                    "[vibrato-rkyv] The cache directory is not writable. Decompressing {} into memory.",
+                    path.display()
+                );
+                return Self::from_zstd_in_memory(path);
             }
         };
 
-        Self::from_zstd_with_options(
+        Self::from_zstd_in_dir(
             path,
             cache_dir,
+            &key,
+            dict_hash,
             #[cfg(feature = "legacy")]
             false,
         )
     }
 
+    /// Loads a dictionary from a Zstandard-compressed file by decompressing it into memory,
+    /// without writing any cache file.
+    ///
+    /// This is useful on read-only file systems and serverless platforms. Since nothing is
+    /// cached, the file is decompressed and fully validated on every call.
+    /// See [`Self::from_zstd_reader()`] for the details.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened, in addition to the errors from
+    /// [`Self::from_zstd_reader()`].
+    pub fn from_zstd_in_memory<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let file = File::open(path.as_ref()).map_err(|e| {
+            VibratoError::invalid_argument("path", format!("Failed to open dictionary file: {}", e))
+        })?;
+        Self::from_zstd_reader(io::BufReader::new(file))
+    }
+
+    /// Loads a dictionary from a reader of Zstandard-compressed data by decompressing it
+    /// into memory.
+    ///
+    /// The data is decompressed directly into an aligned heap buffer, which is reserved
+    /// in advance if the frame header records the decompressed size, and the archived data
+    /// is accessed in place after a full validation.
+    #[cfg_attr(
+        feature = "legacy",
+        doc = r"
+    Legacy (bincode-based) dictionaries are loaded into memory as well."
+    )]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data cannot be read, is not a valid Zstandard-compressed
+    /// archive, or is not a valid dictionary.
+    pub fn from_zstd_reader<R: Read>(mut rdr: R) -> Result<Self> {
+        // The frame header takes at most 18 bytes.
+        let mut header = Vec::with_capacity(18);
+        rdr.by_ref().take(18).read_to_end(&mut header)?;
+        let content_size = zstd::zstd_safe::get_frame_content_size(&header)
+            .ok()
+            .flatten()
+            // Guards against allocating a huge buffer for a corrupted header.
+            .filter(|&size| size <= MAX_RESERVED_CONTENT_SIZE)
+            .and_then(|size| usize::try_from(size).ok());
+
+        let mut decoder = zstd::Decoder::new(io::Cursor::new(header).chain(rdr))?;
+        let mut aligned_bytes = AlignedVec::<16>::with_capacity(content_size.unwrap_or(0));
+        io::copy(&mut decoder, &mut aligned_bytes)?;
+
+        #[cfg(feature = "legacy")]
+        if aligned_bytes.starts_with(LEGACY_MODEL_MAGIC_PREFIX) {
+            use crate::legacy;
+
+            let dict = legacy::Dictionary::read(aligned_bytes.as_slice())?.data;
+
+            let dict = unsafe {
+                use std::mem::transmute;
+
+                Arc::new(transmute::<
+                    legacy::dictionary::DictionaryInner,
+                    DictionaryInner,
+                >(dict))
+            };
+
+            return Ok(Self::Owned {
+                dict,
+                _caching_handle: None,
+                sections: Sections::default(),
+            });
+        }
+
+        // SAFETY: The heap buffer of AlignedVec is never moved, and owned by the dictionary.
+        let bytes: &'static [u8] = unsafe { &*(aligned_bytes.as_slice() as *const [u8]) };
+        // SAFETY: The bytes are valid while the buffer is alive, and validated.
+        unsafe { Self::from_bytes_inner(bytes, DictBuffer::Aligned(aligned_bytes), true) }
+    }
+
     /// Loads a dictionary from a Zstandard-compressed file with configurable caching options.
     ///
     /// This is an advanced version of [`from_zstd`] that allows for fine-grained control
@@ -824,6 +1322,13 @@ impl Dictionary {
     /// If the `.zst` file is modified, its metadata hash will change, and a new cache will be
     /// generated automatically.
     ///
+    /// The cache is populated while holding an exclusive lock on `<hash>.lock` in the cache
+    /// directory, so processes loading the same dictionary simultaneously decompress it
+    /// only once. The others wait for the lock and then map the finished cache.
+    ///
+    /// The path of the `.zst` file and the last use of the cache are recorded in
+    /// `<hash>.meta`, so that outdated caches can be removed with [`CacheManager`].
+    ///
     /// # Arguments
     ///
     /// * `path` - A path to the Zstandard-compressed dictionary file.
@@ -878,14 +1383,31 @@ impl Dictionary {
         Q: AsRef<std::path::Path>,
     {
         let zstd_path = path.as_ref();
-        let meta = fs::metadata(zstd_path)?;
+        let dict_hash = compute_metadata_hash(&fs::metadata(zstd_path)?);
 
-        let dict_hash = compute_metadata_hash(&meta);
-        let decompressed_dir = cache_dir.as_ref().to_path_buf();
+        Self::from_zstd_in_dir(
+            zstd_path,
+            cache_dir.as_ref().to_path_buf(),
+            &CacheKey::Metadata,
+            dict_hash,
+            #[cfg(feature = "legacy")]
+            wait_for_cache,
+        )
+    }
 
+    /// Loads a dictionary from a Zstandard-compressed file, caching it in the directory with
+    /// the name of the key resolved into `dict_hash`.
+    fn from_zstd_in_dir(
+        zstd_path: &std::path::Path,
+        decompressed_dir: PathBuf,
+        key: &CacheKey,
+        dict_hash: String,
+        #[cfg(feature = "legacy")] wait_for_cache: bool,
+    ) -> Result<Self> {
         let decompressed_dict_path = decompressed_dir.join(format!("{}.dic", dict_hash));
 
         if decompressed_dict_path.exists() {
+            touch_meta(&decompressed_dir, &dict_hash);
             return Self::from_path(decompressed_dict_path, LoadMode::TrustCache);
         }
 
@@ -893,6 +1415,15 @@ impl Dictionary {
             create_dir_all(&decompressed_dir)?;
         }
 
+        // Other processes may be populating the same cache. The lock is released when
+        // the file is closed, including when this function returns early with an error.
+        let lock_file = lock_cache(&decompressed_dir, &dict_hash)?;
+        if decompressed_dict_path.exists() {
+            drop(lock_file);
+            touch_meta(&decompressed_dir, &dict_hash);
+            return Self::from_path(decompressed_dict_path, LoadMode::TrustCache);
+        }
+
         let zstd_file = File::open(zstd_path)?;
         let mut temp_file = tempfile::NamedTempFile::new_in(&decompressed_dir)?;
 
@@ -929,6 +1460,8 @@ impl Dictionary {
             };
 
             let dict_for_cache = Arc::clone(&dict);
+            let source = zstd_path.to_path_buf();
+            let key = key.clone();
             let handle = thread::spawn(move || -> Result<()> {
                 let mut temp_file = tempfile::NamedTempFile::new_in(&decompressed_dir)?;
 
@@ -942,6 +1475,8 @@ impl Dictionary {
                     decompressed_dir.join(format!("{}.sha256", decompressed_dict_hash));
 
                 File::create(decompressed_dict_hash_path)?;
+                write_meta(&decompressed_dir, &dict_hash, &key, &source)?;
+                drop(lock_file);
 
                 Ok(())
             });
@@ -966,6 +1501,7 @@ impl Dictionary {
             return Ok(Self::Owned {
                 dict,
                 _caching_handle,
+                sections: Sections::default(),
             });
         }
 
@@ -983,11 +1519,9 @@ impl Dictionary {
 
         temp_file.seek(SeekFrom::Start(0))?;
 
-        let mut data_bytes = Vec::new();
-        temp_file.as_file_mut().read_to_end(&mut data_bytes)?;
-
-        let mut aligned_bytes: AlignedVec = AlignedVec::with_capacity(data_bytes.len());
-        aligned_bytes.extend_from_slice(&data_bytes);
+        let len = usize::try_from(temp_file.as_file().metadata()?.len())?;
+        let mut aligned_bytes: AlignedVec = AlignedVec::with_capacity(len);
+        io::copy(temp_file.as_file_mut(), &mut aligned_bytes)?;
 
         let Some(data_bytes) = &aligned_bytes.get(DATA_START..) else {
             return Err(VibratoError::invalid_argument(
@@ -1012,6 +1546,8 @@ impl Dictionary {
             decompressed_dir.join(format!("{}.sha256", decompressed_dict_hash));
 
         File::create(decompressed_dict_hash_path)?;
+        write_meta(&decompressed_dir, &dict_hash, key, zstd_path)?;
+        drop(lock_file);
 
         Self::from_path(decompressed_dict_path, LoadMode::TrustCache)
     }
@@ -1042,6 +1578,7 @@ impl Dictionary {
         Ok(Self::Owned {
             dict: Arc::new(rkyv_dict_inner),
             _caching_handle: None,
+            sections: Sections::default(),
         })
     }
 
@@ -1055,6 +1592,7 @@ impl Dictionary {
     ///
     /// The downloaded dictionary is compressed with Zstandard. This function transparently
     /// handles decompression and caching for fast subsequent loads via memory-mapping.
+    /// The [`FeatureSchema`] of the preset is attached to the returned dictionary.
     ///
     /// This function is only available when the `download` feature is enabled.
     ///
@@ -1092,12 +1630,18 @@ impl Dictionary {
     ) -> Result<Self> {
         let dict_path = fetch::download_dictionary(kind, cache_dir.as_ref())?;
 
-        Self::from_zstd_with_options(
+        let dict = Self::from_zstd_with_options(
             dict_path,
             cache_dir,
             #[cfg(feature = "legacy")]
             true,
-        )
+        )?;
+
+        // Preset dictionaries compiled without a schema have the known layouts.
+        match kind.feature_schema() {
+            Some(schema) if dict.feature_schema().is_none() => Ok(dict.with_feature_schema(schema)),
+            _ => Ok(dict),
+        }
     }
 
     /// Downloads a preset dictionary file and returns the path to it.
@@ -1228,6 +1772,15 @@ impl Dictionary {
     }
 }
 
+/// Checks if the decompressed cache of the Zstandard-compressed file can be used in the
+/// directory, i.e., it already exists, or the directory can be created and written to.
+fn is_cache_usable(dict_hash: &str, cache_dir: &std::path::Path) -> bool {
+    if cache_dir.join(format!("{dict_hash}.dic")).exists() {
+        return true;
+    }
+    create_dir_all(cache_dir).is_ok() && tempfile::NamedTempFile::new_in(cache_dir).is_ok()
+}
+
 #[inline(always)]
 pub(crate) fn compute_metadata_hash(meta: &Metadata) -> String {
     let mut hasher = Sha256::new();
@@ -1316,6 +1869,14 @@ impl<'a> DictionaryInnerRef<'a> {
             DictionaryInnerRef::Owned(dict) => dict.word_param(word_idx),
         }
     }
+
+    #[inline(always)]
+    pub(crate) fn word_feature(&self, word_idx: WordIdx) -> &'a str {
+        match *self {
+            DictionaryInnerRef::Archived(archived_dict) => archived_dict.word_feature(word_idx),
+            DictionaryInnerRef::Owned(dict) => dict.word_feature(word_idx),
+        }
+    }
 }
 
 impl ArchivedDictionaryInner {
@@ -1332,6 +1893,10 @@ impl ArchivedDictionaryInner {
         &self.user_lexicon
     }
     #[inline(always)]
+    pub(crate) fn mapper(&self) -> &Archived<Option<ConnIdMapper>> {
+        &self.mapper
+    }
+    #[inline(always)]
     pub(crate) fn char_prop(&self) -> &ArchivedCharProperty {
         &self.char_prop
     }
@@ -1346,16 +1911,22 @@ impl ArchivedDictionaryInner {
             LexType::System => self.system_lexicon().word_param(word_idx),
             LexType::User => self.user_lexicon().as_ref().unwrap().word_param(word_idx),
             LexType::Unknown => self.unk_handler().word_param(word_idx),
+            LexType::Custom => panic!("{CUSTOM_WORD_MSG}"),
         }
     }
 
     /// Gets the reference to the feature string.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the word is from a custom lexicon attached to a tokenizer.
     #[inline(always)]
     pub fn word_feature(&self, word_idx: WordIdx) -> &str {
         match word_idx.lex_type {
             LexType::System => self.system_lexicon().word_feature(word_idx),
             LexType::User => self.user_lexicon().as_ref().unwrap().word_feature(word_idx),
             LexType::Unknown => self.unk_handler().word_feature(word_idx),
+            LexType::Custom => panic!("{CUSTOM_WORD_MSG}"),
         }
     }
 }
@@ -0,0 +1,830 @@
+//! Management of dictionaries cached by [`Dictionary::from_zstd()`].
+//!
+//! A cache directory holds the following files for each dictionary decompressed from
+//! a Zstandard-compressed file, where `<hash>` is derived from the file as specified by
+//! [`CacheKey`]:
+//!
+//! - `<hash>.dic`: The decompressed dictionary.
+//! - `<hash>.meta`: The path of the compressed file and the key, whose modification time
+//!   records the last use of the cache.
+//! - `<hash>.lock`: The lock held while populating the cache.
+//!
+//! In addition, `<proof>.sha256` files prove the validity of dictionaries loaded with
+//! [`LoadMode::TrustCache`](crate::LoadMode::TrustCache), and `<fingerprint>.content` files
+//! index the hashes of files identified by [`CacheKey::Content`].
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use vibrato_rkyv::dictionary::{CacheManager, PruneOptions};
+//!
+//! let manager = CacheManager::global_cache().unwrap();
+//! for entry in manager.entries()? {
+//!     println!("{} {} bytes", entry.path().display(), entry.size());
+//! }
+//! let options = PruneOptions::new()
+//!     .max_age(Duration::from_secs(30 * 24 * 60 * 60))
+//!     .max_total_size(2 << 30);
+//! let report = manager.prune(&options)?;
+//! println!("Freed {} bytes", report.freed_bytes());
+//! # Ok::<(), vibrato_rkyv::errors::VibratoError>(())
+//! ```
+use std::collections::HashMap;
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use digest_io::IoWrapper;
+use fs4::fs_std::FileExt;
+use sha2::{Digest, Sha256};
+
+use crate::dictionary::{
+    Dictionary, GLOBAL_CACHE_DIR, GLOBAL_DATA_DIR, LoadMode, compute_metadata_hash,
+};
+use crate::errors::{Result, VibratoError};
+
+/// Temporary files not modified for this duration are regarded as left by aborted processes.
+const ORPHAN_TEMP_FILE_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Maximum length of keys given by [`CacheKey::Explicit`].
+const MAX_EXPLICIT_KEY_LEN: usize = 128;
+
+/// Key identifying a cached dictionary.
+///
+/// The key of a Zstandard-compressed file names its decompressed cache in
+/// [`Dictionary::from_zstd_with_key()`], and the key of a dictionary file names the proof
+/// of its validity in [`Dictionary::from_path_with_key()`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CacheKey {
+    /// Hash of the metadata of the file, such as its size and modification time.
+    ///
+    /// This is the fastest, but a file replaced in place with the same size and
+    /// modification time, e.g., by container image layers normalizing timestamps,
+    /// is mistaken for the original one.
+    #[default]
+    Metadata,
+
+    /// SHA-256 hash of the contents of the file.
+    ///
+    /// The hash is computed once for each file and indexed in the cache directory by
+    /// a fingerprint of the file including its status change time (`ctime`). Unlike the
+    /// modification time, it is maintained by the kernel, which updates it whenever the file
+    /// is written or replaced, so it cannot be restored by tools normalizing timestamps.
+    /// On platforms other than Unix, the file is read on every load to compute the hash.
+    Content,
+
+    /// Key given by the caller, such as the version of the dictionary or the digest of
+    /// the container image shipping it.
+    ///
+    /// The key must consist of at most 128 ASCII alphanumerics, `-`, `_`, and `.`, and must
+    /// not start with `.`. The caller is responsible for changing the key whenever the
+    /// file changes. Since caches and proofs are named after the key and the path of the
+    /// file, the same key for different files never shares them.
+    Explicit(String),
+}
+
+impl CacheKey {
+    /// Computes the hash of the file identified by the key, which is the name of its
+    /// decompressed cache without the extension.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when the file cannot be read, or an explicit key
+    /// contains invalid characters.
+    ///
+    /// For content keys, the whole file is read without consulting the index of hashes.
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> Result<String> {
+        self.resolve_in(path.as_ref(), None)
+    }
+
+    /// Computes the hash of the file identified by the key, looking up the hash of the
+    /// contents in the index of the directory if given.
+    pub(crate) fn resolve_in(&self, path: &Path, index_dir: Option<&Path>) -> Result<String> {
+        match self {
+            Self::Metadata => Ok(compute_metadata_hash(&fs::metadata(path)?)),
+            Self::Content => {
+                let file = File::open(path)?;
+                let meta = file.metadata()?;
+                match index_dir {
+                    Some(index_dir) => indexed_content_hash(&meta, file, index_dir),
+                    None => content_hash(file),
+                }
+            }
+            Self::Explicit(key) => explicit_key_hash(path, key),
+        }
+    }
+
+    const fn kind(&self) -> &'static str {
+        match self {
+            Self::Metadata => "metadata",
+            Self::Content => "content",
+            Self::Explicit(_) => "explicit",
+        }
+    }
+}
+
+/// Computes the SHA-256 hash of the data in hex.
+pub(crate) fn content_hash<R: Read>(mut rdr: R) -> Result<String> {
+    let mut hasher = IoWrapper(Sha256::new());
+    io::copy(&mut rdr, &mut hasher)?;
+    Ok(hex::encode(hasher.0.finalize()))
+}
+
+/// Computes the SHA-256 hash of the contents of the file with the metadata, looking it up in
+/// the index of the directory first, and recording it there if not found.
+///
+/// Failures to record the hash are ignored since the directory may be read-only.
+pub(crate) fn indexed_content_hash<R: Read>(
+    meta: &Metadata,
+    rdr: R,
+    index_dir: &Path,
+) -> Result<String> {
+    let Some(fingerprint) = fingerprint(meta) else {
+        return content_hash(rdr);
+    };
+    let index_path = index_dir.join(format!("{fingerprint}.content"));
+    if let Ok(hash) = fs::read_to_string(&index_path)
+        && is_hash(&hash)
+    {
+        return Ok(hash);
+    }
+    let hash = content_hash(rdr)?;
+    let _ = write_index(index_dir, &index_path, &hash);
+    Ok(hash)
+}
+
+/// Computes the fingerprint of the file identifying it in the index of content hashes,
+/// or `None` if the platform has no status change time.
+fn fingerprint(meta: &Metadata) -> Option<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let mut hasher = Sha256::new();
+        hasher.update(compute_metadata_hash(meta).as_bytes());
+        hasher.update(meta.ctime().to_le_bytes());
+        hasher.update(meta.ctime_nsec().to_le_bytes());
+        Some(hex::encode(hasher.finalize()))
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = meta;
+        None
+    }
+}
+
+/// Records the hash in the index atomically, so that other processes never read
+/// a partially written hash.
+fn write_index(index_dir: &Path, index_path: &Path, hash: &str) -> Result<()> {
+    fs::create_dir_all(index_dir)?;
+    let mut temp_file = tempfile::NamedTempFile::new_in(index_dir)?;
+    temp_file.write_all(hash.as_bytes())?;
+    temp_file.persist(index_path)?;
+    Ok(())
+}
+
+/// Computes the hash of the file identified by the explicit key, which is scoped by the path
+/// so that the same key for different files never shares caches or skips validation.
+pub(crate) fn explicit_key_hash(path: &Path, key: &str) -> Result<String> {
+    check_explicit_key(key)?;
+    let path = fs::canonicalize(path)?;
+    let mut hasher = Sha256::new();
+    hasher.update(key.as_bytes());
+    hasher.update([0]);
+    hasher.update(path.as_os_str().as_encoded_bytes());
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn check_explicit_key(key: &str) -> Result<()> {
+    if is_valid_key(key) {
+        Ok(())
+    } else {
+        Err(VibratoError::invalid_argument(
+            "key",
+            format!(
+                "The cache key must consist of at most {MAX_EXPLICIT_KEY_LEN} ASCII alphanumerics, '-', '_', and '.', and must not start with '.': {key:?}"
+            ),
+        ))
+    }
+}
+
+/// Checks if the string can be an explicit key, which is written in the sidecar of the cache.
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= MAX_EXPLICIT_KEY_LEN
+        && !key.starts_with('.')
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+}
+
+/// Acquires the exclusive lock on populating the cache of the hash in the directory,
+/// blocking until other processes release it.
+///
+/// The lock is held until the returned file is closed.
+pub(crate) fn lock_cache(cache_dir: &Path, hash: &str) -> Result<File> {
+    let lock_file = open_lock_file(cache_dir, hash)?;
+    lock_file.lock_exclusive()?;
+    Ok(lock_file)
+}
+
+fn open_lock_file(cache_dir: &Path, hash: &str) -> Result<File> {
+    Ok(fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(cache_dir.join(format!("{hash}.lock")))?)
+}
+
+/// Writes the sidecar of the cache recording the path of the compressed file and the key.
+pub(crate) fn write_meta(
+    cache_dir: &Path,
+    hash: &str,
+    key: &CacheKey,
+    source: &Path,
+) -> Result<()> {
+    let source = fs::canonicalize(source)?;
+    let mut file = File::create(cache_dir.join(format!("{hash}.meta")))?;
+    writeln!(file, "source\t{}", source.display())?;
+    writeln!(file, "key\t{}", key.kind())?;
+    if let CacheKey::Explicit(key) = key {
+        writeln!(file, "explicit_key\t{key}")?;
+    }
+    Ok(())
+}
+
+/// Records the use of the cache in the modification time of its sidecar.
+///
+/// Failures are ignored since the cache may be in a read-only directory.
+pub(crate) fn touch_meta(cache_dir: &Path, hash: &str) {
+    if let Ok(file) = fs::OpenOptions::new()
+        .write(true)
+        .open(cache_dir.join(format!("{hash}.meta")))
+    {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+/// Contents of the sidecar of a cache.
+#[derive(Default)]
+struct Sidecar {
+    source: Option<PathBuf>,
+    key_kind: Option<String>,
+    explicit_key: Option<String>,
+}
+
+impl Sidecar {
+    /// Reads the sidecar, ignoring unknown fields. Caches created before the kind of the
+    /// key was recorded have metadata keys.
+    fn read(meta_path: &Path) -> Self {
+        let mut sidecar = Self::default();
+        let Ok(file) = File::open(meta_path) else {
+            return sidecar;
+        };
+        for line in BufReader::new(file).lines().map_while(|line| line.ok()) {
+            let Some((name, value)) = line.split_once('\t') else {
+                continue;
+            };
+            match name {
+                "source" => sidecar.source = Some(PathBuf::from(value)),
+                "key" => sidecar.key_kind = Some(value.to_string()),
+                "explicit_key" => sidecar.explicit_key = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        sidecar
+    }
+
+    fn key(&self) -> CacheKey {
+        match (self.key_kind.as_deref(), &self.explicit_key) {
+            (Some("content"), _) => CacheKey::Content,
+            (Some("explicit"), Some(key)) => CacheKey::Explicit(key.clone()),
+            _ => CacheKey::Metadata,
+        }
+    }
+}
+
+/// Checks if the string is a SHA-256 hash in hex, such as one generated by
+/// [`CacheKey::resolve()`].
+fn is_hash(stem: &str) -> bool {
+    stem.len() == 64 && stem.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Dictionary decompressed into a cache directory.
+#[derive(Clone, Debug)]
+pub struct CacheEntry {
+    hash: String,
+    key: CacheKey,
+    path: PathBuf,
+    size: u64,
+    source: Option<PathBuf>,
+    last_used: SystemTime,
+}
+
+impl CacheEntry {
+    /// Gets the hash identifying the cache, which is the name of the decompressed dictionary
+    /// without the extension.
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// Gets the key identifying the cache.
+    pub const fn key(&self) -> &CacheKey {
+        &self.key
+    }
+
+    /// Gets the path of the decompressed dictionary.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gets the size of the decompressed dictionary in bytes.
+    pub const fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Gets the path of the compressed file, or `None` if the cache was created
+    /// without recording it.
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+
+    /// Gets the time the cache was last used, or created if it has never been used
+    /// since its source was recorded.
+    pub const fn last_used(&self) -> SystemTime {
+        self.last_used
+    }
+
+    /// Checks if the compressed file has been removed or modified since the cache was
+    /// created, in which case the cache is never used again.
+    ///
+    /// For content keys, the file is read to compute the hash unless it is indexed in the
+    /// cache directory. Caches with explicit keys are stale only if the file has been removed.
+    /// Returns `false` if the source is unknown.
+    pub fn is_stale(&self) -> bool {
+        self.is_stale_with(&mut HashMap::new())
+    }
+
+    /// Checks if the cache is stale, memoizing the content hashes of the sources.
+    fn is_stale_with(&self, content_hashes: &mut HashMap<PathBuf, Option<String>>) -> bool {
+        let Some(source) = &self.source else {
+            return false;
+        };
+        let Ok(meta) = fs::metadata(source) else {
+            return true;
+        };
+        match &self.key {
+            CacheKey::Metadata => compute_metadata_hash(&meta) != self.hash,
+            CacheKey::Content => content_hashes
+                .entry(source.clone())
+                .or_insert_with(|| {
+                    CacheKey::Content
+                        .resolve_in(source, self.path.parent())
+                        .ok()
+                })
+                .as_ref()
+                .is_none_or(|hash| *hash != self.hash),
+            CacheKey::Explicit(_) => false,
+        }
+    }
+}
+
+/// Status of a cached dictionary checked by [`CacheManager::verify()`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// The dictionary is valid and up to date.
+    Valid,
+    /// The compressed file has been removed or modified. See [`CacheEntry::is_stale()`].
+    Stale,
+    /// The dictionary is corrupted, with the reason.
+    Corrupted(String),
+}
+
+/// Options of [`CacheManager::prune()`].
+///
+/// Stale caches and orphaned files are always removed.
+#[derive(Clone, Debug, Default)]
+pub struct PruneOptions {
+    max_total_size: Option<u64>,
+    max_age: Option<Duration>,
+    verify: bool,
+}
+
+impl PruneOptions {
+    /// Creates options removing only stale caches and orphaned files.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Specifies the maximum total size of the dictionaries in bytes, removing the least
+    /// recently used ones exceeding it.
+    pub const fn max_total_size(mut self, max_total_size: u64) -> Self {
+        self.max_total_size = Some(max_total_size);
+        self
+    }
+
+    /// Specifies the maximum duration since the last use, removing the caches unused longer.
+    pub const fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Specifies whether to fully validate the dictionaries, removing corrupted ones.
+    /// The default value is `false`.
+    pub const fn verify(mut self, yes: bool) -> Self {
+        self.verify = yes;
+        self
+    }
+}
+
+/// Result of [`CacheManager::prune()`].
+#[derive(Clone, Debug, Default)]
+pub struct PruneReport {
+    removed: Vec<PathBuf>,
+    freed_bytes: u64,
+}
+
+impl PruneReport {
+    /// Gets the paths of the removed files.
+    pub fn removed(&self) -> &[PathBuf] {
+        &self.removed
+    }
+
+    /// Gets the total size of the removed files in bytes.
+    pub const fn freed_bytes(&self) -> u64 {
+        self.freed_bytes
+    }
+
+    fn remove_file(&mut self, path: PathBuf) -> Result<()> {
+        let size = fs::metadata(&path)?.len();
+        fs::remove_file(&path)?;
+        self.removed.push(path);
+        self.freed_bytes += size;
+        Ok(())
+    }
+}
+
+/// Manager of a directory caching dictionaries decompressed by
+/// [`Dictionary::from_zstd()`].
+///
+/// Caches being populated by other processes are never removed, and caches mapped by
+/// running processes remain available to them until they are closed on Unix.
+pub struct CacheManager {
+    dir: PathBuf,
+}
+
+impl CacheManager {
+    /// Creates a manager of the cache directory, such as `.cache` next to compressed files
+    /// for [`CacheStrategy::Local`](crate::dictionary::CacheStrategy::Local).
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Creates a manager of the directory used by
+    /// [`CacheStrategy::GlobalCache`](crate::dictionary::CacheStrategy::GlobalCache),
+    /// or `None` if it cannot be determined.
+    pub fn global_cache() -> Option<Self> {
+        GLOBAL_CACHE_DIR.as_ref().map(Self::new)
+    }
+
+    /// Creates a manager of the directory used by
+    /// [`CacheStrategy::GlobalData`](crate::dictionary::CacheStrategy::GlobalData),
+    /// or `None` if it cannot be determined.
+    pub fn global_data() -> Option<Self> {
+        GLOBAL_DATA_DIR.as_ref().map(Self::new)
+    }
+
+    /// Gets the cache directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Lists the cached dictionaries in ascending order of their last use.
+    ///
+    /// Returns an empty list if the directory does not exist.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`](crate::errors::VibratoError) is returned when the directory
+    /// cannot be read.
+    pub fn entries(&self) -> Result<Vec<CacheEntry>> {
+        if !self.dir.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut entries = vec![];
+        for dir_entry in fs::read_dir(&self.dir)? {
+            let path = dir_entry?.path();
+            let Some(hash) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .filter(|s| is_hash(s))
+            else {
+                continue;
+            };
+            if path.extension().is_none_or(|ext| ext != "dic") {
+                continue;
+            }
+            let Ok(meta) = fs::metadata(&path) else {
+                // Removed by another process.
+                continue;
+            };
+            let meta_path = self.dir.join(format!("{hash}.meta"));
+            let last_used = fs::metadata(&meta_path)
+                .and_then(|m| m.modified())
+                .or_else(|_| meta.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            let sidecar = Sidecar::read(&meta_path);
+            entries.push(CacheEntry {
+                hash: hash.to_string(),
+                key: sidecar.key(),
+                size: meta.len(),
+                source: sidecar.source,
+                last_used,
+                path,
+            });
+        }
+        entries.sort_by_key(|e| e.last_used);
+        Ok(entries)
+    }
+
+    /// Verifies the cached dictionary, fully validating its data if it is not stale.
+    pub fn verify(&self, entry: &CacheEntry) -> CacheStatus {
+        if entry.is_stale() {
+            return CacheStatus::Stale;
+        }
+        match Dictionary::from_path(&entry.path, LoadMode::Validate) {
+            Ok(_) => CacheStatus::Valid,
+            Err(e) => CacheStatus::Corrupted(e.to_string()),
+        }
+    }
+
+    /// Removes the cached dictionary with its proof and sidecar.
+    ///
+    /// Returns `false` without removing anything if the cache is being populated
+    /// by another process.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`](crate::errors::VibratoError) is returned when the files
+    /// cannot be removed.
+    pub fn remove(&self, entry: &CacheEntry) -> Result<bool> {
+        let mut report = PruneReport::default();
+        self.remove_entry(entry, &mut report)
+    }
+
+    fn remove_entry(&self, entry: &CacheEntry, report: &mut PruneReport) -> Result<bool> {
+        let lock_file = open_lock_file(&self.dir, &entry.hash)?;
+        if !lock_file.try_lock_exclusive()? {
+            return Ok(false);
+        }
+        if let Ok(meta) = fs::metadata(&entry.path) {
+            let proof_path = self
+                .dir
+                .join(format!("{}.sha256", compute_metadata_hash(&meta)));
+            report.remove_file(entry.path.clone())?;
+            if proof_path.exists() {
+                report.remove_file(proof_path)?;
+            }
+        }
+        let meta_path = self.dir.join(format!("{}.meta", entry.hash));
+        if meta_path.exists() {
+            report.remove_file(meta_path)?;
+        }
+        Ok(true)
+    }
+
+    /// Removes stale caches, orphaned files, and caches exceeding the limits of the options.
+    ///
+    /// Orphaned files are proofs, sidecars, and indexed content hashes of removed
+    /// dictionaries, and temporary files left by aborted processes. Since proofs and indexed
+    /// hashes of dictionaries loaded by [`Dictionary::from_path()`] from other directories
+    /// cannot be told apart, they are also removed, which only makes the next load of them
+    /// validate the data or compute the hash again.
+    /// Lock files are kept since other processes may be waiting on them.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`](crate::errors::VibratoError) is returned when the directory
+    /// cannot be read or the files cannot be removed.
+    pub fn prune(&self, options: &PruneOptions) -> Result<PruneReport> {
+        let mut report = PruneReport::default();
+        if !self.dir.is_dir() {
+            return Ok(report);
+        }
+        let now = SystemTime::now();
+
+        let mut content_hashes = HashMap::new();
+        let mut kept = vec![];
+        for entry in self.entries()? {
+            let expired = options.max_age.is_some_and(|max_age| {
+                now.duration_since(entry.last_used)
+                    .is_ok_and(|age| max_age < age)
+            });
+            let remove = expired
+                || entry.is_stale_with(&mut content_hashes)
+                || (options.verify && self.verify(&entry) != CacheStatus::Valid);
+            if !remove || !self.remove_entry(&entry, &mut report)? {
+                kept.push(entry);
+            }
+        }
+
+        if let Some(max_total_size) = options.max_total_size {
+            let mut total_size: u64 = kept.iter().map(|e| e.size).sum();
+            // The entries are sorted in ascending order of their last use.
+            let mut remaining = vec![];
+            for entry in kept {
+                if max_total_size < total_size && self.remove_entry(&entry, &mut report)? {
+                    total_size -= entry.size;
+                } else {
+                    remaining.push(entry);
+                }
+            }
+            kept = remaining;
+        }
+
+        self.remove_orphans(&kept, now, &mut report)?;
+        Ok(report)
+    }
+
+    fn remove_orphans(
+        &self,
+        kept: &[CacheEntry],
+        now: SystemTime,
+        report: &mut PruneReport,
+    ) -> Result<()> {
+        let proofs: Vec<_> = kept
+            .iter()
+            .filter_map(|e| fs::metadata(&e.path).ok())
+            .map(|meta| compute_metadata_hash(&meta))
+            .collect();
+        for dir_entry in fs::read_dir(&self.dir)? {
+            let dir_entry = dir_entry?;
+            let path = dir_entry.path();
+            let Some(name) = path.file_name().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let orphaned = if let Some(hash) = name.strip_suffix(".sha256") {
+                is_hash(hash) && !proofs.iter().any(|p| p == hash)
+            } else if let Some(hash) = name.strip_suffix(".meta") {
+                is_hash(hash) && !kept.iter().any(|e| e.hash == hash)
+            } else if let Some(fingerprint) = name.strip_suffix(".content") {
+                is_hash(fingerprint)
+                    && fs::read_to_string(&path)
+                        .is_ok_and(|hash| !kept.iter().any(|e| e.hash == hash))
+            } else if name.starts_with(".tmp") {
+                dir_entry
+                    .metadata()
+                    .and_then(|m| m.modified())
+                    .is_ok_and(|modified| {
+                        now.duration_since(modified)
+                            .is_ok_and(|age| ORPHAN_TEMP_FILE_AGE < age)
+                    })
+            } else {
+                false
+            };
+            if orphaned {
+                report.remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::dictionary::SystemDictionaryBuilder;
+
+    fn write_zstd_dictionary(path: &Path, lexicon: &str) {
+        let dict = SystemDictionaryBuilder::from_readers(
+            lexicon.as_bytes(),
+            "1 1\n0 0 0".as_bytes(),
+            "DEFAULT 0 1 0".as_bytes(),
+            "DEFAULT,0,0,100,*".as_bytes(),
+        )
+        .unwrap();
+        let mut encoder = zstd::Encoder::new(File::create(path).unwrap(), 3).unwrap();
+        dict.write(&mut encoder).unwrap();
+        encoder.finish().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_indexed_content_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let index_dir = dir.path().join("cache");
+        let path = dir.path().join("a.dic.zst");
+        fs::write(&path, "a").unwrap();
+        let hash = CacheKey::Content.resolve(&path).unwrap();
+        let resolve = || {
+            CacheKey::Content
+                .resolve_in(&path, Some(&index_dir))
+                .unwrap()
+        };
+        assert_eq!(resolve(), hash);
+
+        // The indexed hash is used without reading the file.
+        let index_path = fs::read_dir(&index_dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        assert_eq!(index_path.extension().unwrap(), "content");
+        let fake_hash = "0".repeat(64);
+        fs::write(&index_path, &fake_hash).unwrap();
+        assert_eq!(resolve(), fake_hash);
+
+        // Writing the file changes its fingerprint even with the same modification time.
+        let mtime = fs::metadata(&path).unwrap().modified().unwrap();
+        fs::write(&path, "b").unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        assert_eq!(resolve(), CacheKey::Content.resolve(&path).unwrap());
+        assert_ne!(resolve(), hash);
+
+        // The indices of hashes without caches are orphaned.
+        let report = CacheManager::new(&index_dir)
+            .prune(&PruneOptions::new())
+            .unwrap();
+        assert_eq!(report.removed().len(), 2);
+    }
+
+    #[test]
+    fn test_entries_and_prune() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().join("cache");
+        let manager = CacheManager::new(&cache_dir);
+        assert!(manager.entries().unwrap().is_empty());
+
+        let path_a = dir.path().join("a.dic.zst");
+        let path_b = dir.path().join("b.dic.zst");
+        write_zstd_dictionary(&path_a, "京都,0,0,1,a");
+        write_zstd_dictionary(&path_b, "東京,0,0,1,b");
+        for path in [&path_a, &path_b] {
+            Dictionary::from_zstd_with_options(
+                path,
+                &cache_dir,
+                #[cfg(feature = "legacy")]
+                true,
+            )
+            .unwrap();
+        }
+        // Orphaned files
+        File::create(cache_dir.join(format!("{}.sha256", "0".repeat(64)))).unwrap();
+        let temp_file = cache_dir.join(".tmp0000");
+        File::create(&temp_file)
+            .unwrap()
+            .set_modified(SystemTime::now() - 2 * ORPHAN_TEMP_FILE_AGE)
+            .unwrap();
+
+        let entries = manager.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        let sources: Vec<_> = entries.iter().map(|e| e.source().unwrap()).collect();
+        assert!(sources.contains(&fs::canonicalize(&path_a).unwrap().as_path()));
+        assert!(sources.contains(&fs::canonicalize(&path_b).unwrap().as_path()));
+        for entry in &entries {
+            assert!(!entry.is_stale());
+            assert_eq!(manager.verify(entry), CacheStatus::Valid);
+        }
+
+        // Modifying the source makes its cache stale.
+        write_zstd_dictionary(&path_a, "京都,0,0,1,modified");
+        let report = manager.prune(&PruneOptions::new()).unwrap();
+        assert!(report.removed().contains(&temp_file));
+        let entries = manager.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].source(),
+            Some(fs::canonicalize(&path_b).unwrap().as_path())
+        );
+        let files = |ext: &str| {
+            fs::read_dir(&cache_dir)
+                .unwrap()
+                .filter(|e| e.as_ref().unwrap().path().extension().unwrap() == ext)
+                .count()
+        };
+        assert_eq!(files("sha256"), 1);
+        assert_eq!(files("meta"), 1);
+
+        let report = manager
+            .prune(&PruneOptions::new().max_total_size(0))
+            .unwrap();
+        assert_eq!(report.removed().len(), 3);
+        assert!(0 < report.freed_bytes());
+        assert!(manager.entries().unwrap().is_empty());
+        assert_eq!(files("sha256"), 0);
+        assert_eq!(files("meta"), 0);
+    }
+}
@@ -2,6 +2,8 @@
 
 use std::fmt;
 
+use crate::dictionary::FeatureSchema;
+
 /// Represents a preset dictionary that can be used without manual configuration.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PresetDictionaryKind {
@@ -76,6 +78,29 @@ impl PresetDictionaryKind {
     pub fn name(&self) -> &'static str {
         self.meta().name
     }
+
+    /// Gets the schema of the feature strings in the dictionary.
+    ///
+    /// Returns `None` for the dictionaries whose features are extracted from the original ones.
+    pub fn feature_schema(&self) -> Option<FeatureSchema> {
+        use PresetDictionaryKind::*;
+
+        match self {
+            Ipadic => Some(FeatureSchema::ipadic()),
+            UnidicCwj | UnidicCsj => Some(FeatureSchema::unidic()),
+
+            #[cfg(feature = "legacy")]
+            UnidicCwjCompact | UnidicCwjCompactDual => Some(FeatureSchema::unidic()),
+
+            #[cfg(feature = "legacy")]
+            BccwjUnidic | BccwjUnidicCompact | BccwjUnidicCompactDual => {
+                Some(FeatureSchema::unidic())
+            }
+
+            #[cfg(feature = "legacy")]
+            BccwjUnidicExtractedCompact | BccwjUnidicExtractedCompactDual => None,
+        }
+    }
 }
 
 use FileType::*;
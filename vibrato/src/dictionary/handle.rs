@@ -0,0 +1,123 @@
+//! Swappable handle of a dictionary.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::dictionary::Dictionary;
+
+/// Shared handle of a dictionary that can be replaced while in use.
+///
+/// A [`Tokenizer`](crate::Tokenizer) created with
+/// [`Tokenizer::from_handle()`](crate::Tokenizer::from_handle) and its workers
+/// switch to the latest dictionary at the beginning of each tokenization,
+/// while the tokenization in progress continues to use the previous one.
+/// Clones of the handle share the same dictionary.
+///
+/// # Examples
+///
+/// ```
+/// use vibrato_rkyv::dictionary::DictionaryHandle;
+/// use vibrato_rkyv::{Dictionary, SystemDictionaryBuilder, Tokenizer};
+///
+/// let build = |lex: &str| {
+///     let dict = SystemDictionaryBuilder::from_readers(
+///         lex.as_bytes(),
+///         "1 1\n0 0 0".as_bytes(),
+///         "DEFAULT 0 1 0".as_bytes(),
+///         "DEFAULT,0,0,100,*".as_bytes(),
+///     )?;
+///     Ok::<_, vibrato_rkyv::errors::VibratoError>(Dictionary::from_inner(dict))
+/// };
+///
+/// let handle = DictionaryHandle::new(build("京都,0,0,1,old")?);
+/// let tokenizer = Tokenizer::from_handle(handle.clone());
+/// let mut worker = tokenizer.new_worker();
+///
+/// worker.reset_sentence("京都");
+/// worker.tokenize();
+/// assert_eq!(worker.token(0).feature(), "old");
+///
+/// let old = handle.swap(build("京都,0,0,1,new")?);
+/// worker.tokenize();
+/// assert_eq!(worker.token(0).feature(), "new");
+///
+/// // The previous dictionary is released when the last reference is dropped.
+/// drop(old);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone)]
+pub struct DictionaryHandle {
+    inner: Arc<HandleInner>,
+}
+
+struct HandleInner {
+    // Pair of the current dictionary and its generation.
+    current: RwLock<(Arc<Dictionary>, u64)>,
+    // Copy of the generation to check updates without locking.
+    generation: AtomicU64,
+}
+
+impl DictionaryHandle {
+    /// Creates a new handle holding the dictionary.
+    pub fn new(dict: Dictionary) -> Self {
+        Self::from_shared_dictionary(Arc::new(dict))
+    }
+
+    /// Creates a new handle holding the shared dictionary.
+    pub fn from_shared_dictionary(dict: Arc<Dictionary>) -> Self {
+        Self {
+            inner: Arc::new(HandleInner {
+                current: RwLock::new((dict, 0)),
+                generation: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Gets the current dictionary.
+    pub fn load(&self) -> Arc<Dictionary> {
+        self.load_with_generation().0
+    }
+
+    /// Replaces the dictionary, returning the previous one.
+    ///
+    /// Tokenizers and workers sharing this handle use the new dictionary from
+    /// their next tokenization.
+    ///
+    /// The previous dictionary is released when the returned value and all the
+    /// workers still using it are dropped or switched. If the dictionary was loaded by
+    /// [`Dictionary::from_zstd()`] with a background caching thread, the one dropping
+    /// the last reference waits for the thread to finish. Holding the returned value
+    /// and dropping it at a convenient point avoids blocking a worker.
+    pub fn swap(&self, dict: Dictionary) -> Arc<Dictionary> {
+        self.swap_shared_dictionary(Arc::new(dict))
+    }
+
+    /// Replaces the dictionary with the shared one, returning the previous one.
+    ///
+    /// See [`Self::swap()`] for the details.
+    pub fn swap_shared_dictionary(&self, dict: Arc<Dictionary>) -> Arc<Dictionary> {
+        let mut current = self
+            .inner
+            .current
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        let generation = current.1 + 1;
+        let old = std::mem::replace(&mut current.0, dict);
+        current.1 = generation;
+        self.inner.generation.store(generation, Ordering::Release);
+        // The lock is released before the previous dictionary can be dropped.
+        drop(current);
+        old
+    }
+
+    /// Gets the generation of the current dictionary, which is incremented in every swap.
+    #[inline(always)]
+    pub(crate) fn generation(&self) -> u64 {
+        self.inner.generation.load(Ordering::Acquire)
+    }
+
+    /// Gets the current dictionary and its generation.
+    pub(crate) fn load_with_generation(&self) -> (Arc<Dictionary>, u64) {
+        let current = self.inner.current.read().unwrap_or_else(|e| e.into_inner());
+        (Arc::clone(&current.0), current.1)
+    }
+}
@@ -8,7 +8,7 @@ use csv_core::ReadFieldResult;
 use rkyv::{Archive, Deserialize, Serialize};
 
 use crate::dictionary::LexType;
-use crate::dictionary::connector::Connector;
+use crate::dictionary::connector::ConnectorView;
 use crate::dictionary::lexicon::feature::WordFeatures;
 use crate::dictionary::lexicon::map::WordMap;
 use crate::dictionary::lexicon::param::WordParams;
@@ -64,10 +64,16 @@ impl Lexicon {
         self.features.get(usize::from_u32(word_idx.word_id))
     }
 
+    /// Gets the number of words.
+    #[inline(always)]
+    pub fn num_words(&self) -> usize {
+        self.params.len()
+    }
+
     /// Checks if left/right-ids are valid with connector.
     pub fn verify<C>(&self, conn: &C) -> bool
     where
-        C: Connector,
+        C: ConnectorView,
     {
         for i in 0..self.params.len() {
             let p = self.params.get(i);
@@ -253,6 +259,12 @@ impl ArchivedLexicon {
         debug_assert_eq!(word_idx.lex_type, self.lex_type);
         self.features.get(usize::from_u32(word_idx.word_id))
     }
+
+    /// Gets the number of words.
+    #[inline(always)]
+    pub fn num_words(&self) -> usize {
+        self.params.len()
+    }
 }
 
 #[cfg(test)]
@@ -2,14 +2,19 @@ use rkyv::{Archive, Deserialize, Serialize};
 
 use crate::dictionary::mapper::ConnIdMapper;
 
+/// Parameters of a word for the cost computation.
 #[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Archive, Serialize, Deserialize)]
 pub struct WordParam {
+    /// Left connection id.
     pub left_id: u16,
+    /// Right connection id.
     pub right_id: u16,
+    /// Cost of the word.
     pub word_cost: i16,
 }
 
 impl WordParam {
+    /// Creates a new instance.
     #[inline(always)]
     pub const fn new(left_id: u16, right_id: u16, word_cost: i16) -> Self {
         Self {
@@ -68,4 +73,9 @@ impl ArchivedWordParams {
     pub fn get(&self, word_id: usize) -> WordParam {
         self.params[word_id].to_native()
     }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
 }
@@ -1,5 +1,6 @@
 use rkyv::{Archive, Deserialize, Serialize};
 
+use crate::dictionary::connector::ConnectorView;
 use crate::errors::{Result, VibratoError};
 
 use crate::common::BOS_EOS_CONNECTION_ID;
@@ -80,6 +81,48 @@ impl ConnIdMapper {
     }
 }
 
+impl ConnectorView for ConnIdMapper {
+    /// Returns the number of left connection ids that can be mapped.
+    fn num_left(&self) -> usize {
+        self.left.len()
+    }
+
+    /// Returns the number of right connection ids that can be mapped.
+    fn num_right(&self) -> usize {
+        self.right.len()
+    }
+}
+
+impl ArchivedConnIdMapper {
+    #[inline(always)]
+    pub fn num_left(&self) -> usize {
+        self.left.len()
+    }
+
+    #[inline(always)]
+    pub fn num_right(&self) -> usize {
+        self.right.len()
+    }
+
+    #[inline(always)]
+    pub fn left(&self, id: u16) -> u16 {
+        self.left[usize::from(id)].to_native()
+    }
+
+    #[inline(always)]
+    pub fn right(&self, id: u16) -> u16 {
+        self.right[usize::from(id)].to_native()
+    }
+
+    /// Copies the mappings into a native instance.
+    pub fn to_native(&self) -> ConnIdMapper {
+        ConnIdMapper::new(
+            self.left.iter().map(|id| id.to_native()).collect(),
+            self.right.iter().map(|id| id.to_native()).collect(),
+        )
+    }
+}
+
 /// Trained occurrence probabilities of connection ids.
 pub type ConnIdProbs = Vec<(usize, f64)>;
 
@@ -0,0 +1,263 @@
+//! Schema of feature strings.
+use std::io::Write;
+
+use crate::errors::{Result, VibratoError};
+use crate::utils;
+
+/// Magic bytes identifying the feature schema section in a compiled dictionary.
+pub(crate) const SECTION_MAGIC: &[u8] = b"VibratoFeatureSchema\n";
+
+const IPADIC_COLUMNS: &[&str] = &[
+    "pos1",
+    "pos2",
+    "pos3",
+    "pos4",
+    "conjugation_type",
+    "conjugation_form",
+    "base_form",
+    "reading",
+    "pronunciation",
+];
+
+const UNIDIC_COLUMNS: &[&str] = &[
+    "pos1", "pos2", "pos3", "pos4", "cType", "cForm", "lForm", "lemma", "orth", "pron", "orthBase",
+    "pronBase", "goshu", "iType", "iForm", "fType", "fForm", "iConType", "fConType", "type",
+    "kana", "kanaBase", "form", "formBase", "aType", "aConType", "aModType", "lid", "lemma_id",
+];
+
+/// Names of the columns in feature strings.
+///
+/// A schema gives access to the columns of a feature string by name, such as
+/// [`Token::feature_field()`](crate::token::Token::feature_field).
+/// The values are parsed on demand, taking quoted commas into account.
+///
+/// # Examples
+///
+/// ```
+/// use vibrato_rkyv::dictionary::FeatureSchema;
+///
+/// let schema = FeatureSchema::ipadic();
+/// let feature = "名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー";
+/// assert_eq!(schema.field(feature, "pos2").as_deref(), Some("固有名詞"));
+/// assert_eq!(schema.lemma(feature).as_deref(), Some("東京"));
+/// assert_eq!(schema.reading(feature).as_deref(), Some("トウキョウ"));
+///
+/// let schema = FeatureSchema::new(["pos", "yomi"]).reading_column("yomi")?;
+/// assert_eq!(schema.reading("名詞,\"とう,きょう\"").as_deref(), Some("とう,きょう"));
+/// # Ok::<(), vibrato_rkyv::errors::VibratoError>(())
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeatureSchema {
+    columns: Vec<String>,
+    lemma: Option<usize>,
+    reading: Option<usize>,
+}
+
+impl FeatureSchema {
+    /// Creates a new schema with the column names.
+    ///
+    /// The columns named `lemma` and `reading`, if any, are used for
+    /// [`Self::lemma()`] and [`Self::reading()`].
+    pub fn new<I, S>(columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let columns: Vec<String> = columns.into_iter().map(Into::into).collect();
+        let lemma = columns.iter().position(|c| c == "lemma");
+        let reading = columns.iter().position(|c| c == "reading");
+        Self {
+            columns,
+            lemma,
+            reading,
+        }
+    }
+
+    /// Creates the schema of MeCab IPADIC.
+    ///
+    /// The lemma is in `base_form`, and the reading is in `reading`.
+    pub fn ipadic() -> Self {
+        Self {
+            lemma: Some(6),
+            ..Self::new(IPADIC_COLUMNS.iter().copied())
+        }
+    }
+
+    /// Creates the schema of UniDic v3.
+    ///
+    /// The lemma is in `lemma`, and the reading of the surface is in `kana`.
+    pub fn unidic() -> Self {
+        Self {
+            reading: Some(20),
+            ..Self::new(UNIDIC_COLUMNS.iter().copied())
+        }
+    }
+
+    /// Specifies the column of lemmas.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when the column is not in the schema.
+    pub fn lemma_column(mut self, name: &str) -> Result<Self> {
+        self.lemma = Some(self.expect_column(name)?);
+        Ok(self)
+    }
+
+    /// Specifies the column of readings.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when the column is not in the schema.
+    pub fn reading_column(mut self, name: &str) -> Result<Self> {
+        self.reading = Some(self.expect_column(name)?);
+        Ok(self)
+    }
+
+    /// Gets the column names.
+    #[inline(always)]
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Gets the index of the column.
+    #[inline(always)]
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c == name)
+    }
+
+    /// Gets the index of the lemma column.
+    #[inline(always)]
+    pub const fn lemma_index(&self) -> Option<usize> {
+        self.lemma
+    }
+
+    /// Gets the index of the reading column.
+    #[inline(always)]
+    pub const fn reading_index(&self) -> Option<usize> {
+        self.reading
+    }
+
+    /// Gets the value of the column in the feature string.
+    ///
+    /// Returns `None` if the column is not in the schema or the feature string.
+    pub fn field(&self, feature: &str, name: &str) -> Option<String> {
+        utils::parse_csv_field(feature, self.column_index(name)?)
+    }
+
+    /// Gets the lemma in the feature string.
+    ///
+    /// Returns `None` if the schema has no lemma column or the feature string is too short.
+    pub fn lemma(&self, feature: &str) -> Option<String> {
+        utils::parse_csv_field(feature, self.lemma?)
+    }
+
+    /// Gets the reading in the feature string.
+    ///
+    /// Returns `None` if the schema has no reading column or the feature string is too short.
+    pub fn reading(&self, feature: &str) -> Option<String> {
+        utils::parse_csv_field(feature, self.reading?)
+    }
+
+    fn expect_column(&self, name: &str) -> Result<usize> {
+        self.column_index(name).ok_or_else(|| {
+            VibratoError::invalid_argument("name", format!("{name} is not in the schema."))
+        })
+    }
+
+    /// Encodes the schema into the payload of its section.
+    pub(crate) fn to_payload(&self) -> Result<Vec<u8>> {
+        let mut payload = vec![];
+        for (i, column) in self.columns.iter().enumerate() {
+            if i != 0 {
+                payload.push(b',');
+            }
+            utils::quote_csv_cell(&mut payload, column.as_bytes())?;
+        }
+        let index_to_string = |i: Option<usize>| i.map_or_else(String::new, |i| i.to_string());
+        write!(
+            &mut payload,
+            "\n{},{}",
+            index_to_string(self.lemma),
+            index_to_string(self.reading),
+        )?;
+        Ok(payload)
+    }
+
+    /// Decodes the schema from the payload of its section.
+    pub(crate) fn from_payload(payload: &[u8]) -> Result<Self> {
+        let invalid = || VibratoError::invalid_format("data", "The feature schema is corrupted.");
+        let payload = std::str::from_utf8(payload).map_err(|_| invalid())?;
+        let (columns, indices) = payload.rsplit_once('\n').ok_or_else(invalid)?;
+        let (lemma, reading) = indices.split_once(',').ok_or_else(invalid)?;
+
+        let columns = if columns.is_empty() {
+            vec![]
+        } else {
+            utils::parse_csv_row(columns)
+        };
+        let parse_index = |s: &str| -> Result<Option<usize>> {
+            if s.is_empty() {
+                return Ok(None);
+            }
+            let i = s.parse().map_err(|_| invalid())?;
+            if i < columns.len() {
+                Ok(Some(i))
+            } else {
+                Err(invalid())
+            }
+        };
+        Ok(Self {
+            lemma: parse_index(lemma)?,
+            reading: parse_index(reading)?,
+            columns,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unidic() {
+        let schema = FeatureSchema::unidic();
+        let feature = "動詞,一般,*,*,五段-マ行,連用形-撥音便,ヨム,読む,読ん,ヨン,読む,ヨム,和,*,*,*,*,*,*,用,ヨン,ヨム,ヨム,ヨム,1,C1,*,10803,39310";
+        assert_eq!(schema.field(feature, "pos1").as_deref(), Some("動詞"));
+        assert_eq!(
+            schema.field(feature, "cForm").as_deref(),
+            Some("連用形-撥音便")
+        );
+        assert_eq!(schema.lemma(feature).as_deref(), Some("読む"));
+        assert_eq!(schema.reading(feature).as_deref(), Some("ヨン"));
+        assert_eq!(schema.field(feature, "unknown"), None);
+    }
+
+    #[test]
+    fn test_short_feature() {
+        let schema = FeatureSchema::ipadic();
+        let feature = "名詞,一般,*,*,*,*,*";
+        assert_eq!(schema.lemma(feature).as_deref(), Some("*"));
+        assert_eq!(schema.reading(feature), None);
+    }
+
+    #[test]
+    fn test_column_not_found() {
+        assert!(FeatureSchema::new(["pos"]).lemma_column("lemma").is_err());
+    }
+
+    #[test]
+    fn test_payload() {
+        let schema = FeatureSchema::new(["pos", "a,\"b\"", "lemma"])
+            .reading_column("a,\"b\"")
+            .unwrap();
+        let payload = schema.to_payload().unwrap();
+        assert_eq!(FeatureSchema::from_payload(&payload).unwrap(), schema);
+
+        let schema = FeatureSchema::new(Vec::<String>::new());
+        let payload = schema.to_payload().unwrap();
+        assert_eq!(FeatureSchema::from_payload(&payload).unwrap(), schema);
+
+        assert!(FeatureSchema::from_payload(b"pos").is_err());
+        assert!(FeatureSchema::from_payload(b"pos\n1,").is_err());
+    }
+}
@@ -0,0 +1,148 @@
+//! Optional sections of a compiled dictionary.
+//!
+//! Sections are placed between the header and the archived data. Since the archived
+//! data is accessed from its end, dictionaries without sections and readers not knowing
+//! them are unaffected. Each section consists of its magic bytes, the length of its payload
+//! in a little-endian `u32`, the payload, and zero padding to the alignment of archived data.
+use std::io::Write;
+
+use crate::dictionary::schema::{self, FeatureSchema};
+use crate::dictionary::sub_words::{self, SubWords};
+use crate::errors::{Result, VibratoError};
+
+const SECTION_ALIGNMENT: usize = 16;
+
+/// Magic bytes of the sections known by this version, which can be skipped.
+const KNOWN_MAGICS: &[&[u8]] = &[schema::SECTION_MAGIC, sub_words::SECTION_MAGIC];
+
+/// Optional data stored along with a compiled dictionary,
+/// such as [`FeatureSchema`] and [`SubWords`].
+#[derive(Default)]
+pub struct Sections {
+    pub(crate) feature_schema: Option<FeatureSchema>,
+    pub(crate) sub_words: Option<SubWords>,
+}
+
+impl Sections {
+    /// Reads the sections at the beginning of the archived data, whose system lexicon
+    /// has `num_words` words.
+    pub(crate) fn read(data: &[u8], num_words: usize) -> Result<Self> {
+        let feature_schema = find_section(data, schema::SECTION_MAGIC)?
+            .map(FeatureSchema::from_payload)
+            .transpose()?;
+        let sub_words = find_section(data, sub_words::SECTION_MAGIC)?
+            .map(SubWords::from_payload)
+            .transpose()?;
+        if let Some(sub_words) = sub_words.as_ref()
+            && sub_words.num_words() != num_words
+        {
+            return Err(VibratoError::invalid_format(
+                "data",
+                format!(
+                    "The sub-words have {} words, but the system lexicon has {num_words} words.",
+                    sub_words.num_words()
+                ),
+            ));
+        }
+        Ok(Self {
+            feature_schema,
+            sub_words,
+        })
+    }
+
+    /// Writes the sections, which must be followed by the archived data.
+    pub(crate) fn write<W>(&self, mut wtr: W) -> Result<()>
+    where
+        W: Write,
+    {
+        if let Some(schema) = self.feature_schema.as_ref() {
+            write_section(&mut wtr, schema::SECTION_MAGIC, &schema.to_payload()?)?;
+        }
+        if let Some(sub_words) = self.sub_words.as_ref() {
+            write_section(&mut wtr, sub_words::SECTION_MAGIC, &sub_words.to_payload())?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a section with the payload.
+pub(crate) fn write_section<W>(mut wtr: W, magic: &[u8], payload: &[u8]) -> Result<()>
+where
+    W: Write,
+{
+    let payload_len = u32::try_from(payload.len())
+        .map_err(|_| VibratoError::invalid_argument("payload", "too large section."))?;
+    let len = magic.len() + 4 + payload.len();
+    let padding_len = len.next_multiple_of(SECTION_ALIGNMENT) - len;
+    wtr.write_all(magic)?;
+    wtr.write_all(&payload_len.to_le_bytes())?;
+    wtr.write_all(payload)?;
+    wtr.write_all(&vec![0; padding_len])?;
+    Ok(())
+}
+
+/// Finds the payload of the section, skipping the other known sections.
+///
+/// Returns `None` if the data has no such section.
+pub(crate) fn find_section<'a>(mut data: &'a [u8], magic: &[u8]) -> Result<Option<&'a [u8]>> {
+    let invalid = || VibratoError::invalid_format("data", "A dictionary section is corrupted.");
+    while let Some(&known) = KNOWN_MAGICS.iter().find(|&&m| data.starts_with(m)) {
+        let (len_bytes, rest) = data[known.len()..]
+            .split_first_chunk::<4>()
+            .ok_or_else(invalid)?;
+        let payload_len = usize::try_from(u32::from_le_bytes(*len_bytes)).map_err(|_| invalid())?;
+        let payload = rest.get(..payload_len).ok_or_else(invalid)?;
+        if known == magic {
+            return Ok(Some(payload));
+        }
+        let len = known.len() + 4 + payload_len;
+        data = data
+            .get(len.next_multiple_of(SECTION_ALIGNMENT)..)
+            .ok_or_else(invalid)?;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_section() {
+        let mut data = vec![];
+        write_section(&mut data, schema::SECTION_MAGIC, b"abc").unwrap();
+        assert_eq!(data.len() % SECTION_ALIGNMENT, 0);
+        write_section(&mut data, sub_words::SECTION_MAGIC, b"").unwrap();
+        data.extend_from_slice(b"archived data");
+
+        assert_eq!(
+            find_section(&data, schema::SECTION_MAGIC).unwrap(),
+            Some(&b"abc"[..])
+        );
+        assert_eq!(
+            find_section(&data, sub_words::SECTION_MAGIC).unwrap(),
+            Some(&b""[..])
+        );
+        assert_eq!(
+            find_section(b"archived data", schema::SECTION_MAGIC).unwrap(),
+            None
+        );
+        assert!(find_section(&data[..24], sub_words::SECTION_MAGIC).is_err());
+    }
+
+    #[test]
+    fn test_read_mismatched_sub_words() {
+        let lexicon_csv = "東京,0,0,1,*\n都,0,0,1,*\n東京都,0,0,1,0/1";
+        let sections = Sections {
+            sub_words: Some(SubWords::from_reader(lexicon_csv.as_bytes(), 4).unwrap()),
+            ..Sections::default()
+        };
+        let mut data = vec![];
+        sections.write(&mut data).unwrap();
+        data.extend_from_slice(b"archived data");
+
+        assert!(Sections::read(&data, 3).unwrap().sub_words.is_some());
+        assert!(Sections::read(&data, 2).is_err());
+        assert!(Sections::read(&data, 4).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! Decomposition of words into sub-words.
+use std::io::Read;
+
+use crate::dictionary::lexicon::Lexicon;
+use crate::errors::{Result, VibratoError};
+use crate::utils::{self, FromU32};
+
+/// Magic bytes identifying the sub-word section in a compiled dictionary.
+pub(crate) const SECTION_MAGIC: &[u8] = b"VibratoSubWords\n";
+
+/// Decomposition of words in the system lexicon into sub-words.
+///
+/// This allows a dictionary to output tokens at multiple granularities, e.g.,
+/// long units of UniDic for display and short units for search indexing.
+/// See [`Worker::set_granularity()`](crate::tokenizer::worker::Worker::set_granularity).
+///
+/// # Examples
+///
+/// ```
+/// use vibrato_rkyv::dictionary::SubWords;
+/// use vibrato_rkyv::tokenizer::worker::Granularity;
+/// use vibrato_rkyv::{Dictionary, SystemDictionaryBuilder, Tokenizer};
+///
+/// // The last column lists the word ids of the sub-words.
+/// let lexicon_csv = "国際,0,0,1,名詞,*
+/// 空港,0,0,1,名詞,*
+/// 国際空港,0,0,1,名詞,0/1";
+/// let dict = SystemDictionaryBuilder::from_readers(
+///     lexicon_csv.as_bytes(),
+///     "1 1\n0 0 0".as_bytes(),
+///     "DEFAULT 0 1 0".as_bytes(),
+///     "DEFAULT,0,0,100,*".as_bytes(),
+/// )?;
+/// let sub_words = SubWords::from_reader(lexicon_csv.as_bytes(), 1)?;
+/// let dict = Dictionary::from_inner(dict).with_sub_words(sub_words)?;
+///
+/// let tokenizer = Tokenizer::new(dict);
+/// let mut worker = tokenizer.new_worker();
+/// worker.reset_sentence("国際空港");
+/// worker.tokenize();
+/// assert_eq!(worker.token(0).surface(), "国際空港");
+///
+/// worker.set_granularity(Granularity::Fine);
+/// worker.reset_sentence("国際空港");
+/// worker.tokenize();
+/// assert_eq!(worker.num_tokens(), 2);
+/// assert_eq!(worker.token(0).surface(), "国際");
+/// assert_eq!(worker.token(1).surface(), "空港");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SubWords {
+    // The sub-words of word i are entries[offsets[i]..offsets[i + 1]].
+    offsets: Vec<u32>,
+    // Pairs of the word id and the length in characters of each sub-word
+    entries: Vec<(u32, u32)>,
+}
+
+impl SubWords {
+    /// Reads the decomposition from a column of the features in a system lexicon file.
+    ///
+    /// The column lists the word ids of the sub-words separated by `/`, such as `12/34`,
+    /// where the word id of an entry is its index in the lexicon (skipping rows with empty
+    /// surfaces). The column of a word without sub-words is empty or `*`.
+    ///
+    /// # Arguments
+    ///
+    ///  - `system_lexicon_rdr`: A reader of the lexicon file `*.csv` used to build the dictionary.
+    ///  - `column`: The index of the column in the feature string.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when
+    ///  - the lexicon file is in an invalid format,
+    ///  - a word id is out of range, or
+    ///  - the surfaces of the sub-words are not identical to the surface of the word.
+    pub fn from_reader<R>(mut system_lexicon_rdr: R, column: usize) -> Result<Self>
+    where
+        R: Read,
+    {
+        let mut buf = vec![];
+        system_lexicon_rdr.read_to_end(&mut buf)?;
+        let entries = Lexicon::parse_csv(&buf, "lex.csv")?;
+
+        let mut sub_words = Self {
+            offsets: vec![0],
+            entries: vec![],
+        };
+        for entry in &entries {
+            let field = utils::parse_csv_field(entry.feature, column).unwrap_or_default();
+            if !field.is_empty() && field != "*" {
+                let mut surface = String::new();
+                for id in field.split('/') {
+                    let sub_entry = id
+                        .parse::<u32>()
+                        .ok()
+                        .and_then(|i| Some((i, entries.get(usize::from_u32(i))?)));
+                    let Some((word_id, sub_entry)) = sub_entry else {
+                        let msg = format!("Invalid word id {id:?} of {:?}", entry.surface);
+                        return Err(VibratoError::invalid_format("lex.csv", msg));
+                    };
+                    surface.push_str(&sub_entry.surface);
+                    let len_char = u32::try_from(sub_entry.surface.chars().count())?;
+                    sub_words.entries.push((word_id, len_char));
+                }
+                if surface != entry.surface {
+                    let msg = format!(
+                        "The sub-words of {:?} are concatenated into {surface:?}",
+                        entry.surface,
+                    );
+                    return Err(VibratoError::invalid_format("lex.csv", msg));
+                }
+            }
+            sub_words
+                .offsets
+                .push(u32::try_from(sub_words.entries.len())?);
+        }
+        Ok(sub_words)
+    }
+
+    /// Gets the number of words in the lexicon.
+    #[inline(always)]
+    pub fn num_words(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+
+    /// Gets the pairs of the word id and the length in characters of the sub-words
+    /// of the word in the system lexicon, which is empty if the word is not decomposed.
+    #[inline(always)]
+    pub fn get(&self, word_id: u32) -> &[(u32, u32)] {
+        let i = usize::from_u32(word_id);
+        match (self.offsets.get(i), self.offsets.get(i + 1)) {
+            (Some(&start), Some(&end)) => {
+                &self.entries[usize::from_u32(start)..usize::from_u32(end)]
+            }
+            _ => &[],
+        }
+    }
+
+    /// Encodes the decomposition into the payload of its section.
+    pub(crate) fn to_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(4 * (1 + self.offsets.len() + 2 * self.entries.len()));
+        // The number of words fits in u32 since each of them has an offset.
+        payload.extend_from_slice(&(self.num_words() as u32).to_le_bytes());
+        for &offset in &self.offsets {
+            payload.extend_from_slice(&offset.to_le_bytes());
+        }
+        for &(word_id, len_char) in &self.entries {
+            payload.extend_from_slice(&word_id.to_le_bytes());
+            payload.extend_from_slice(&len_char.to_le_bytes());
+        }
+        payload
+    }
+
+    /// Decodes the decomposition from the payload of its section.
+    pub(crate) fn from_payload(payload: &[u8]) -> Result<Self> {
+        let invalid = || VibratoError::invalid_format("data", "The sub-words are corrupted.");
+        let mut values = payload
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+        if !payload.len().is_multiple_of(4) {
+            return Err(invalid());
+        }
+        let num_words = usize::from_u32(values.next().ok_or_else(invalid)?);
+        let offsets: Vec<u32> = values.by_ref().take(num_words + 1).collect();
+        let rest: Vec<u32> = values.collect();
+        let entries: Vec<_> = rest.chunks_exact(2).map(|e| (e[0], e[1])).collect();
+
+        let is_valid = offsets.len() == num_words + 1
+            && rest.len().is_multiple_of(2)
+            && offsets[0] == 0
+            && offsets.windows(2).all(|w| w[0] <= w[1])
+            && usize::from_u32(offsets[num_words]) == entries.len()
+            && entries
+                .iter()
+                .all(|&(word_id, _)| usize::from_u32(word_id) < num_words);
+        if !is_valid {
+            return Err(invalid());
+        }
+        Ok(Self { offsets, entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_reader() {
+        let lexicon_csv = "東京,0,0,1,名詞,*
+都,0,0,1,名詞,
+東京都,0,0,1,名詞,0/1
+,0,0,1,名詞,*
+京都,0,0,1,名詞";
+        let sub_words = SubWords::from_reader(lexicon_csv.as_bytes(), 1).unwrap();
+        assert_eq!(sub_words.num_words(), 4);
+        assert_eq!(sub_words.get(0), []);
+        assert_eq!(sub_words.get(2), [(0, 2), (1, 1)]);
+        assert_eq!(sub_words.get(3), []);
+        assert_eq!(sub_words.get(4), []);
+
+        let payload = sub_words.to_payload();
+        assert_eq!(SubWords::from_payload(&payload).unwrap(), sub_words);
+        assert!(SubWords::from_payload(&payload[..payload.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn test_from_reader_invalid() {
+        let lexicon_csv = "東京,0,0,1,名詞,*\n東京都,0,0,1,名詞,0/2";
+        assert!(SubWords::from_reader(lexicon_csv.as_bytes(), 1).is_err());
+
+        let lexicon_csv = "東京,0,0,1,名詞,*\n東京都,0,0,1,名詞,0/0";
+        assert!(SubWords::from_reader(lexicon_csv.as_bytes(), 1).is_err());
+
+        let lexicon_csv = "東京,0,0,1,名詞,*\n東京都,0,0,1,名詞,0/x";
+        assert!(SubWords::from_reader(lexicon_csv.as_bytes(), 1).is_err());
+    }
+}
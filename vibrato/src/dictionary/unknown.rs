@@ -1,4 +1,5 @@
 use std::io::Read;
+use std::ops::Range;
 
 use rkyv::{Archive, Deserialize, Serialize};
 
@@ -115,6 +116,21 @@ impl UnkHandler {
         }
     }
 
+    /// Generates unknown words spanning exactly `start_char..end_char`,
+    /// using the character category of the first character.
+    pub fn gen_unk_words_in_span<F>(
+        &self,
+        sent: &Sentence,
+        start_char: usize,
+        end_char: usize,
+        f: F,
+    ) where
+        F: FnMut(UnkWord),
+    {
+        debug_assert!(start_char < end_char && end_char <= sent.len_char());
+        self.scan_entries(start_char, end_char, sent.char_info(start_char), f);
+    }
+
     #[inline(always)]
     fn scan_entries<F>(&self, start_char: usize, end_char: usize, cinfo: CharInfo, mut f: F) -> F
     where
@@ -194,12 +210,19 @@ impl UnkHandler {
         self.entries[usize::from_u32(word_idx.word_id)].cate_id
     }
 
-    #[cfg(feature = "train")]
     #[inline(always)]
     pub fn len(&self) -> usize {
         self.entries.len()
     }
 
+    /// Gets the range of word ids of the entries in the category.
+    #[inline(always)]
+    pub fn category_word_ids(&self, cate_id: u32) -> Range<u32> {
+        let cate_id = usize::from_u32(cate_id);
+        // The number of entries fits in u16 since word ids of unknown words are u16.
+        self.offsets[cate_id] as u32..self.offsets[cate_id + 1] as u32
+    }
+
     /// Do NOT make this function public to maintain consistency in
     /// the connection-id mapping among members of `Dictionary`.
     /// The consistency is managed in `Dictionary`.
@@ -313,6 +336,21 @@ impl ArchivedUnkHandler {
         }
     }
 
+    /// Generates unknown words spanning exactly `start_char..end_char`,
+    /// using the character category of the first character.
+    pub fn gen_unk_words_in_span<F>(
+        &self,
+        sent: &Sentence,
+        start_char: usize,
+        end_char: usize,
+        f: F,
+    ) where
+        F: FnMut(UnkWord),
+    {
+        debug_assert!(start_char < end_char && end_char <= sent.len_char());
+        self.scan_entries(start_char, end_char, sent.char_info(start_char), f);
+    }
+
     #[inline(always)]
     fn scan_entries<F>(&self, start_char: usize, end_char: usize, cinfo: CharInfo, mut f: F) -> F
     where
@@ -359,6 +397,20 @@ impl ArchivedUnkHandler {
             .cate_id
             .to_native()
     }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Gets the range of word ids of the entries in the category.
+    #[inline(always)]
+    pub fn category_word_ids(&self, cate_id: u32) -> Range<u32> {
+        let cate_id = usize::from_u32(cate_id);
+        let start = self.offsets[cate_id].to_native() as usize;
+        let end = self.offsets[cate_id + 1].to_native() as usize;
+        start as u32..end as u32
+    }
 }
 
 #[cfg(test)]
@@ -49,6 +49,7 @@
 #[cfg(not(any(target_pointer_width = "32", target_pointer_width = "64")))]
 compile_error!("`target_pointer_width` must be 32 or 64");
 
+pub mod char_filter;
 pub mod common;
 pub mod dictionary;
 pub mod errors;
@@ -105,6 +105,13 @@ impl Sentence {
         self.c2b[pos_char]
     }
 
+    /// Gets the position in characters of the character boundary at `pos_byte`.
+    #[inline(always)]
+    pub fn char_position(&self, pos_byte: usize) -> usize {
+        debug_assert!(self.input.is_char_boundary(pos_byte));
+        self.c2b.partition_point(|&b| b < pos_byte)
+    }
+
     #[inline(always)]
     pub fn char_info(&self, pos_char: usize) -> CharInfo {
         self.cinfos[pos_char]
@@ -1,5 +1,16 @@
-use crate::dictionary::SystemDictionaryBuilder;
-use crate::{Dictionary, Tokenizer};
+use std::sync::Arc;
+
+use crate::char_filter::{UnicodeNormalizer, WidthFolding};
+use crate::dictionary::word_idx::WordIdx;
+use crate::dictionary::{CacheKey, CacheManager, CacheStrategy};
+use crate::dictionary::{
+    DictionaryHandle, FeatureSchema, LexType, SubWords, SystemDictionaryBuilder, WordParam,
+};
+use crate::tokenizer::custom_lexicon::CustomLexicon;
+use crate::tokenizer::unk_generator::{UnkGenerator, UnkInput};
+use crate::tokenizer::unk_rule::UnkRule;
+use crate::tokenizer::worker::Granularity;
+use crate::{Dictionary, LoadMode, Tokenizer};
 
 const LEX_CSV: &str = include_str!("./resources/lex.csv");
 const USER_CSV: &str = include_str!("./resources/user.csv");
@@ -574,3 +585,1006 @@ fn test_tokenize_repeat() {
     worker.tokenize();
     assert_eq!(worker.num_tokens(), 1);
 }
+
+#[test]
+fn test_tokenize_kyotokyo_with_runtime_user() {
+    let dict = {
+        let dict_inner = SystemDictionaryBuilder::from_readers(
+            LEX_CSV.as_bytes(),
+            MATRIX_DEF.as_bytes(),
+            CHAR_DEF.as_bytes(),
+            UNK_DEF.as_bytes(),
+        )
+        .unwrap();
+        let mut buf = vec![];
+        dict_inner.write(&mut buf).unwrap();
+        Dictionary::read(buf.as_slice()).unwrap()
+    };
+
+    let tokenizer = Tokenizer::new(dict)
+        .user_lexicon_from_reader(USER_CSV.as_bytes())
+        .unwrap();
+    let mut worker = tokenizer.new_worker();
+    worker.reset_sentence("京都東京都京都");
+    worker.tokenize();
+    assert_eq!(worker.num_tokens(), 2);
+
+    {
+        let t = worker.token(0);
+        assert_eq!(t.surface(), "京都東京都");
+        assert_eq!(t.range_char(), 0..5);
+        assert_eq!(t.feature(), "カスタム名詞");
+        assert_eq!(t.lex_type(), LexType::User);
+        assert_eq!(t.word_cost(), -1000);
+    }
+    {
+        let t = worker.token(1);
+        assert_eq!(t.surface(), "京都");
+        assert_eq!(t.lex_type(), LexType::System);
+    }
+
+    assert_eq!(worker.token(0).total_cost(), -79 - 1000);
+    assert_eq!(
+        worker.token(1).total_cost(),
+        worker.token(0).total_cost() - 352 + 5293
+    );
+}
+
+#[test]
+fn test_tokenize_with_multiple_user_lexicons() {
+    let dict = {
+        let dict_inner = SystemDictionaryBuilder::from_readers(
+            LEX_CSV.as_bytes(),
+            MATRIX_DEF.as_bytes(),
+            CHAR_DEF.as_bytes(),
+            UNK_DEF.as_bytes(),
+        )
+        .unwrap()
+        .reset_user_lexicon_from_reader(Some(USER_CSV.as_bytes()))
+        .unwrap();
+        Dictionary::from_inner(dict_inner)
+    };
+
+    let tokenizer = Tokenizer::new(dict)
+        .user_lexicon_from_reader("ヴェネツィア京都,0,0,-5000,追加名詞1".as_bytes())
+        .unwrap()
+        .user_lexicon_from_reader("京都東京都京都,0,0,-5000,追加名詞2".as_bytes())
+        .unwrap();
+    let mut worker = tokenizer.new_worker();
+
+    worker.reset_sentence("京都東京都京都");
+    worker.tokenize();
+    assert_eq!(worker.num_tokens(), 1);
+    let t = worker.token(0);
+    assert_eq!(t.feature(), "追加名詞2");
+    assert_eq!(t.word_idx(), WordIdx::new(LexType::User, 4));
+
+    worker.reset_sentence("ヴェネツィア京都");
+    worker.tokenize();
+    assert_eq!(worker.num_tokens(), 1);
+    let t = worker.token(0);
+    assert_eq!(t.feature(), "追加名詞1");
+    assert_eq!(t.word_idx(), WordIdx::new(LexType::User, 3));
+
+    worker.reset_sentence("kampersanda");
+    worker.tokenize();
+    assert_eq!(worker.num_tokens(), 1);
+    let t = worker.token(0);
+    assert_eq!(t.feature(), "カスタム名詞");
+    assert_eq!(t.word_idx(), WordIdx::new(LexType::User, 1));
+}
+
+#[test]
+fn test_user_lexicon_from_reader_invalid_ids() {
+    let dict = build_test_dictionary(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    );
+
+    let result = Tokenizer::new(dict).user_lexicon_from_reader("京都,10000,0,0,名詞".as_bytes());
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_user_lexicon_from_reader_invalid_ids_with_mapper() {
+    let dict_inner = SystemDictionaryBuilder::from_readers(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    )
+    .unwrap()
+    .map_connection_ids_from_iter((1..10).rev(), (1..10).rev())
+    .unwrap();
+    let tokenizer = Tokenizer::new(Dictionary::from_inner(dict_inner));
+
+    assert!(
+        tokenizer
+            .clone()
+            .user_lexicon_from_reader("京都,9,9,0,名詞".as_bytes())
+            .is_ok()
+    );
+    // The ids are out of range of the mapper.
+    assert!(
+        tokenizer
+            .clone()
+            .user_lexicon_from_reader("京都,10,0,0,名詞".as_bytes())
+            .is_err()
+    );
+    assert!(
+        tokenizer
+            .user_lexicon_from_reader("京都,0,10,0,名詞".as_bytes())
+            .is_err()
+    );
+}
+
+#[test]
+fn test_tokenize_with_swapped_dictionary() {
+    let handle = DictionaryHandle::new(build_test_dictionary(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    ));
+    let tokenizer = Tokenizer::from_handle(handle.clone());
+    let mut worker = tokenizer.new_worker();
+
+    worker.reset_sentence("京都東京都京都");
+    worker.tokenize();
+    assert_eq!(worker.num_tokens(), 3);
+
+    let old = handle.swap({
+        let dict_inner = SystemDictionaryBuilder::from_readers(
+            LEX_CSV.as_bytes(),
+            MATRIX_DEF.as_bytes(),
+            CHAR_DEF.as_bytes(),
+            UNK_DEF.as_bytes(),
+        )
+        .unwrap()
+        .reset_user_lexicon_from_reader(Some(USER_CSV.as_bytes()))
+        .unwrap();
+        Dictionary::from_inner(dict_inner)
+    });
+
+    // The results are kept until the next tokenization.
+    assert_eq!(worker.num_tokens(), 3);
+    assert_eq!(worker.token(0).lex_type(), LexType::System);
+
+    worker.tokenize();
+    assert_eq!(worker.num_tokens(), 2);
+    assert_eq!(worker.token(0).feature(), "カスタム名詞");
+
+    // Workers created later also use the new dictionary.
+    let mut worker = tokenizer.new_worker();
+    worker.reset_sentence("京都東京都京都");
+    worker.tokenize();
+    assert_eq!(worker.num_tokens(), 2);
+
+    // Only `tokenizer` still refers to the previous dictionary.
+    assert_eq!(Arc::strong_count(&old), 2);
+    drop(tokenizer);
+    assert_eq!(Arc::strong_count(&old), 1);
+}
+
+#[test]
+fn test_user_lexicon_from_reader_with_handle() {
+    let handle = DictionaryHandle::new(build_test_dictionary(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    ));
+
+    let result = Tokenizer::from_handle(handle).user_lexicon_from_reader(USER_CSV.as_bytes());
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_tokenize_with_char_filters() {
+    let dict = build_test_dictionary(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    );
+
+    let tokenizer = Tokenizer::new(dict)
+        .char_filter(WidthFolding::new())
+        .char_filter(UnicodeNormalizer::nfkc());
+    let mut worker = tokenizer.new_worker();
+    worker.reset_sentence("ｋａｍｐｅｒｓａｎｄａ　東京都");
+    worker.tokenize();
+
+    let tokens: Vec<_> = worker
+        .token_iter()
+        .map(|t| {
+            (
+                t.surface().to_string(),
+                t.original_surface().to_string(),
+                t.original_range_char(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        tokens,
+        vec![
+            (
+                "kampersanda".to_string(),
+                "ｋａｍｐｅｒｓａｎｄａ".to_string(),
+                0..11
+            ),
+            (" ".to_string(), "　".to_string(), 11..12),
+            ("東京都".to_string(), "東京都".to_string(), 12..15),
+        ]
+    );
+    let t = worker.token(2);
+    assert_eq!(t.range_byte(), 12..21);
+    assert_eq!(t.original_range_byte(), 36..45);
+}
+
+#[test]
+fn test_tokenize_with_feature_schema() {
+    let dict_inner = SystemDictionaryBuilder::from_readers(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    )
+    .unwrap();
+    let schema = FeatureSchema::new([
+        "orth", "pos1", "pos2", "pos3", "pos4", "cType", "cForm", "reading", "lemma",
+    ]);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("system.dic");
+    dict_inner
+        .write_with_feature_schema(&schema, std::fs::File::create(&path).unwrap())
+        .unwrap();
+    let mut buffer = vec![];
+    dict_inner.write(&mut buffer).unwrap();
+
+    for dict in [
+        Dictionary::from_path(&path, LoadMode::Validate).unwrap(),
+        Dictionary::read(std::fs::read(&path).unwrap().as_slice()).unwrap(),
+        Dictionary::read(buffer.as_slice())
+            .unwrap()
+            .with_feature_schema(schema.clone()),
+    ] {
+        assert_eq!(dict.feature_schema(), Some(&schema));
+
+        let tokenizer = Tokenizer::new(dict);
+        let mut worker = tokenizer.new_worker();
+        worker.reset_sentence("京都東京都");
+        worker.tokenize();
+        assert_eq!(worker.num_tokens(), 2);
+
+        let t = worker.token(1);
+        assert_eq!(t.feature_field("pos2").as_deref(), Some("固有名詞"));
+        assert_eq!(t.feature_field("unknown"), None);
+        assert_eq!(t.lemma().as_deref(), Some("東京都"));
+        assert_eq!(t.reading().as_deref(), Some("トウキョウト"));
+    }
+
+    let dict = Dictionary::read(buffer.as_slice()).unwrap();
+    assert_eq!(dict.feature_schema(), None);
+    let tokenizer = Tokenizer::new(dict);
+    let mut worker = tokenizer.new_worker();
+    worker.reset_sentence("東京都");
+    worker.tokenize();
+    assert_eq!(worker.token(0).lemma(), None);
+}
+
+#[test]
+fn test_tokenize_from_bytes() {
+    let dict_inner = SystemDictionaryBuilder::from_readers(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    )
+    .unwrap();
+    let mut buffer = vec![];
+    dict_inner.write(&mut buffer).unwrap();
+
+    let mut aligned = rkyv::util::AlignedVec::<16>::new();
+    aligned.extend_from_slice(&buffer);
+    let aligned: &'static [u8] = aligned.leak();
+    // Shifts the bytes by one to make them misaligned.
+    let mut shifted = vec![0];
+    shifted.extend_from_slice(&buffer);
+    let misaligned: &'static [u8] = &shifted.leak()[1..];
+
+    let shared: Arc<[u8]> = buffer.clone().into();
+    for dict in [
+        Dictionary::from_static_slice(aligned, LoadMode::Validate).unwrap(),
+        Dictionary::from_static_slice(aligned, LoadMode::TrustCache).unwrap(),
+        Dictionary::from_static_slice(aligned, LoadMode::TrustCache).unwrap(),
+        Dictionary::from_static_slice(misaligned, LoadMode::Validate).unwrap(),
+        unsafe { Dictionary::from_static_slice_unchecked(misaligned).unwrap() },
+        Dictionary::from_shared_bytes(buffer.clone()).unwrap(),
+        Dictionary::from_shared_bytes(shared.clone()).unwrap(),
+        unsafe { Dictionary::from_shared_bytes_unchecked(shared).unwrap() },
+        unsafe { Dictionary::from_shared_bytes_unchecked(misaligned).unwrap() },
+    ] {
+        let tokenizer = Tokenizer::new(dict);
+        let mut worker = tokenizer.new_worker();
+        worker.reset_sentence("京都東京都");
+        worker.tokenize();
+        assert_eq!(worker.num_tokens(), 2);
+        assert_eq!(worker.token(1).surface(), "東京都");
+    }
+
+    let corrupted = buffer[..buffer.len() / 2].to_vec();
+    assert!(Dictionary::from_shared_bytes(corrupted).is_err());
+    assert!(Dictionary::from_shared_bytes(&b"VibratoTokenizer 0.5\n"[..]).is_err());
+    assert!(unsafe { Dictionary::from_shared_bytes_unchecked(&b""[..]) }.is_err());
+    assert!(Dictionary::from_static_slice(b"", LoadMode::Validate).is_err());
+}
+
+#[test]
+fn test_tokenize_from_zstd_in_memory() {
+    let dict_inner = SystemDictionaryBuilder::from_readers(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    )
+    .unwrap();
+    let mut buffer = vec![];
+    dict_inner.write(&mut buffer).unwrap();
+
+    // The streaming encoder does not record the decompressed size, unlike the bulk one.
+    let mut encoder = zstd::Encoder::new(vec![], 3).unwrap();
+    std::io::Write::write_all(&mut encoder, &buffer).unwrap();
+    let streamed = encoder.finish().unwrap();
+    let bulk = zstd::bulk::compress(&buffer, 3).unwrap();
+
+    // The local cache directory cannot be created since a file occupies its path.
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("system.dic.zst");
+    std::fs::write(&path, &bulk).unwrap();
+    std::fs::write(dir.path().join(".cache"), b"").unwrap();
+
+    for dict in [
+        Dictionary::from_zstd_reader(streamed.as_slice()).unwrap(),
+        Dictionary::from_zstd_reader(bulk.as_slice()).unwrap(),
+        Dictionary::from_zstd_in_memory(&path).unwrap(),
+        Dictionary::from_zstd(&path, CacheStrategy::Local).unwrap(),
+    ] {
+        let tokenizer = Tokenizer::new(dict);
+        let mut worker = tokenizer.new_worker();
+        worker.reset_sentence("京都東京都");
+        worker.tokenize();
+        assert_eq!(worker.num_tokens(), 2);
+        assert_eq!(worker.token(1).surface(), "東京都");
+    }
+    assert!(dir.path().join(".cache").is_file());
+
+    assert!(Dictionary::from_zstd_reader(&bulk[..bulk.len() / 2]).is_err());
+    assert!(Dictionary::from_zstd_reader(buffer.as_slice()).is_err());
+}
+
+#[test]
+fn test_from_zstd_concurrently() {
+    let dict_inner = SystemDictionaryBuilder::from_readers(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    )
+    .unwrap();
+    let mut buffer = vec![];
+    dict_inner.write(&mut buffer).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("system.dic.zst");
+    std::fs::write(&path, zstd::bulk::compress(&buffer, 3).unwrap()).unwrap();
+    let cache_dir = dir.path().join("cache");
+
+    let num_loaders = 8;
+    let barrier = std::sync::Barrier::new(num_loaders);
+    std::thread::scope(|s| {
+        let handles: Vec<_> = (0..num_loaders)
+            .map(|_| {
+                s.spawn(|| {
+                    barrier.wait();
+                    Dictionary::from_zstd_with_options(
+                        &path,
+                        &cache_dir,
+                        #[cfg(feature = "legacy")]
+                        true,
+                    )
+                })
+            })
+            .collect();
+        for handle in handles {
+            let tokenizer = Tokenizer::new(handle.join().unwrap().unwrap());
+            let mut worker = tokenizer.new_worker();
+            worker.reset_sentence("京都東京都");
+            worker.tokenize();
+            assert_eq!(worker.num_tokens(), 2);
+        }
+    });
+
+    // The cache is populated only once, leaving a single proof file.
+    let count = |ext: &str| {
+        std::fs::read_dir(&cache_dir)
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().path().extension().unwrap() == ext)
+            .count()
+    };
+    assert_eq!(count("dic"), 1);
+    assert_eq!(count("sha256"), 1);
+    assert_eq!(count("lock"), 1);
+}
+
+#[test]
+fn test_from_zstd_with_cache_key() {
+    let compress = |feature: &str| {
+        let dict_inner = SystemDictionaryBuilder::from_readers(
+            format!("京都,0,0,1,{feature}").as_bytes(),
+            "1 1\n0 0 0".as_bytes(),
+            "DEFAULT 0 1 0".as_bytes(),
+            "DEFAULT,0,0,100,*".as_bytes(),
+        )
+        .unwrap();
+        let mut buffer = vec![];
+        dict_inner.write(&mut buffer).unwrap();
+        zstd::bulk::compress(&buffer, 3).unwrap()
+    };
+    let feature = |dict: Dictionary| {
+        let tokenizer = Tokenizer::new(dict);
+        let mut worker = tokenizer.new_worker();
+        worker.reset_sentence("京都");
+        worker.tokenize();
+        worker.token(0).feature().to_string()
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("system.dic.zst");
+    std::fs::write(&path, compress("a")).unwrap();
+    let mtime = std::fs::metadata(&path).unwrap().modified().unwrap();
+    let load = |key: CacheKey| Dictionary::from_zstd_with_key(&path, CacheStrategy::Local, key);
+    assert_eq!(feature(load(CacheKey::Metadata).unwrap()), "a");
+    assert_eq!(feature(load(CacheKey::Content).unwrap()), "a");
+
+    // Replaces the file in place, keeping its size and modification time.
+    let replaced = compress("b");
+    assert_eq!(
+        replaced.len() as u64,
+        std::fs::metadata(&path).unwrap().len()
+    );
+    std::fs::write(&path, &replaced).unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+    assert_eq!(feature(load(CacheKey::Metadata).unwrap()), "a");
+    assert_eq!(feature(load(CacheKey::Content).unwrap()), "b");
+
+    let key = CacheKey::Explicit("system-v2".to_string());
+    assert_eq!(feature(load(key.clone()).unwrap()), "b");
+    let cache_dir = dir.path().join(".cache");
+    let dic_path = cache_dir.join(format!("{}.dic", key.resolve(&path).unwrap()));
+    assert!(dic_path.is_file());
+    assert!(load(CacheKey::Explicit("../system".to_string())).is_err());
+
+    // The same explicit key for another file does not share the cache.
+    let other_path = dir.path().join("other.dic.zst");
+    std::fs::write(&other_path, compress("c")).unwrap();
+    let other = Dictionary::from_zstd_with_key(&other_path, CacheStrategy::Local, key.clone());
+    assert_eq!(feature(other.unwrap()), "c");
+
+    for key in [CacheKey::Metadata, CacheKey::Content, key.clone()] {
+        assert_eq!(
+            feature(Dictionary::from_path_with_key(&dic_path, key).unwrap()),
+            "b"
+        );
+    }
+
+    // The caches of the metadata key and the content key of the original file are stale,
+    // but only the content key can tell it since the metadata is unchanged.
+    let entries = CacheManager::new(&cache_dir).entries().unwrap();
+    assert_eq!(entries.len(), 5);
+    assert_eq!(entries.iter().filter(|e| *e.key() == key).count(), 2);
+    let stale: Vec<_> = entries
+        .iter()
+        .filter(|e| e.is_stale())
+        .map(|e| e.key().clone())
+        .collect();
+    assert_eq!(stale, [CacheKey::Content]);
+}
+
+#[test]
+fn test_tokenize_with_sub_words() {
+    let dict_inner = SystemDictionaryBuilder::from_readers(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    )
+    .unwrap();
+    // The 11th column lists the sub-words in the same manner as Sudachi's A unit splitting.
+    let sub_words = SubWords::from_reader(LEX_CSV.as_bytes(), 11).unwrap();
+    assert!(SubWords::from_reader(LEX_CSV.as_bytes(), 14).is_err());
+
+    let mut buffer = vec![];
+    Dictionary::from_inner(dict_inner)
+        .with_sub_words(sub_words.clone())
+        .unwrap()
+        .write(&mut buffer)
+        .unwrap();
+    let dict = Dictionary::read(buffer.as_slice()).unwrap();
+    assert_eq!(dict.sub_words(), Some(&sub_words));
+    assert!(
+        Dictionary::read(buffer.as_slice())
+            .unwrap()
+            .with_sub_words(SubWords::default())
+            .is_err()
+    );
+
+    let tokenizer = Tokenizer::new(dict);
+    let mut worker = tokenizer.new_worker();
+    worker.reset_sentence("京都東京都");
+    worker.tokenize();
+    assert_eq!(worker.num_tokens(), 2);
+    let total_cost = worker.token(1).total_cost();
+
+    worker.set_granularity(Granularity::Fine);
+    worker.reset_sentence("京都東京都");
+    worker.tokenize();
+    assert_eq!(worker.num_tokens(), 3);
+    let t = worker.token(0);
+    assert_eq!(t.surface(), "京都");
+    assert_eq!(t.range_char(), 0..2);
+    let t = worker.token(1);
+    assert_eq!(t.surface(), "東京");
+    assert_eq!(t.range_char(), 2..4);
+    assert_eq!(t.word_idx(), WordIdx::new(LexType::System, 5));
+    assert_eq!(t.feature().split(',').next(), Some("東京"));
+    let t = worker.token(2);
+    assert_eq!(t.surface(), "都");
+    assert_eq!(t.range_char(), 4..5);
+    assert_eq!(t.word_idx(), WordIdx::new(LexType::System, 9));
+    assert_eq!(t.total_cost(), total_cost);
+
+    worker.tokenize_nbest(1);
+    assert_eq!(worker.nbest_token_iter(0).unwrap().count(), 2);
+}
+
+#[test]
+fn test_tokenize_with_unk_rules() {
+    let dict = build_test_dictionary(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    );
+    let tokenizer = Tokenizer::new(dict)
+        .unk_rule(UnkRule::word(r"[0-9]+\.[0-9]+", 9, 9, 0, "名詞,数詞,小数").unwrap())
+        .unwrap()
+        .unk_rule(UnkRule::category(r"[a-z]+@[a-z]+(\.[a-z]+)+", "ALPHA").unwrap())
+        .unwrap();
+    let mut worker = tokenizer.new_worker();
+
+    worker.reset_sentence("東京3.14");
+    worker.tokenize();
+    let tokens: Vec<_> = worker
+        .token_iter()
+        .map(|t| (t.surface().to_string(), t.feature().to_string()))
+        .collect();
+    assert_eq!(
+        tokens,
+        [
+            (
+                "東京".to_string(),
+                "東京,名詞,固有名詞,地名,一般,*,*,トウキョウ,東京,*,A,*,*,*,*".to_string()
+            ),
+            ("3.14".to_string(), "名詞,数詞,小数".to_string()),
+        ]
+    );
+    let t = worker.token(1);
+    assert_eq!(t.word_idx().lex_type, LexType::Unknown);
+    assert_eq!(t.left_id(), 9);
+    assert_eq!(t.word_cost(), 0);
+
+    worker.reset_sentence("user@example.com東京");
+    worker.tokenize();
+    assert_eq!(worker.num_tokens(), 2);
+    let t = worker.token(0);
+    assert_eq!(t.surface(), "user@example.com");
+    assert_eq!(t.feature(), "名詞,普通名詞,一般,*,*,*");
+
+    worker.tokenize_nbest(1);
+    let surfaces: Vec<_> = worker
+        .nbest_token_iter(0)
+        .unwrap()
+        .map(|t| t.surface().to_string())
+        .collect();
+    assert_eq!(surfaces, ["user@example.com", "東京"]);
+
+    let tokenizer = Tokenizer::new(build_test_dictionary(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    ));
+    assert!(
+        tokenizer
+            .clone()
+            .unk_rule(UnkRule::category("x", "NUMERIC").unwrap())
+            .is_err()
+    );
+    assert!(
+        tokenizer
+            .unk_rule(UnkRule::word("x", 10, 0, 0, "*").unwrap())
+            .is_err()
+    );
+
+    // The ids are checked against the mapper before being mapped.
+    let dict_inner = SystemDictionaryBuilder::from_readers(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    )
+    .unwrap()
+    .map_connection_ids_from_iter((1..10).rev(), (1..10).rev())
+    .unwrap();
+    let tokenizer = Tokenizer::new(Dictionary::from_inner(dict_inner));
+    assert!(
+        tokenizer
+            .clone()
+            .unk_rule(UnkRule::word("x", 9, 9, 0, "*").unwrap())
+            .is_ok()
+    );
+    assert!(
+        tokenizer
+            .clone()
+            .unk_rule(UnkRule::word("x", 10, 0, 0, "*").unwrap())
+            .is_err()
+    );
+    assert!(
+        tokenizer
+            .unk_rule(UnkRule::word("x", 0, 10, 0, "*").unwrap())
+            .is_err()
+    );
+}
+
+struct TestCustomLexicon(Vec<(&'static str, WordParam, &'static str)>);
+
+impl CustomLexicon for TestCustomLexicon {
+    fn common_prefix_search(&self, chars: &[char], f: &mut dyn FnMut(usize, u32)) {
+        for (word_id, (surface, _, _)) in self.0.iter().enumerate() {
+            let len_char = surface.chars().count();
+            if surface.chars().eq(chars.iter().take(len_char).copied()) {
+                f(len_char, u32::try_from(word_id).unwrap());
+            }
+        }
+    }
+
+    fn word_param(&self, word_id: u32) -> WordParam {
+        self.0[word_id as usize].1
+    }
+
+    fn word_feature(&self, word_id: u32) -> &str {
+        self.0[word_id as usize].2
+    }
+}
+
+#[test]
+fn test_tokenize_with_custom_lexicon() {
+    let dict = build_test_dictionary(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    );
+    let tokenizer = Tokenizer::new(dict).custom_lexicon(TestCustomLexicon(vec![
+        ("京都東", WordParam::new(6, 6, 100), "custom,valid"),
+        (
+            "京都東京都",
+            WordParam::new(10, 6, -10000),
+            "custom,invalid",
+        ),
+    ]));
+    let mut worker = tokenizer.new_worker();
+    worker.reset_sentence("京都東京都");
+    worker.tokenize();
+    assert_eq!(worker.num_tokens(), 2);
+
+    let t = worker.token(0);
+    assert_eq!(t.surface(), "京都東");
+    assert_eq!(t.lex_type(), LexType::Custom);
+    assert_eq!(t.word_idx(), WordIdx::new(LexType::Custom, 0));
+    assert_eq!(t.feature(), "custom,valid");
+    assert_eq!(t.left_id(), 6);
+    assert_eq!(t.word_cost(), 100);
+    assert_eq!(worker.token(1).surface(), "京都");
+
+    worker.tokenize_nbest(1);
+    let surfaces: Vec<_> = worker
+        .nbest_token_iter(0)
+        .unwrap()
+        .map(|t| t.surface().to_string())
+        .collect();
+    assert_eq!(surfaces, ["京都東", "京都"]);
+
+    // Words too long for any sentence are ignored.
+    let tokenizer = Tokenizer::new(build_test_dictionary(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    ))
+    .custom_lexicon(OverlongCustomLexicon);
+    let mut worker = tokenizer.new_worker();
+    worker.reset_sentence("京都東京都");
+    worker.tokenize();
+    let surfaces: Vec<_> = worker
+        .token_iter()
+        .map(|t| t.surface().to_string())
+        .collect();
+    assert_eq!(surfaces, ["京都", "東京都"]);
+}
+
+/// Custom lexicon reporting a word of the maximum length at every position.
+struct OverlongCustomLexicon;
+
+impl CustomLexicon for OverlongCustomLexicon {
+    fn common_prefix_search(&self, _chars: &[char], f: &mut dyn FnMut(usize, u32)) {
+        f(usize::MAX, 0);
+    }
+
+    fn word_param(&self, _word_id: u32) -> WordParam {
+        WordParam::default()
+    }
+
+    fn word_feature(&self, _word_id: u32) -> &str {
+        "*"
+    }
+}
+
+/// Generator of unknown words of single characters, with invalid words spanning the rest.
+struct TestUnkGenerator;
+
+impl UnkGenerator for TestUnkGenerator {
+    fn gen_unk_words(&self, input: &UnkInput<'_>, f: &mut dyn FnMut(usize, u32)) {
+        let start_char = input.start_char();
+        let alpha = 1 << input.cate_id("ALPHA").unwrap();
+        if input.cate_idset(start_char) & alpha != 0 || !input.has_matched() {
+            f(start_char + 1, 0);
+        }
+        f(input.chars().len(), 1);
+        f(input.chars().len(), 70000);
+    }
+
+    fn word_param(&self, word_id: u32) -> WordParam {
+        match word_id {
+            0 => WordParam::new(6, 6, 100),
+            _ => WordParam::new(10, 6, -10000),
+        }
+    }
+
+    fn word_feature(&self, word_id: u32) -> &str {
+        match word_id {
+            0 => "unk,char",
+            _ => "unk,invalid",
+        }
+    }
+}
+
+#[test]
+fn test_tokenize_with_unk_generator() {
+    let dict = build_test_dictionary(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    );
+    let tokenizer = Tokenizer::new(dict)
+        .unk_generator(TestUnkGenerator)
+        .unwrap();
+    let mut worker = tokenizer.new_worker();
+    worker.reset_sentence("abc東京");
+    worker.tokenize();
+    let surfaces: Vec<_> = worker
+        .token_iter()
+        .map(|t| t.surface().to_string())
+        .collect();
+    assert_eq!(surfaces, ["a", "b", "c", "東京"]);
+
+    let t = worker.token(0);
+    assert_eq!(t.lex_type(), LexType::Unknown);
+    assert_eq!(t.word_idx(), WordIdx::new(LexType::Unknown, 0));
+    assert_eq!(t.feature(), "unk,char");
+    assert_eq!(t.left_id(), 6);
+    assert_eq!(t.word_cost(), 100);
+
+    worker.tokenize_nbest(1);
+    let surfaces: Vec<_> = worker
+        .nbest_token_iter(0)
+        .unwrap()
+        .map(|t| t.surface().to_string())
+        .collect();
+    assert_eq!(surfaces, ["a", "b", "c", "東京"]);
+
+    assert!(
+        tokenizer
+            .unk_rule(UnkRule::category("x", "ALPHA").unwrap())
+            .is_err()
+    );
+    let tokenizer = Tokenizer::new(build_test_dictionary(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    ))
+    .unk_rule(UnkRule::category("x", "ALPHA").unwrap())
+    .unwrap();
+    assert!(tokenizer.unk_generator(TestUnkGenerator).is_err());
+}
+
+#[test]
+fn test_tokenize_batch() {
+    let dict = build_test_dictionary(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    );
+    let tokenizer = Tokenizer::new(dict);
+
+    let sentences = ["京都東京都", "東京都京都", "", "kampersanda東京"];
+    let documents: Vec<_> = (0..200).map(|i| sentences[i % sentences.len()]).collect();
+
+    let mut worker = tokenizer.new_worker();
+    let expected: Vec<Vec<_>> = documents
+        .iter()
+        .map(|d| {
+            worker.reset_sentence(d);
+            worker.tokenize();
+            worker
+                .token_iter()
+                .map(|t| (t.surface().to_string(), t.word_idx()))
+                .collect()
+        })
+        .collect();
+
+    for num_threads in [0, 1, 3] {
+        let results = tokenizer.tokenize_batch(&documents, num_threads);
+        let results: Vec<Vec<_>> = results
+            .into_iter()
+            .map(|tokens| tokens.into_iter().map(|t| (t.surface, t.word_id)).collect())
+            .collect();
+        assert_eq!(results, expected);
+    }
+
+    // A batch smaller than the threads also keeps the input order.
+    let surfaces = tokenizer.tokenize_batch_with(&documents[..16], 4, |worker| {
+        worker
+            .token_iter()
+            .map(|t| t.surface().to_string())
+            .collect::<Vec<_>>()
+    });
+    let expected: Vec<Vec<_>> = expected[..16]
+        .iter()
+        .map(|tokens| tokens.iter().map(|(s, _)| s.clone()).collect())
+        .collect();
+    assert_eq!(surfaces, expected);
+}
+
+/// Reader yielding a few bytes at once, which may split characters.
+struct PieceReader<'a>(&'a [u8]);
+
+impl std::io::Read for PieceReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.0.len()).min(7);
+        buf[..len].copy_from_slice(&self.0[..len]);
+        self.0 = &self.0[len..];
+        Ok(len)
+    }
+}
+
+#[test]
+fn test_tokenize_stream() {
+    let dict = build_test_dictionary(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    );
+    let tokenizer = Tokenizer::new(dict);
+
+    let sentences = ["京都東京都。", "東京都京都\n", "kampersanda東京。", "京都"];
+    let text: String = (0..300)
+        .map(|i| sentences[i * 7 % sentences.len()])
+        .collect();
+
+    let mut worker = tokenizer.new_worker();
+    worker.reset_sentence(&text);
+    worker.tokenize();
+    let expected: Vec<_> = worker
+        .token_iter()
+        .map(|t| (t.range_char(), t.range_byte(), t.word_idx(), t.total_cost()))
+        .collect();
+
+    for max_chunk_len in [65, 100, 1000] {
+        let tokens: Vec<_> = tokenizer
+            .tokenize_stream(PieceReader(text.as_bytes()))
+            .max_chunk_len(max_chunk_len)
+            .unwrap()
+            .map(|t| {
+                let t = t.unwrap();
+                assert_eq!(&text[t.range_byte.clone()], t.surface);
+                (t.range_char, t.range_byte, t.word_id, t.total_cost)
+            })
+            .collect();
+        assert_eq!(tokens, expected);
+    }
+}
+
+#[test]
+fn test_tokenize_stream_errors() {
+    let dict = build_test_dictionary(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    );
+    let tokenizer = Tokenizer::new(dict);
+
+    assert!(tokenizer.tokenize_stream(&b""[..]).next().is_none());
+    assert!(
+        tokenizer
+            .tokenize_stream(&b""[..])
+            .max_chunk_len(64)
+            .is_err()
+    );
+
+    let results: Vec<_> = tokenizer.tokenize_stream(&b"\xe4\xba"[..]).collect();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].is_err());
+}
+
+#[test]
+fn test_worker_moved_to_thread() {
+    let dict = build_test_dictionary(
+        LEX_CSV.as_bytes(),
+        MATRIX_DEF.as_bytes(),
+        CHAR_DEF.as_bytes(),
+        UNK_DEF.as_bytes(),
+    );
+    let tokenizer = Tokenizer::new(dict);
+    let mut worker = tokenizer.new_worker();
+    worker.reset_sentence("京都東京都");
+    worker.tokenize_nbest(3);
+
+    let collect_paths = |worker: &crate::tokenizer::worker::Worker| {
+        (0..worker.num_nbest_paths())
+            .map(|i| {
+                worker
+                    .nbest_token_iter(i)
+                    .unwrap()
+                    .map(|t| (t.surface().to_string(), t.range_char()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    };
+    let expected = collect_paths(&worker);
+    assert_eq!(expected.len(), 3);
+
+    let results = std::thread::spawn(move || {
+        let paths = collect_paths(&worker);
+        worker.reset_sentence("東京都京都");
+        worker.tokenize();
+        (paths, worker.num_tokens())
+    })
+    .join()
+    .unwrap();
+    assert_eq!(results, (expected, 2));
+}
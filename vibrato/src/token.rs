@@ -1,9 +1,10 @@
 //! Container of resultant tokens.
+pub mod filter;
+
 use std::ops::Range;
 
-use crate::dictionary::DictionaryInnerRef;
 use crate::dictionary::{LexType, word_idx::WordIdx};
-use crate::tokenizer::lattice::Node;
+use crate::tokenizer::lattice::{Lattice, Node};
 use crate::tokenizer::worker::Worker;
 
 /// Resultant token.
@@ -39,6 +40,34 @@ impl<'w> Token<'w> {
         let sent = &self.worker.sent;
         &sent.raw()[self.range_byte()]
     }
+
+    /// Gets the position range of the token in bytes in the sentence before
+    /// the character filters are applied.
+    ///
+    /// If a filter replaces characters including the boundary of the token,
+    /// the range is extended to cover all of them.
+    #[inline(always)]
+    pub fn original_range_byte(&self) -> Range<usize> {
+        self.worker.original_range_byte(self.range_byte())
+    }
+
+    /// Gets the position range of the token in characters in the sentence before
+    /// the character filters are applied.
+    ///
+    /// See [`Self::original_range_byte()`] for the details.
+    #[inline(always)]
+    pub fn original_range_char(&self) -> Range<usize> {
+        self.worker
+            .original_range_char(self.range_char(), self.range_byte())
+    }
+
+    /// Gets the surface string of the token in the sentence before
+    /// the character filters are applied.
+    #[inline(always)]
+    pub fn original_surface(&self) -> &'w str {
+        &self.worker.original_sentence()[self.original_range_byte()]
+    }
+
     /// Gets the word index of the token.
     #[inline(always)]
     pub fn word_idx(&self) -> WordIdx {
@@ -49,10 +78,40 @@ impl<'w> Token<'w> {
     /// Gets the feature string of the token.
     #[inline(always)]
     pub fn feature(&self) -> &str {
-        match self.worker.tokenizer.dictionary() {
-            DictionaryInnerRef::Archived(dict) => dict.word_feature(self.word_idx()),
-            DictionaryInnerRef::Owned(dict) => dict.word_feature(self.word_idx()),
-        }
+        self.worker.tokenizer.word_feature(self.word_idx())
+    }
+
+    /// Gets the value of the feature column with the name in the schema of the dictionary.
+    ///
+    /// Returns `None` if the dictionary has no [`FeatureSchema`](crate::dictionary::FeatureSchema) or the column is not found.
+    #[inline(always)]
+    pub fn feature_field(&self, name: &str) -> Option<String> {
+        self.worker
+            .tokenizer
+            .feature_schema()?
+            .field(self.feature(), name)
+    }
+
+    /// Gets the lemma of the token from the feature string.
+    ///
+    /// Returns `None` if the dictionary has no [`FeatureSchema`](crate::dictionary::FeatureSchema) with the lemma column.
+    #[inline(always)]
+    pub fn lemma(&self) -> Option<String> {
+        self.worker
+            .tokenizer
+            .feature_schema()?
+            .lemma(self.feature())
+    }
+
+    /// Gets the reading of the token from the feature string.
+    ///
+    /// Returns `None` if the dictionary has no [`FeatureSchema`](crate::dictionary::FeatureSchema) with the reading column.
+    #[inline(always)]
+    pub fn reading(&self) -> Option<String> {
+        self.worker
+            .tokenizer
+            .feature_schema()?
+            .reading(self.feature())
     }
 
     /// Gets the lexicon type where the token is from.
@@ -79,10 +138,7 @@ impl<'w> Token<'w> {
     #[inline(always)]
     pub fn word_cost(&self) -> i16 {
         let (_, node) = &self.worker.top_nodes[self.index];
-        match self.worker.tokenizer.dictionary() {
-            DictionaryInnerRef::Archived(dict) => dict.word_param(node.word_idx()).word_cost,
-            DictionaryInnerRef::Owned(dict) => dict.word_param(node.word_idx()).word_cost,
-        }
+        self.worker.tokenizer.word_param(node.word_idx()).word_cost
     }
 
     /// Gets the total cost from BOS to the token's node.
@@ -92,6 +148,15 @@ impl<'w> Token<'w> {
         node.min_cost
     }
 
+    /// Gets the marginal probability of the token.
+    ///
+    /// Returns `None` if [`Worker::compute_marginals()`] has not been called.
+    #[inline(always)]
+    pub fn marginal_prob(&self) -> Option<f64> {
+        self.worker
+            .marginal_prob(self.range_char(), self.word_idx())
+    }
+
     pub fn to_buf(&self) -> TokenBuf {
         TokenBuf {
             surface: self.surface().to_string(),
@@ -136,18 +201,10 @@ pub struct NbestToken<'w> {
 }
 
 impl<'w> NbestToken<'w> {
-    /// Gets a raw pointer to the underlying `Node` for this token.
-    #[inline(always)]
-    fn node_ptr(&self) -> *const Node {
-        // This relies on bounds checks performed in NbestTokenIter::new
-        // and NbestTokenIter::next, so it should be safe within the iterator context.
-        self.worker.nbest_paths[self.path_idx].0[self.token_idx]
-    }
-
-    /// Gets a safe reference to the underlying `Node`.
+    /// Gets the underlying `Node` for this token.
     #[inline(always)]
     fn node(&self) -> &'w Node {
-        unsafe { &*self.node_ptr() }
+        &self.worker.nbest_paths[self.path_idx].0[self.token_idx]
     }
 
     /// Gets the end position (in characters) of this token.
@@ -156,7 +213,7 @@ impl<'w> NbestToken<'w> {
         let path = &self.worker.nbest_paths[self.path_idx].0;
         if self.token_idx + 1 < path.len() {
             // If there is a next token, its start position is our end position.
-            unsafe { (*path[self.token_idx + 1]).start_word }
+            path[self.token_idx + 1].start_word
         } else {
             // If this is the last token in the path, the sentence end is our end.
             self.worker.sent.len_char()
@@ -169,13 +226,70 @@ impl<'w> NbestToken<'w> {
         &self.worker.sent.raw()[self.range_byte()]
     }
 
+    /// Gets the position range of the token in bytes in the sentence before
+    /// the character filters are applied.
+    ///
+    /// If a filter replaces characters including the boundary of the token,
+    /// the range is extended to cover all of them.
+    #[inline(always)]
+    pub fn original_range_byte(&self) -> Range<usize> {
+        self.worker.original_range_byte(self.range_byte())
+    }
+
+    /// Gets the position range of the token in characters in the sentence before
+    /// the character filters are applied.
+    ///
+    /// See [`Self::original_range_byte()`] for the details.
+    #[inline(always)]
+    pub fn original_range_char(&self) -> Range<usize> {
+        self.worker
+            .original_range_char(self.range_char(), self.range_byte())
+    }
+
+    /// Gets the surface string of the token in the sentence before
+    /// the character filters are applied.
+    #[inline(always)]
+    pub fn original_surface(&self) -> &'w str {
+        &self.worker.original_sentence()[self.original_range_byte()]
+    }
+
     /// Gets the feature string of the token.
     #[inline(always)]
     pub fn feature(&self) -> &'w str {
-        match self.worker.tokenizer.dictionary() {
-            DictionaryInnerRef::Archived(dict) => dict.word_feature(self.word_idx()),
-            DictionaryInnerRef::Owned(dict) => dict.word_feature(self.word_idx()),
-        }
+        self.worker.tokenizer.word_feature(self.word_idx())
+    }
+
+    /// Gets the value of the feature column with the name in the schema of the dictionary.
+    ///
+    /// Returns `None` if the dictionary has no [`FeatureSchema`](crate::dictionary::FeatureSchema) or the column is not found.
+    #[inline(always)]
+    pub fn feature_field(&self, name: &str) -> Option<String> {
+        self.worker
+            .tokenizer
+            .feature_schema()?
+            .field(self.feature(), name)
+    }
+
+    /// Gets the lemma of the token from the feature string.
+    ///
+    /// Returns `None` if the dictionary has no [`FeatureSchema`](crate::dictionary::FeatureSchema) with the lemma column.
+    #[inline(always)]
+    pub fn lemma(&self) -> Option<String> {
+        self.worker
+            .tokenizer
+            .feature_schema()?
+            .lemma(self.feature())
+    }
+
+    /// Gets the reading of the token from the feature string.
+    ///
+    /// Returns `None` if the dictionary has no [`FeatureSchema`](crate::dictionary::FeatureSchema) with the reading column.
+    #[inline(always)]
+    pub fn reading(&self) -> Option<String> {
+        self.worker
+            .tokenizer
+            .feature_schema()?
+            .reading(self.feature())
     }
 
     /// Gets the position range of the token in characters.
@@ -218,8 +332,7 @@ impl<'w> NbestToken<'w> {
     /// Gets the word cost of the token's node.
     #[inline(always)]
     pub fn word_cost(&self) -> i16 {
-        let dict = self.worker.tokenizer.dictionary();
-        dict.word_param(self.word_idx()).word_cost
+        self.worker.tokenizer.word_param(self.word_idx()).word_cost
     }
 
     /// Gets the total cost from the beginning of the sentence (BOS)
@@ -345,6 +458,172 @@ impl<'w> Iterator for NbestTokenIter<'w> {
     }
 }
 
+/// A candidate node in the lattice.
+///
+/// Unlike [`Token`], it can be any node inserted into the lattice,
+/// not only the one in the best path. It is created by [`LatticeNodeIter`].
+pub struct LatticeNode<'w> {
+    worker: &'w Worker,
+    lattice: &'w Lattice,
+    end_char: usize,
+    index: usize,
+}
+
+impl<'w> LatticeNode<'w> {
+    #[inline(always)]
+    fn node(&self) -> &'w Node {
+        &self.lattice.nodes(self.end_char)[self.index]
+    }
+
+    /// Gets the position range of the node in characters.
+    #[inline(always)]
+    pub fn range_char(&self) -> Range<usize> {
+        self.node().start_word..self.end_char
+    }
+
+    /// Gets the position range of the node in bytes.
+    #[inline(always)]
+    pub fn range_byte(&self) -> Range<usize> {
+        let sent = &self.worker.sent;
+        sent.byte_position(self.node().start_word)..sent.byte_position(self.end_char)
+    }
+
+    /// Gets the surface string of the node.
+    #[inline(always)]
+    pub fn surface(&self) -> &'w str {
+        &self.worker.sent.raw()[self.range_byte()]
+    }
+
+    /// Gets the word index of the node.
+    #[inline(always)]
+    pub fn word_idx(&self) -> WordIdx {
+        self.node().word_idx()
+    }
+
+    /// Gets the feature string of the node.
+    #[inline(always)]
+    pub fn feature(&self) -> &'w str {
+        self.worker.tokenizer.word_feature(self.word_idx())
+    }
+
+    /// Gets the lexicon type where the node is from.
+    #[inline(always)]
+    pub fn lex_type(&self) -> LexType {
+        self.word_idx().lex_type
+    }
+
+    /// Gets the left connection ID of the node.
+    #[inline(always)]
+    pub fn left_id(&self) -> u16 {
+        self.node().left_id
+    }
+
+    /// Gets the right connection ID of the node.
+    #[inline(always)]
+    pub fn right_id(&self) -> u16 {
+        self.node().right_id
+    }
+
+    /// Gets the word cost of the node.
+    #[inline(always)]
+    pub fn word_cost(&self) -> i16 {
+        self.worker.tokenizer.word_param(self.word_idx()).word_cost
+    }
+
+    /// Gets the minimum total cost from BOS to the node,
+    /// calculated during the forward Viterbi pass.
+    #[inline(always)]
+    pub fn total_cost(&self) -> i32 {
+        self.node().min_cost
+    }
+
+    /// Gets the marginal probability of the node.
+    ///
+    /// Returns `None` if [`Worker::compute_marginals()`] has not been called.
+    #[inline(always)]
+    pub fn marginal_prob(&self) -> Option<f64> {
+        self.worker.marginals.prob_at(self.end_char, self.index)
+    }
+
+    /// Converts this node view into an owned `TokenBuf`.
+    pub fn to_buf(&self) -> TokenBuf {
+        TokenBuf {
+            surface: self.surface().to_string(),
+            feature: self.feature().to_string(),
+            range_char: self.range_char(),
+            range_byte: self.range_byte(),
+            word_id: self.word_idx(),
+            lex_type: self.lex_type(),
+            left_id: self.left_id(),
+            right_id: self.right_id(),
+            word_cost: self.word_cost(),
+            total_cost: self.total_cost(),
+        }
+    }
+}
+
+impl std::fmt::Debug for LatticeNode<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LatticeNode")
+            .field("surface", &self.surface())
+            .field("range_char", &self.range_char())
+            .field("range_byte", &self.range_byte())
+            .field("feature", &self.feature())
+            .field("lex_type", &self.lex_type())
+            .field("word_id", &self.word_idx())
+            .field("left_id", &self.left_id())
+            .field("right_id", &self.right_id())
+            .field("word_cost", &self.word_cost())
+            .field("total_cost", &self.total_cost())
+            .finish()
+    }
+}
+
+/// Iterator of all the nodes in the lattice, except BOS and EOS.
+///
+/// The nodes are sorted by their end positions.
+pub struct LatticeNodeIter<'w> {
+    worker: &'w Worker,
+    lattice: Option<&'w Lattice>,
+    end_char: usize,
+    index: usize,
+}
+
+impl<'w> LatticeNodeIter<'w> {
+    #[inline(always)]
+    pub(crate) const fn new(worker: &'w Worker, lattice: Option<&'w Lattice>) -> Self {
+        Self {
+            worker,
+            lattice,
+            end_char: 1,
+            index: 0,
+        }
+    }
+}
+
+impl<'w> Iterator for LatticeNodeIter<'w> {
+    type Item = LatticeNode<'w>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let lattice = self.lattice?;
+        while self.end_char <= lattice.len_char() {
+            if self.index < lattice.nodes(self.end_char).len() {
+                let node = LatticeNode {
+                    worker: self.worker,
+                    lattice,
+                    end_char: self.end_char,
+                    index: self.index,
+                };
+                self.index += 1;
+                return Some(node);
+            }
+            self.end_char += 1;
+            self.index = 0;
+        }
+        None
+    }
+}
+
 /// An owned, self-contained token.
 ///
 /// This struct is the owned counterpart to [`Token`].
@@ -413,4 +692,67 @@ mod tests {
         }
         assert!(it.next().is_none());
     }
+
+    #[test]
+    fn test_lattice_nodes() {
+        let lexicon_csv = "自然,0,0,1,sizen
+言語,0,0,4,gengo
+処理,0,0,3,shori
+自然言語,0,0,6,sizengengo
+言語処理,0,0,5,gengoshori";
+        let matrix_def = "1 1\n0 0 0";
+        let char_def = "DEFAULT 0 1 0";
+        let unk_def = "DEFAULT,0,0,100,*";
+
+        let dict_inner = SystemDictionaryBuilder::from_readers(
+            lexicon_csv.as_bytes(),
+            matrix_def.as_bytes(),
+            char_def.as_bytes(),
+            unk_def.as_bytes(),
+        )
+        .unwrap();
+
+        let mut buffer = Vec::new();
+        dict_inner.write(&mut buffer).unwrap();
+
+        let dict = Dictionary::read(buffer.as_slice()).unwrap();
+
+        let tokenizer = Tokenizer::new(dict);
+        let mut worker = tokenizer.new_worker();
+        worker.reset_sentence("自然言語処理");
+        assert!(worker.lattice_nodes().next().is_none());
+
+        worker.tokenize();
+        let nodes: Vec<_> = worker
+            .lattice_nodes()
+            .map(|n| {
+                (
+                    n.surface(),
+                    n.range_char(),
+                    n.feature(),
+                    n.word_cost(),
+                    n.total_cost(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            nodes,
+            [
+                ("自然", 0..2, "sizen", 1, 1),
+                ("自然言語", 0..4, "sizengengo", 6, 6),
+                ("言語", 2..4, "gengo", 4, 5),
+                ("言語処理", 2..6, "gengoshori", 5, 6),
+                ("処理", 4..6, "shori", 3, 8),
+            ]
+        );
+        assert!(worker.lattice_nodes().all(|n| n.marginal_prob().is_none()));
+
+        worker.compute_marginals(1.0).unwrap();
+        let sum: f64 = worker
+            .lattice_nodes()
+            .filter(|n| n.range_char().end == 6)
+            .map(|n| n.marginal_prob().unwrap())
+            .sum();
+        assert!((sum - 1.0).abs() < 1e-9);
+    }
 }
@@ -0,0 +1,325 @@
+//! Filters for post-processing resultant tokens.
+//!
+//! A [`TokenFilter`] removes or rewrites a [`TokenBuf`], and [`TokenFilterChain`]
+//! composes filters to process the tokens of a sentence at once.
+//!
+//! # Examples
+//!
+//! ```
+//! use vibrato_rkyv::token::filter::{PosFilter, ReplaceSurface, StopwordFilter, TokenFilterChain};
+//! use vibrato_rkyv::{SystemDictionaryBuilder, Tokenizer};
+//!
+//! let lexicon_csv = "本,0,0,1,名詞,普通名詞,本,ホン
+//! を,0,0,1,助詞,格助詞,を,ヲ
+//! 読ん,0,0,1,動詞,一般,読む,ヨン
+//! だ,0,0,1,助動詞,*,た,ダ";
+//! let dict = SystemDictionaryBuilder::from_readers(
+//!     lexicon_csv.as_bytes(),
+//!     "1 1\n0 0 0".as_bytes(),
+//!     "DEFAULT 0 1 0".as_bytes(),
+//!     "DEFAULT,0,0,100,*".as_bytes(),
+//! )?;
+//! let tokenizer = Tokenizer::from_inner(dict);
+//! let mut worker = tokenizer.new_worker();
+//! worker.reset_sentence("本を読んだ");
+//! worker.tokenize();
+//!
+//! let chain = TokenFilterChain::new()
+//!     .filter(PosFilter::exclude(["助詞", "助動詞"]))
+//!     .filter(StopwordFilter::new(["本"]))
+//!     .filter(ReplaceSurface::new(2));
+//! let tokens = chain.filter_tokens(worker.token_iter());
+//! assert_eq!(tokens.len(), 1);
+//! assert_eq!(tokens[0].surface, "読む");
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use hashbrown::HashSet;
+
+use crate::token::TokenBuf;
+use crate::utils;
+
+/// Filter removing or rewriting tokens.
+///
+/// Closures of `Fn(&mut TokenBuf) -> bool` also implement this trait.
+pub trait TokenFilter: Send + Sync {
+    /// Processes the token, returning `false` if it should be removed.
+    fn apply(&self, token: &mut TokenBuf) -> bool;
+}
+
+impl<F> TokenFilter for F
+where
+    F: Fn(&mut TokenBuf) -> bool + Send + Sync,
+{
+    fn apply(&self, token: &mut TokenBuf) -> bool {
+        self(token)
+    }
+}
+
+/// Sequence of token filters applied in order.
+///
+/// A token removed by a filter is not passed to the subsequent ones.
+#[derive(Default)]
+pub struct TokenFilterChain {
+    filters: Vec<Box<dyn TokenFilter>>,
+}
+
+impl TokenFilterChain {
+    /// Creates a new empty chain.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a filter to the chain.
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: TokenFilter + 'static,
+    {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Applies the filters to the tokens, returning the remaining ones.
+    ///
+    /// Both [`Token`](crate::token::Token)s and [`TokenBuf`]s can be passed.
+    pub fn filter_tokens<I>(&self, tokens: I) -> Vec<TokenBuf>
+    where
+        I: IntoIterator,
+        I::Item: Into<TokenBuf>,
+    {
+        tokens
+            .into_iter()
+            .filter_map(|t| {
+                let mut token = t.into();
+                self.apply(&mut token).then_some(token)
+            })
+            .collect()
+    }
+}
+
+impl TokenFilter for TokenFilterChain {
+    fn apply(&self, token: &mut TokenBuf) -> bool {
+        self.filters.iter().all(|f| f.apply(token))
+    }
+}
+
+/// Field of a token referred by filters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenField {
+    /// Surface string.
+    Surface,
+    /// Column of the feature string at the index.
+    Feature(usize),
+}
+
+impl TokenField {
+    fn get<'a>(&self, token: &'a TokenBuf, features: &'a [String]) -> Option<&'a str> {
+        match *self {
+            Self::Surface => Some(&token.surface),
+            Self::Feature(i) => features.get(i).map(String::as_str),
+        }
+    }
+}
+
+/// Filter keeping or removing tokens by part-of-speech prefixes.
+///
+/// A pattern is given as CSV columns such as `名詞,固有名詞`, which matches a token
+/// whose feature starts with the columns. `*` matches any value.
+pub struct PosFilter {
+    patterns: Vec<Vec<String>>,
+    start_column: usize,
+    include: bool,
+}
+
+impl PosFilter {
+    /// Creates a new filter keeping only tokens matching one of the patterns.
+    pub fn include<I, S>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self::new(patterns, true)
+    }
+
+    /// Creates a new filter removing tokens matching one of the patterns.
+    pub fn exclude<I, S>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self::new(patterns, false)
+    }
+
+    fn new<I, S>(patterns: I, include: bool) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            patterns: patterns
+                .into_iter()
+                .map(|p| utils::parse_csv_row(p.as_ref()))
+                .collect(),
+            start_column: 0,
+            include,
+        }
+    }
+
+    /// Specifies the column of the feature where part-of-speech tags start.
+    /// The default value is 0.
+    pub fn start_column(mut self, column: usize) -> Self {
+        self.start_column = column;
+        self
+    }
+}
+
+impl TokenFilter for PosFilter {
+    fn apply(&self, token: &mut TokenBuf) -> bool {
+        let features = utils::parse_csv_row(&token.feature);
+        let tags = features.get(self.start_column..).unwrap_or_default();
+        let matched = self.patterns.iter().any(|pattern| {
+            pattern.len() <= tags.len() && pattern.iter().zip(tags).all(|(p, t)| p == "*" || p == t)
+        });
+        matched == self.include
+    }
+}
+
+/// Filter removing tokens contained in a set of stopwords.
+pub struct StopwordFilter {
+    words: HashSet<String>,
+    field: TokenField,
+}
+
+impl StopwordFilter {
+    /// Creates a new filter removing tokens whose surfaces are in `words`.
+    pub fn new<I, S>(words: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            words: words.into_iter().map(Into::into).collect(),
+            field: TokenField::Surface,
+        }
+    }
+
+    /// Specifies the field compared with the stopwords, such as the column of base forms.
+    /// The default value is [`TokenField::Surface`].
+    pub fn field(mut self, field: TokenField) -> Self {
+        self.field = field;
+        self
+    }
+}
+
+impl TokenFilter for StopwordFilter {
+    fn apply(&self, token: &mut TokenBuf) -> bool {
+        let features = match self.field {
+            TokenField::Surface => vec![],
+            TokenField::Feature(_) => utils::parse_csv_row(&token.feature),
+        };
+        self.field
+            .get(token, &features)
+            .is_none_or(|w| !self.words.contains(w))
+    }
+}
+
+/// Filter replacing the surface of a token with a column of the feature,
+/// such as the base form or the reading.
+///
+/// The surface is kept if the column does not exist or its value is `*` or empty.
+/// Note that the positions of the token are not changed.
+pub struct ReplaceSurface {
+    column: usize,
+}
+
+impl ReplaceSurface {
+    /// Creates a new filter replacing surfaces with the column at the index.
+    pub const fn new(column: usize) -> Self {
+        Self { column }
+    }
+}
+
+impl TokenFilter for ReplaceSurface {
+    fn apply(&self, token: &mut TokenBuf) -> bool {
+        let mut features = utils::parse_csv_row(&token.feature);
+        if self.column < features.len() {
+            let value = features.swap_remove(self.column);
+            if !value.is_empty() && value != "*" {
+                token.surface = value;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::dictionary::LexType;
+    use crate::dictionary::word_idx::WordIdx;
+
+    fn token(surface: &str, feature: &str) -> TokenBuf {
+        TokenBuf {
+            surface: surface.to_string(),
+            feature: feature.to_string(),
+            range_char: 0..surface.chars().count(),
+            range_byte: 0..surface.len(),
+            lex_type: LexType::System,
+            word_id: WordIdx::new(LexType::System, 0),
+            left_id: 0,
+            right_id: 0,
+            word_cost: 0,
+            total_cost: 0,
+        }
+    }
+
+    #[test]
+    fn test_pos_filter() {
+        let filter = PosFilter::include(["名詞,固有名詞", "動詞"]);
+        assert!(filter.apply(&mut token("京都", "名詞,固有名詞,地名")));
+        assert!(!filter.apply(&mut token("本", "名詞,普通名詞")));
+        assert!(filter.apply(&mut token("読む", "動詞,一般")));
+        assert!(!filter.apply(&mut token("名詞", "名詞")));
+
+        let filter = PosFilter::exclude(["*,格助詞"]).start_column(1);
+        assert!(!filter.apply(&mut token("を", "を,助詞,格助詞")));
+        assert!(filter.apply(&mut token("は", "は,助詞,係助詞")));
+    }
+
+    #[test]
+    fn test_stopword_filter() {
+        let filter = StopwordFilter::new(["する"]);
+        assert!(!filter.apply(&mut token("する", "動詞")));
+        assert!(filter.apply(&mut token("し", "動詞,する")));
+
+        let filter = StopwordFilter::new(["する"]).field(TokenField::Feature(1));
+        assert!(!filter.apply(&mut token("し", "動詞,する")));
+        assert!(filter.apply(&mut token("する", "動詞")));
+    }
+
+    #[test]
+    fn test_replace_surface() {
+        let filter = ReplaceSurface::new(1);
+        let mut t = token("読ん", "動詞,読む");
+        assert!(filter.apply(&mut t));
+        assert_eq!(t.surface, "読む");
+        assert_eq!(t.range_char, 0..2);
+
+        let mut t = token("ｶﾞ", "*,*");
+        filter.apply(&mut t);
+        assert_eq!(t.surface, "ｶﾞ");
+    }
+
+    #[test]
+    fn test_chain() {
+        let chain = TokenFilterChain::new()
+            .filter(|t: &mut TokenBuf| {
+                t.surface.make_ascii_lowercase();
+                true
+            })
+            .filter(StopwordFilter::new(["the"]));
+        let tokens = chain.filter_tokens([token("The", "DET"), token("Cat", "NOUN")]);
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].surface, "cat");
+    }
+}
@@ -1,25 +1,64 @@
 //! Viterbi-based tokenizer.
+pub mod constraints;
+pub mod custom_lexicon;
+pub mod explain;
+mod export;
 pub(crate) mod lattice;
+pub(crate) mod marginal;
 mod nbest_generator;
+pub mod search;
+pub mod stream;
+pub mod unk_generator;
+pub mod unk_rule;
+mod user_lexicon;
 pub mod worker;
 
-use std::sync::Arc;
+use std::io::Read;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 use crate::Dictionary;
-use crate::dictionary::connector::{ArchivedConnectorWrapper, ConnectorCost, ConnectorWrapper};
-use crate::dictionary::{ArchivedDictionaryInner, DictionaryInner, DictionaryInnerRef};
+use crate::char_filter::CharFilter;
+use crate::dictionary::connector::{
+    ArchivedConnectorWrapper, ConnectorCost, ConnectorView, ConnectorWrapper,
+};
+use crate::dictionary::lexicon::{Lexicon, WordParam};
+use crate::dictionary::word_idx::WordIdx;
+use crate::dictionary::{
+    ArchivedDictionaryInner, DictionaryHandle, DictionaryInner, DictionaryInnerRef, FeatureSchema,
+    LexType, SubWords,
+};
 use crate::errors::{Result, VibratoError};
 use crate::sentence::Sentence;
+use crate::token::TokenBuf;
+use crate::tokenizer::constraints::ConstraintTable;
+use crate::tokenizer::custom_lexicon::CustomLexicon;
 use crate::tokenizer::lattice::{Lattice, LatticeNBest};
+use crate::tokenizer::search::{CategoryPenalties, TokenizeMode};
+use crate::tokenizer::stream::TokenStream;
+use crate::tokenizer::unk_generator::{UnkGenerator, UnkInput};
+use crate::tokenizer::unk_rule::{UnkRule, UnkRuleMatch, UnkRules};
+use crate::tokenizer::user_lexicon::UserLexicons;
 use crate::tokenizer::worker::Worker;
 
 /// Tokenizer.
 #[derive(Clone)]
 pub struct Tokenizer {
     dict: Arc<Dictionary>,
+    // Handle to follow when the dictionary is swappable, with the generation of `dict`
+    handle: Option<(DictionaryHandle, u64)>,
+    user_lexicons: UserLexicons,
+    unk_rules: UnkRules,
+    custom_lexicon: Option<Arc<dyn CustomLexicon>>,
+    unk_generator: Option<Arc<dyn UnkGenerator>>,
+    char_filters: Vec<Arc<dyn CharFilter>>,
     // For the MeCab compatibility
     space_cateset: Option<u32>,
     max_grouping_len: Option<usize>,
+    mode: TokenizeMode,
+    // Penalties of the search mode resolved with `dict`
+    search_penalty: Option<CategoryPenalties>,
 }
 
 impl Tokenizer {
@@ -34,20 +73,33 @@ impl Tokenizer {
     pub fn new(dict: Dictionary) -> Self {
         Self {
             dict: Arc::new(dict),
+            handle: None,
+            user_lexicons: UserLexicons::default(),
+            unk_rules: UnkRules::default(),
+            custom_lexicon: None,
+            unk_generator: None,
+            char_filters: vec![],
             space_cateset: None,
             max_grouping_len: None,
+            mode: TokenizeMode::Normal,
+            search_penalty: None,
         }
     }
 
     /// Creates a new tokenizer from `DictionaryInner`.
     pub fn from_inner(dict: DictionaryInner) -> Self {
         Self {
-            dict: Arc::new(Dictionary::Owned {
-                dict: Arc::new(dict),
-                _caching_handle: None,
-            }),
+            dict: Arc::new(Dictionary::from_inner(dict)),
+            handle: None,
+            user_lexicons: UserLexicons::default(),
+            unk_rules: UnkRules::default(),
+            custom_lexicon: None,
+            unk_generator: None,
+            char_filters: vec![],
             space_cateset: None,
             max_grouping_len: None,
+            mode: TokenizeMode::Normal,
+            search_penalty: None,
         }
     }
 
@@ -59,11 +111,36 @@ impl Tokenizer {
     pub fn from_shared_dictionary(dict: Arc<Dictionary>) -> Self {
         Self {
             dict,
+            handle: None,
+            user_lexicons: UserLexicons::default(),
+            unk_rules: UnkRules::default(),
+            custom_lexicon: None,
+            unk_generator: None,
+            char_filters: vec![],
             space_cateset: None,
             max_grouping_len: None,
+            mode: TokenizeMode::Normal,
+            search_penalty: None,
         }
     }
 
+    /// Creates a new tokenizer following the dictionary in the handle.
+    ///
+    /// When the dictionary in the handle is replaced with [`DictionaryHandle::swap()`],
+    /// the tokenizer and its workers switch to the new one at the beginning of their
+    /// next tokenization. Tokens obtained before the switch remain valid until then.
+    /// Note that the tokenizer itself keeps the dictionary loaded at its creation
+    /// until it is dropped, while workers created later start with the latest one.
+    ///
+    /// If [`Self::ignore_space()`] is enabled, category `SPACE` is looked up again
+    /// in the new dictionary, and spaces are no longer ignored if it is not defined.
+    pub fn from_handle(handle: DictionaryHandle) -> Self {
+        let (dict, generation) = handle.load_with_generation();
+        let mut tokenizer = Self::from_shared_dictionary(dict);
+        tokenizer.handle = Some((handle, generation));
+        tokenizer
+    }
+
     /// Ignores spaces from tokens.
     ///
     /// This option is for compatibility with MeCab.
@@ -111,6 +188,348 @@ impl Tokenizer {
         self
     }
 
+    /// Specifies the mode of tokenization.
+    /// The default value is [`TokenizeMode::Normal`].
+    ///
+    /// See [`search`] for the search mode decompounding long words.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when a category of the penalties is not defined
+    /// in the input dictionary (i.e., char.def).
+    pub fn mode(mut self, mode: TokenizeMode) -> Result<Self> {
+        if let Some(penalty) = mode.penalty() {
+            for (category, _) in penalty.categories() {
+                if self.cate_id(category).is_none() {
+                    return Err(VibratoError::invalid_argument(
+                        "mode",
+                        format!(
+                            "{category} is not defined in the input dictionary (i.e., char.def)."
+                        ),
+                    ));
+                }
+            }
+        }
+        self.search_penalty = mode
+            .penalty()
+            .map(|penalty| CategoryPenalties::new(penalty, |c| self.cate_id(c)));
+        self.mode = mode;
+        Ok(self)
+    }
+
+    /// Gets the mode of tokenization.
+    #[inline(always)]
+    pub const fn tokenize_mode(&self) -> &TokenizeMode {
+        &self.mode
+    }
+
+    /// Gets the id of the character category in the dictionary.
+    fn cate_id(&self, category: &str) -> Option<u32> {
+        match &*self.dict {
+            Dictionary::Archived(archived_dict) => archived_dict.char_prop().cate_id(category),
+            Dictionary::Owned { dict, .. } => dict.char_prop().cate_id(category),
+        }
+    }
+
+    /// Appends a character filter applied to input sentences before tokenization.
+    ///
+    /// Filters are applied in the order they are appended. Positions of tokens refer to
+    /// the filtered text, and those in the original text are available through
+    /// [`Token::original_range_byte()`](crate::token::Token::original_range_byte) and
+    /// [`Token::original_range_char()`](crate::token::Token::original_range_char).
+    /// See [`char_filter`](crate::char_filter) for the built-in filters.
+    pub fn char_filter<F>(mut self, filter: F) -> Self
+    where
+        F: CharFilter + 'static,
+    {
+        self.char_filters.push(Arc::new(filter));
+        self
+    }
+
+    /// Attaches a user lexicon in the CSV format to the tokenizer.
+    ///
+    /// Unlike [`SystemDictionaryBuilder`](crate::SystemDictionaryBuilder) or
+    /// [`DictionaryInner::reset_user_lexicon_from_reader()`], this does not modify
+    /// the dictionary, so it works for dictionaries loaded in the archived form and
+    /// shared among multiple tokenizers. It can be called multiple times to attach
+    /// several lexicons, in addition to the user lexicon in the dictionary.
+    ///
+    /// Words in the lexicon are treated as [`LexType::User`], and their connection ids
+    /// are interpreted in the same way as ones given to the dictionary builder.
+    ///
+    /// # Arguments
+    ///
+    ///  - `user_lexicon_rdr`: A reader of a lexicon file `*.csv`.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when
+    ///  - the tokenizer is created by [`Self::from_handle()`],
+    ///  - the lexicon file is in an invalid format,
+    ///  - the lexicon includes connection ids out of range of the dictionary, or
+    ///  - the number of words exceeds [`u32::MAX`].
+    pub fn user_lexicon_from_reader<R>(mut self, user_lexicon_rdr: R) -> Result<Self>
+    where
+        R: Read,
+    {
+        if self.handle.is_some() {
+            return Err(VibratoError::invalid_state(
+                "User lexicons cannot be attached to a tokenizer with a swappable dictionary.",
+                "The connection ids depend on the dictionary.",
+            ));
+        }
+        let mut user_lexicon = Lexicon::from_reader(user_lexicon_rdr, LexType::User)?;
+        // The connection ids are verified before being mapped since the mapper does not
+        // check their ranges.
+        let (is_valid, first_id) = match self.dictionary() {
+            DictionaryInnerRef::Archived(dict) => {
+                let is_valid = match dict.mapper().as_ref() {
+                    Some(mapper) => {
+                        let mapper = mapper.to_native();
+                        if user_lexicon.verify(&mapper) {
+                            user_lexicon.map_connection_ids(&mapper);
+                            user_lexicon.verify(dict.connector())
+                        } else {
+                            false
+                        }
+                    }
+                    None => user_lexicon.verify(dict.connector()),
+                };
+                (
+                    is_valid,
+                    dict.user_lexicon().as_ref().map_or(0, |l| l.num_words()),
+                )
+            }
+            DictionaryInnerRef::Owned(dict) => {
+                let is_valid = match dict.mapper() {
+                    Some(mapper) => {
+                        if user_lexicon.verify(mapper) {
+                            user_lexicon.map_connection_ids(mapper);
+                            user_lexicon.verify(dict.connector())
+                        } else {
+                            false
+                        }
+                    }
+                    None => user_lexicon.verify(dict.connector()),
+                };
+                (is_valid, dict.user_lexicon().map_or(0, |l| l.num_words()))
+            }
+        };
+        if !is_valid {
+            return Err(VibratoError::invalid_argument(
+                "user_lexicon_rdr",
+                "includes invalid connection ids.",
+            ));
+        }
+        let first_id = u32::try_from(first_id)?;
+        self.user_lexicons.push(user_lexicon, first_id)?;
+        Ok(self)
+    }
+
+    /// Attaches a custom lexicon looked up at runtime, replacing the one attached before.
+    ///
+    /// Words in the lexicon are treated as [`LexType::Custom`] and never penalized in
+    /// the search mode. See [`custom_lexicon`] for the details.
+    pub fn custom_lexicon<L>(mut self, lexicon: L) -> Self
+    where
+        L: CustomLexicon + 'static,
+    {
+        self.custom_lexicon = Some(Arc::new(lexicon));
+        self
+    }
+
+    /// Replaces the generator of unknown words following `unk.def` with another one.
+    ///
+    /// The generator is called at each position in lattice construction, and the
+    /// generated words are penalized in the search mode in the same way as ones of
+    /// `unk.def`. See [`unk_generator`] for the details.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when rules of categories are attached by
+    /// [`Self::unk_rule()`], whose words are the unknown words of `unk.def`.
+    pub fn unk_generator<G>(mut self, generator: G) -> Result<Self>
+    where
+        G: UnkGenerator + 'static,
+    {
+        if self.unk_rules.has_category() {
+            return Err(VibratoError::invalid_state(
+                "An unknown word generator cannot be set with rules of categories.",
+                "The words of the rules are the unknown words of unk.def.",
+            ));
+        }
+        self.unk_generator = Some(Arc::new(generator));
+        Ok(self)
+    }
+
+    /// Gets the parameter of the word in the custom lexicon, whose connection ids are
+    /// mapped in the same way as user lexicons.
+    ///
+    /// Returns `None` if the connection ids are out of range of the dictionary.
+    fn custom_word_param(&self, word_id: u32) -> Option<WordParam> {
+        self.map_word_param(self.custom_lexicon.as_ref()?.word_param(word_id))
+    }
+
+    /// Gets the parameter of the word given by the unknown word generator, whose
+    /// connection ids are mapped in the same way as user lexicons.
+    ///
+    /// Returns `None` if the connection ids are out of range of the dictionary.
+    fn generated_word_param(&self, word_id: u32) -> Option<WordParam> {
+        self.map_word_param(self.unk_generator.as_ref()?.word_param(word_id))
+    }
+
+    /// Maps the connection ids of the parameter given from outside the dictionary.
+    ///
+    /// Returns `None` if the connection ids are out of range of the dictionary.
+    fn map_word_param(&self, mut param: WordParam) -> Option<WordParam> {
+        let (num_left, num_right) = match self.dictionary() {
+            DictionaryInnerRef::Archived(dict) => {
+                if let Some(mapper) = dict.mapper().as_ref() {
+                    if mapper.num_left() <= usize::from(param.left_id)
+                        || mapper.num_right() <= usize::from(param.right_id)
+                    {
+                        return None;
+                    }
+                    param.left_id = mapper.left(param.left_id);
+                    param.right_id = mapper.right(param.right_id);
+                }
+                (dict.connector().num_left(), dict.connector().num_right())
+            }
+            DictionaryInnerRef::Owned(dict) => {
+                if let Some(mapper) = dict.mapper() {
+                    if mapper.num_left() <= usize::from(param.left_id)
+                        || mapper.num_right() <= usize::from(param.right_id)
+                    {
+                        return None;
+                    }
+                    param.left_id = mapper.left(param.left_id);
+                    param.right_id = mapper.right(param.right_id);
+                }
+                (dict.connector().num_left(), dict.connector().num_right())
+            }
+        };
+        (usize::from(param.left_id) < num_left && usize::from(param.right_id) < num_right)
+            .then_some(param)
+    }
+
+    /// Appends a rule injecting unknown words for spans matching a regular expression.
+    ///
+    /// The injected words compete with the other candidates in the lattice by their costs.
+    /// See [`unk_rule`] for the details.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when
+    ///  - the category of the rule is not defined in the input dictionary (i.e., unk.def),
+    ///  - the rule has connection ids out of range of the dictionary, or
+    ///  - the rule has connection ids and the tokenizer is created by [`Self::from_handle()`],
+    ///  - the rule has a category and an unknown word generator is set by
+    ///    [`Self::unk_generator()`].
+    pub fn unk_rule(mut self, mut rule: UnkRule) -> Result<Self> {
+        if let Some(category) = rule.category_name() {
+            if self.unk_generator.is_some() {
+                return Err(VibratoError::invalid_state(
+                    "Rules of categories cannot be attached with an unknown word generator.",
+                    "The words of the rules are the unknown words of unk.def.",
+                ));
+            }
+            if self.unk_words_of_category(category).is_none() {
+                return Err(VibratoError::invalid_argument(
+                    "rule",
+                    format!("{category} is not defined in the input dictionary (i.e., unk.def)."),
+                ));
+            }
+        } else if self.handle.is_some() {
+            return Err(VibratoError::invalid_state(
+                "Rules with connection ids cannot be attached to a tokenizer with a swappable dictionary.",
+                "The connection ids depend on the dictionary.",
+            ));
+        }
+        if let Some(param) = rule.word_param() {
+            let param = self.map_word_param(param).ok_or_else(|| {
+                VibratoError::invalid_argument("rule", "includes invalid connection ids.")
+            })?;
+            rule.set_word_param(param);
+        }
+        let mut unk_rules = std::mem::take(&mut self.unk_rules);
+        unk_rules.push(rule, |c| self.unk_words_of_category(c))?;
+        self.unk_rules = unk_rules;
+        Ok(self)
+    }
+
+    /// Gets the unknown words of the category defined in the dictionary.
+    fn unk_words_of_category(&self, category: &str) -> Option<Vec<(WordIdx, WordParam)>> {
+        let cate_id = self.cate_id(category)?;
+        let word_ids = match self.dictionary() {
+            DictionaryInnerRef::Archived(dict) => dict.unk_handler().category_word_ids(cate_id),
+            DictionaryInnerRef::Owned(dict) => dict.unk_handler().category_word_ids(cate_id),
+        };
+        if word_ids.is_empty() {
+            return None;
+        }
+        Some(
+            word_ids
+                .map(|word_id| {
+                    let word_idx = WordIdx::new(LexType::Unknown, word_id);
+                    (word_idx, self.dictionary().word_param(word_idx))
+                })
+                .collect(),
+        )
+    }
+
+    /// Switches to the latest dictionary in the handle if it has been swapped.
+    ///
+    /// Returns `true` if the dictionary is switched.
+    pub(crate) fn refresh_dictionary(&mut self) -> bool {
+        let Some((handle, generation)) = self.handle.as_mut() else {
+            return false;
+        };
+        if handle.generation() == *generation {
+            return false;
+        }
+        let (dict, new_generation) = handle.load_with_generation();
+        *generation = new_generation;
+        // The previous dictionary is dropped here if this is the last reference.
+        self.dict = dict;
+        if self.space_cateset.is_some() {
+            let cate_id = match &*self.dict {
+                Dictionary::Archived(archived_dict) => archived_dict.char_prop().cate_id("SPACE"),
+                Dictionary::Owned { dict, .. } => dict.char_prop().cate_id("SPACE"),
+            };
+            self.space_cateset = cate_id.map(|cate_id| 1 << cate_id);
+        }
+        if let Some(penalty) = self.mode.penalty() {
+            self.search_penalty = Some(CategoryPenalties::new(penalty, |c| self.cate_id(c)));
+        }
+        if !self.unk_rules.is_empty() {
+            let mut unk_rules = std::mem::take(&mut self.unk_rules);
+            // Never fails since the rules have been resolved once.
+            let _ = unk_rules.resolve(|c| self.unk_words_of_category(c));
+            self.unk_rules = unk_rules;
+        }
+        true
+    }
+
+    /// Gets the character filters.
+    #[inline(always)]
+    pub(crate) fn char_filters(&self) -> &[Arc<dyn CharFilter>] {
+        &self.char_filters
+    }
+
+    /// Gets the schema of feature strings in the dictionary.
+    ///
+    /// See [`Dictionary::feature_schema()`] for the details.
+    pub fn feature_schema(&self) -> Option<&FeatureSchema> {
+        self.dict.feature_schema()
+    }
+
+    /// Gets the decomposition of words into sub-words in the dictionary.
+    ///
+    /// See [`Dictionary::sub_words()`] for the details.
+    pub fn sub_words(&self) -> Option<&SubWords> {
+        self.dict.sub_words()
+    }
+
     /// Gets the reference to the dictionary.
     pub(crate) fn dictionary<'a>(&'a self) -> DictionaryInnerRef<'a> {
         match &*self.dict {
@@ -119,22 +538,238 @@ impl Tokenizer {
         }
     }
 
+    /// Gets the feature string of the word, including ones in the attached user lexicons.
+    #[inline(always)]
+    pub(crate) fn word_feature(&self, word_idx: WordIdx) -> &str {
+        if word_idx.lex_type == LexType::Custom {
+            return self
+                .custom_lexicon
+                .as_ref()
+                .map_or("", |lexicon| lexicon.word_feature(word_idx.word_id));
+        }
+        if let Some((feature, _)) = self.unk_rules.get(word_idx) {
+            return feature;
+        }
+        if let Some(generator) = self.generator_of(word_idx) {
+            return generator.word_feature(word_idx.word_id);
+        }
+        match self.user_lexicons.get(word_idx) {
+            Some((lexicon, word_idx)) => lexicon.word_feature(word_idx),
+            None => self.dictionary().word_feature(word_idx),
+        }
+    }
+
+    /// Gets the unknown word generator if the word is generated by it.
+    #[inline(always)]
+    fn generator_of(&self, word_idx: WordIdx) -> Option<&dyn UnkGenerator> {
+        if word_idx.lex_type != LexType::Unknown || unk_rule::FIRST_WORD_ID <= word_idx.word_id {
+            return None;
+        }
+        self.unk_generator.as_deref()
+    }
+
+    /// Gets the word parameter, including ones in the attached user lexicons.
+    #[inline(always)]
+    pub(crate) fn word_param(&self, word_idx: WordIdx) -> WordParam {
+        if word_idx.lex_type == LexType::Custom {
+            return self.custom_word_param(word_idx.word_id).unwrap_or_default();
+        }
+        if let Some((_, param)) = self.unk_rules.get(word_idx) {
+            return param;
+        }
+        if self.generator_of(word_idx).is_some() {
+            return self
+                .generated_word_param(word_idx.word_id)
+                .unwrap_or_default();
+        }
+        match self.user_lexicons.get(word_idx) {
+            Some((lexicon, word_idx)) => lexicon.word_param(word_idx),
+            None => self.dictionary().word_param(word_idx),
+        }
+    }
+
     /// Creates a new worker.
     pub fn new_worker(&self) -> Worker {
-        Worker::new(self.clone())
+        let mut tokenizer = self.clone();
+        tokenizer.refresh_dictionary();
+        Worker::new(tokenizer)
+    }
+
+    /// Creates an iterator tokenizing the text read from `rdr` chunk by chunk.
+    ///
+    /// See [`TokenStream`] for the details.
+    pub fn tokenize_stream<R>(&self, rdr: R) -> TokenStream<R>
+    where
+        R: Read,
+    {
+        TokenStream::new(self.new_worker(), rdr)
+    }
+
+    /// Tokenizes the documents in parallel, returning the tokens of each document
+    /// in the input order.
+    ///
+    /// See [`Self::tokenize_batch_with()`] for the details.
+    pub fn tokenize_batch<I>(&self, documents: I, num_threads: usize) -> Vec<Vec<TokenBuf>>
+    where
+        I: IntoIterator,
+        I::IntoIter: Send,
+        I::Item: AsRef<str> + Send,
+    {
+        self.tokenize_batch_with(documents, num_threads, |worker| {
+            worker.token_iter().map(TokenBuf::from).collect()
+        })
+    }
+
+    /// Tokenizes the documents in parallel, returning the values computed by `f`
+    /// from the worker after each document is tokenized, in the input order.
+    ///
+    /// Each thread reuses one worker for the documents assigned to it, which are
+    /// fetched from `documents` in small chunks to balance the load.
+    ///
+    /// # Arguments
+    ///
+    /// * `documents` - Documents to be tokenized.
+    /// * `num_threads` - Number of threads. If 0 is given, the available parallelism is used.
+    /// * `f` - Function to extract the result of a document from the worker.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use vibrato_rkyv::{SystemDictionaryBuilder, Tokenizer};
+    ///
+    /// let dict = SystemDictionaryBuilder::from_readers(
+    ///     "京都,0,0,1,kyoto\n東京,0,0,1,tokyo".as_bytes(),
+    ///     "1 1\n0 0 0".as_bytes(),
+    ///     "DEFAULT 0 1 0".as_bytes(),
+    ///     "DEFAULT,0,0,100,*".as_bytes(),
+    /// )?;
+    /// let tokenizer = Tokenizer::from_inner(dict);
+    ///
+    /// let features = tokenizer.tokenize_batch_with(["東京", "京都東京"], 2, |worker| {
+    ///     worker.token_iter().map(|t| t.feature().to_string()).collect::<Vec<_>>()
+    /// });
+    /// assert_eq!(features, vec![vec!["tokyo"], vec!["kyoto", "tokyo"]]);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn tokenize_batch_with<I, F, T>(&self, documents: I, num_threads: usize, f: F) -> Vec<T>
+    where
+        I: IntoIterator,
+        I::IntoIter: Send,
+        I::Item: AsRef<str> + Send,
+        F: Fn(&Worker) -> T + Sync,
+        T: Send,
+    {
+        // Maximum number of documents fetched at once, which keeps the lock contention
+        // low for many short documents.
+        const MAX_CHUNK_SIZE: usize = 64;
+
+        let num_threads = if num_threads == 0 {
+            thread::available_parallelism().map_or(1, NonZeroUsize::get)
+        } else {
+            num_threads
+        };
+        let documents = documents.into_iter();
+        // Each thread receives about four chunks so that small batches are also distributed.
+        // Documents of unknown number are fetched one by one.
+        let chunk_size = (documents.size_hint().0 / (4 * num_threads)).clamp(1, MAX_CHUNK_SIZE);
+        let documents = Mutex::new(documents.enumerate());
+
+        let run = || {
+            let mut worker = self.new_worker();
+            let mut results = vec![];
+            loop {
+                let chunk: Vec<_> = documents
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .by_ref()
+                    .take(chunk_size)
+                    .collect();
+                if chunk.is_empty() {
+                    break;
+                }
+                for (i, document) in chunk {
+                    worker.reset_sentence(document);
+                    worker.tokenize();
+                    results.push((i, f(&worker)));
+                }
+            }
+            results
+        };
+
+        let mut results = if num_threads == 1 {
+            run()
+        } else {
+            thread::scope(|s| {
+                let handles: Vec<_> = (0..num_threads).map(|_| s.spawn(run)).collect();
+                handles
+                    .into_iter()
+                    .flat_map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+                    .collect()
+            })
+        };
+        results.sort_unstable_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, result)| result).collect()
     }
 
-    pub(crate) fn build_lattice(&self, sent: &Sentence, lattice: &mut Lattice) {
+    /// Gets the penalty of the word spanning `start_char..end_char` in the search mode,
+    /// which is 0 in the normal mode and for words never penalized.
+    pub(crate) fn search_penalty_cost(
+        &self,
+        sent: &Sentence,
+        word_idx: WordIdx,
+        start_char: usize,
+        end_char: usize,
+    ) -> i32 {
+        self.search_penalty
+            .as_ref()
+            .map_or(0, |p| p.word_cost(sent, word_idx, start_char, end_char))
+    }
+
+    /// Builds the lattice for 1-best tokenization.
+    ///
+    /// If `constraints` is given, nodes violating it are not inserted, and
+    /// EOS may not be inserted when no path satisfies it.
+    pub(crate) fn build_lattice(
+        &self,
+        sent: &Sentence,
+        lattice: &mut Lattice,
+        constraints: Option<&ConstraintTable>,
+    ) {
+        self.build_lattice_with_penalty(sent, lattice, constraints, self.search_penalty.as_ref());
+    }
+
+    /// Builds the lattice for 1-best tokenization in the same manner as
+    /// [`Self::build_lattice()`], with the length penalties of the search mode
+    /// instead of the ones of the tokenizer.
+    pub(crate) fn build_lattice_with_penalty(
+        &self,
+        sent: &Sentence,
+        lattice: &mut Lattice,
+        constraints: Option<&ConstraintTable>,
+        penalty: Option<&CategoryPenalties>,
+    ) {
         match &*self.dict {
             Dictionary::Archived(archived_dict) => match archived_dict.connector() {
-                ArchivedConnectorWrapper::Matrix(c) => self.build_lattice_inner(sent, lattice, c),
-                ArchivedConnectorWrapper::Raw(c) => self.build_lattice_inner(sent, lattice, c),
-                ArchivedConnectorWrapper::Dual(c) => self.build_lattice_inner(sent, lattice, c),
+                ArchivedConnectorWrapper::Matrix(c) => {
+                    self.build_lattice_inner(sent, lattice, c, constraints, penalty)
+                }
+                ArchivedConnectorWrapper::Raw(c) => {
+                    self.build_lattice_inner(sent, lattice, c, constraints, penalty)
+                }
+                ArchivedConnectorWrapper::Dual(c) => {
+                    self.build_lattice_inner(sent, lattice, c, constraints, penalty)
+                }
             },
             Dictionary::Owned { dict, .. } => match dict.connector() {
-                ConnectorWrapper::Matrix(c) => self.build_lattice_inner(sent, lattice, c),
-                ConnectorWrapper::Raw(c) => self.build_lattice_inner(sent, lattice, c),
-                ConnectorWrapper::Dual(c) => self.build_lattice_inner(sent, lattice, c),
+                ConnectorWrapper::Matrix(c) => {
+                    self.build_lattice_inner(sent, lattice, c, constraints, penalty)
+                }
+                ConnectorWrapper::Raw(c) => {
+                    self.build_lattice_inner(sent, lattice, c, constraints, penalty)
+                }
+                ConnectorWrapper::Dual(c) => {
+                    self.build_lattice_inner(sent, lattice, c, constraints, penalty)
+                }
             },
         }
     }
@@ -160,11 +795,18 @@ impl Tokenizer {
         }
     }
 
-    fn build_lattice_inner<C>(&self, sent: &Sentence, lattice: &mut Lattice, connector: &C)
-    where
+    fn build_lattice_inner<C>(
+        &self,
+        sent: &Sentence,
+        lattice: &mut Lattice,
+        connector: &C,
+        constraints: Option<&ConstraintTable>,
+        penalty: Option<&CategoryPenalties>,
+    ) where
         C: ConnectorCost,
     {
         lattice.reset(sent.len_char());
+        let rule_matches = self.unk_rules.find(sent);
 
         // These variables indicate the starting character positions of words currently stored
         // in the lattice. If ignore_space() is unset, these always have the same values, and
@@ -198,13 +840,25 @@ impl Tokenizer {
                 break;
             }
 
-            self.add_lattice_edges(sent, lattice, start_node, start_word, connector);
+            self.add_lattice_edges(
+                sent,
+                lattice,
+                start_node,
+                start_word,
+                connector,
+                &rule_matches,
+                constraints,
+                penalty,
+            );
 
             start_word += 1;
             start_node = start_word;
         }
 
-        lattice.insert_eos(start_node, connector);
+        // Constraints can make the end of the sentence unreachable.
+        if lattice.has_previous_node(start_node) {
+            lattice.insert_eos(start_node, connector);
+        }
     }
 
     fn build_lattice_inner_nbest<C>(
@@ -216,6 +870,7 @@ impl Tokenizer {
         C: ConnectorCost,
     {
         lattice.reset(sent.len_char());
+        let rule_matches = self.unk_rules.find(sent);
 
         // These variables indicate the starting character positions of words currently stored
         // in the lattice. If ignore_space() is unset, these always have the same values, and
@@ -249,7 +904,14 @@ impl Tokenizer {
                 break;
             }
 
-            self.add_lattice_edges_nbest(sent, lattice, start_node, start_word, connector);
+            self.add_lattice_edges_nbest(
+                sent,
+                lattice,
+                start_node,
+                start_word,
+                connector,
+                &rule_matches,
+            );
 
             start_word += 1;
             start_node = start_word;
@@ -269,58 +931,244 @@ macro_rules! add_lattice_edges_logic {
         $start_word:expr,
         $connector:expr,
         $dict:expr,
+        $rule_matches:expr,
+        $constraints:expr,
+        $penalty:expr,
     ) => {{
         let mut has_matched = false;
+        let mut num_inserted = 0;
         let suffix = &$sent.chars()[$start_word..];
 
+        // Penalties are given only by CategoryPenalties::word_cost(), so that they are
+        // consistent with the ones computed for explanations and exports.
+        let penalty = |word_idx: WordIdx, start_word: usize, end_word: usize| {
+            $penalty.map_or(0, |p: &CategoryPenalties| {
+                p.word_cost($sent, word_idx, start_word, end_word)
+            })
+        };
+
+        let is_allowed = |end_word: usize, word_idx| {
+            $constraints.is_none_or(|c: &ConstraintTable| {
+                c.allows($start_word, end_word)
+                    && (!c.has_pattern($start_word)
+                        || c.matches_feature($start_word, $self.word_feature(word_idx)))
+            })
+        };
+
         if let Some(user_lexicon) = $dict.user_lexicon().as_ref() {
             for m in user_lexicon.common_prefix_iterator(suffix) {
                 debug_assert!($start_word + m.end_char <= $sent.len_char());
+                if !is_allowed($start_word + m.end_char, m.word_idx) {
+                    continue;
+                }
+                $lattice.insert_node(
+                    $start_node,
+                    $start_word,
+                    $start_word + m.end_char,
+                    m.word_idx,
+                    m.word_param,
+                    penalty(m.word_idx, $start_word, $start_word + m.end_char),
+                    $connector,
+                );
+                has_matched = true;
+                num_inserted += 1;
+            }
+        }
+
+        for (first_id, user_lexicon) in $self.user_lexicons.iter() {
+            for mut m in user_lexicon.common_prefix_iterator(suffix) {
+                debug_assert!($start_word + m.end_char <= $sent.len_char());
+                m.word_idx.word_id += first_id;
+                if !is_allowed($start_word + m.end_char, m.word_idx) {
+                    continue;
+                }
                 $lattice.insert_node(
                     $start_node,
                     $start_word,
                     $start_word + m.end_char,
                     m.word_idx,
                     m.word_param,
+                    penalty(m.word_idx, $start_word, $start_word + m.end_char),
                     $connector,
                 );
                 has_matched = true;
+                num_inserted += 1;
+            }
+        }
+
+        if let Some(custom_lexicon) = $self.custom_lexicon.as_deref() {
+            custom_lexicon.common_prefix_search(suffix, &mut |len_char, word_id| {
+                // The length is given from outside, so it may be too large.
+                let Some(end_word) = $start_word.checked_add(len_char) else {
+                    return;
+                };
+                let word_idx = WordIdx::new(LexType::Custom, word_id);
+                if len_char == 0 || $sent.len_char() < end_word || !is_allowed(end_word, word_idx) {
+                    return;
+                }
+                let Some(word_param) = $self.custom_word_param(word_id) else {
+                    return;
+                };
+                $lattice.insert_node(
+                    $start_node,
+                    $start_word,
+                    end_word,
+                    word_idx,
+                    word_param,
+                    penalty(word_idx, $start_word, end_word),
+                    $connector,
+                );
+                has_matched = true;
+                num_inserted += 1;
+            });
+        }
+
+        let first_match = $rule_matches.partition_point(|m| m.start_char < $start_word);
+        for m in $rule_matches[first_match..]
+            .iter()
+            .take_while(|m| m.start_char == $start_word)
+        {
+            for &(word_idx, word_param) in m.words {
+                if !is_allowed(m.end_char, word_idx) {
+                    continue;
+                }
+                $lattice.insert_node(
+                    $start_node,
+                    $start_word,
+                    m.end_char,
+                    word_idx,
+                    word_param,
+                    penalty(word_idx, $start_word, m.end_char),
+                    $connector,
+                );
+                has_matched = true;
+                num_inserted += 1;
             }
         }
 
         for m in $dict.system_lexicon().common_prefix_iterator(suffix) {
             debug_assert!($start_word + m.end_char <= $sent.len_char());
+            if !is_allowed($start_word + m.end_char, m.word_idx) {
+                continue;
+            }
             $lattice.insert_node(
                 $start_node,
                 $start_word,
                 $start_word + m.end_char,
                 m.word_idx,
                 m.word_param,
+                penalty(m.word_idx, $start_word, $start_word + m.end_char),
                 $connector,
             );
             has_matched = true;
+            num_inserted += 1;
         }
 
-        $dict.unk_handler().gen_unk_words(
-            $sent,
-            $start_word,
-            has_matched,
-            $self.max_grouping_len,
-            |w| {
+        if let Some(generator) = $self.unk_generator.as_deref() {
+            let input = UnkInput {
+                tokenizer: $self,
+                sent: $sent,
+                start_char: $start_word,
+                has_matched,
+            };
+            generator.gen_unk_words(&input, &mut |end_word, word_id| {
+                let word_idx = WordIdx::new(LexType::Unknown, word_id);
+                if end_word <= $start_word
+                    || $sent.len_char() < end_word
+                    || unk_rule::FIRST_WORD_ID <= word_id
+                    || !is_allowed(end_word, word_idx)
+                {
+                    return;
+                }
+                let Some(word_param) = $self.generated_word_param(word_id) else {
+                    return;
+                };
                 $lattice.insert_node(
                     $start_node,
-                    w.start_char(),
-                    w.end_char(),
-                    w.word_idx(),
-                    w.word_param(),
+                    $start_word,
+                    end_word,
+                    word_idx,
+                    word_param,
+                    penalty(word_idx, $start_word, end_word),
                     $connector,
                 );
-            },
-        );
+                num_inserted += 1;
+            });
+        } else {
+            $dict.unk_handler().gen_unk_words(
+                $sent,
+                $start_word,
+                has_matched,
+                $self.max_grouping_len,
+                |w| {
+                    if !is_allowed(w.end_char(), w.word_idx()) {
+                        return;
+                    }
+                    $lattice.insert_node(
+                        $start_node,
+                        w.start_char(),
+                        w.end_char(),
+                        w.word_idx(),
+                        w.word_param(),
+                        penalty(w.word_idx(), w.start_char(), w.end_char()),
+                        $connector,
+                    );
+                    num_inserted += 1;
+                },
+            );
+        }
+
+        // Under constraints, all the candidates can be rejected. Generates unknown words
+        // up to the next required boundary so that this position is not a dead end.
+        if num_inserted == 0
+            && let Some(end_word) = $constraints.map(|c| c.next_boundary($start_word))
+        {
+            if let Some(generator) = $self.unk_generator.as_deref() {
+                let input = UnkInput {
+                    tokenizer: $self,
+                    sent: $sent,
+                    start_char: $start_word,
+                    has_matched: false,
+                };
+                generator.gen_unk_words_in_span(&input, end_word, &mut |word_id| {
+                    if unk_rule::FIRST_WORD_ID <= word_id {
+                        return;
+                    }
+                    let Some(word_param) = $self.generated_word_param(word_id) else {
+                        return;
+                    };
+                    let word_idx = WordIdx::new(LexType::Unknown, word_id);
+                    $lattice.insert_node(
+                        $start_node,
+                        $start_word,
+                        end_word,
+                        word_idx,
+                        word_param,
+                        penalty(word_idx, $start_word, end_word),
+                        $connector,
+                    );
+                });
+            } else {
+                $dict
+                    .unk_handler()
+                    .gen_unk_words_in_span($sent, $start_word, end_word, |w| {
+                        $lattice.insert_node(
+                            $start_node,
+                            w.start_char(),
+                            w.end_char(),
+                            w.word_idx(),
+                            w.word_param(),
+                            penalty(w.word_idx(), w.start_char(), w.end_char()),
+                            $connector,
+                        );
+                    });
+            }
+        }
     }};
 }
 
 impl Tokenizer {
+    #[allow(clippy::too_many_arguments)]
     fn add_lattice_edges<C>(
         &self,
         sent: &Sentence,
@@ -328,15 +1176,35 @@ impl Tokenizer {
         start_node: usize,
         start_word: usize,
         connector: &C,
+        rule_matches: &[UnkRuleMatch],
+        constraints: Option<&ConstraintTable>,
+        penalty: Option<&CategoryPenalties>,
     ) where
         C: ConnectorCost,
     {
         match self.dictionary() {
-            DictionaryInnerRef::Archived(dict) => self
-                .add_lattice_edges_archived(sent, lattice, start_node, start_word, connector, dict),
-            DictionaryInnerRef::Owned(dict) => {
-                self.add_lattice_edges_owned(sent, lattice, start_node, start_word, connector, dict)
-            }
+            DictionaryInnerRef::Archived(dict) => self.add_lattice_edges_archived(
+                sent,
+                lattice,
+                start_node,
+                start_word,
+                connector,
+                dict,
+                rule_matches,
+                constraints,
+                penalty,
+            ),
+            DictionaryInnerRef::Owned(dict) => self.add_lattice_edges_owned(
+                sent,
+                lattice,
+                start_node,
+                start_word,
+                connector,
+                dict,
+                rule_matches,
+                constraints,
+                penalty,
+            ),
         }
     }
 
@@ -347,19 +1215,33 @@ impl Tokenizer {
         start_node: usize,
         start_word: usize,
         connector: &C,
+        rule_matches: &[UnkRuleMatch],
     ) where
         C: ConnectorCost,
     {
         match self.dictionary() {
             DictionaryInnerRef::Archived(dict) => self.add_lattice_edges_archived_nbest(
-                sent, lattice, start_node, start_word, connector, dict,
+                sent,
+                lattice,
+                start_node,
+                start_word,
+                connector,
+                dict,
+                rule_matches,
             ),
             DictionaryInnerRef::Owned(dict) => self.add_lattice_edges_owned_nbest(
-                sent, lattice, start_node, start_word, connector, dict,
+                sent,
+                lattice,
+                start_node,
+                start_word,
+                connector,
+                dict,
+                rule_matches,
             ),
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn add_lattice_edges_archived<C>(
         &self,
         sent: &Sentence,
@@ -368,12 +1250,27 @@ impl Tokenizer {
         start_word: usize,
         connector: &C,
         dict: &ArchivedDictionaryInner,
+        rule_matches: &[UnkRuleMatch],
+        constraints: Option<&ConstraintTable>,
+        penalty: Option<&CategoryPenalties>,
     ) where
         C: ConnectorCost,
     {
-        add_lattice_edges_logic!(self, sent, lattice, start_node, start_word, connector, dict,)
+        add_lattice_edges_logic!(
+            self,
+            sent,
+            lattice,
+            start_node,
+            start_word,
+            connector,
+            dict,
+            rule_matches,
+            constraints,
+            penalty,
+        )
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn add_lattice_edges_owned<C>(
         &self,
         sent: &Sentence,
@@ -382,12 +1279,27 @@ impl Tokenizer {
         start_word: usize,
         connector: &C,
         dict: &DictionaryInner,
+        rule_matches: &[UnkRuleMatch],
+        constraints: Option<&ConstraintTable>,
+        penalty: Option<&CategoryPenalties>,
     ) where
         C: ConnectorCost,
     {
-        add_lattice_edges_logic!(self, sent, lattice, start_node, start_word, connector, dict,)
+        add_lattice_edges_logic!(
+            self,
+            sent,
+            lattice,
+            start_node,
+            start_word,
+            connector,
+            dict,
+            rule_matches,
+            constraints,
+            penalty,
+        )
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn add_lattice_edges_archived_nbest<C>(
         &self,
         sent: &Sentence,
@@ -396,12 +1308,25 @@ impl Tokenizer {
         start_word: usize,
         connector: &C,
         dict: &ArchivedDictionaryInner,
+        rule_matches: &[UnkRuleMatch],
     ) where
         C: ConnectorCost,
     {
-        add_lattice_edges_logic!(self, sent, lattice, start_node, start_word, connector, dict,)
+        add_lattice_edges_logic!(
+            self,
+            sent,
+            lattice,
+            start_node,
+            start_word,
+            connector,
+            dict,
+            rule_matches,
+            None::<&ConstraintTable>,
+            self.search_penalty.as_ref(),
+        )
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn add_lattice_edges_owned_nbest<C>(
         &self,
         sent: &Sentence,
@@ -410,10 +1335,22 @@ impl Tokenizer {
         start_word: usize,
         connector: &C,
         dict: &DictionaryInner,
+        rule_matches: &[UnkRuleMatch],
     ) where
         C: ConnectorCost,
     {
-        add_lattice_edges_logic!(self, sent, lattice, start_node, start_word, connector, dict,)
+        add_lattice_edges_logic!(
+            self,
+            sent,
+            lattice,
+            start_node,
+            start_word,
+            connector,
+            dict,
+            rule_matches,
+            None::<&ConstraintTable>,
+            self.search_penalty.as_ref(),
+        )
     }
 }
 
@@ -421,7 +1358,13 @@ impl Tokenizer {
 mod tests {
     use super::*;
 
-    use crate::dictionary::SystemDictionaryBuilder;
+    use crate::common::BOS_EOS_CONNECTION_ID;
+    use crate::dictionary::word_idx::WordIdx;
+    use crate::dictionary::{LexType, SystemDictionaryBuilder};
+    use crate::tokenizer::constraints::Constraints;
+    use crate::tokenizer::lattice::LatticeKind;
+    use crate::tokenizer::search::SearchPenalty;
+    use crate::tokenizer::worker::NbestOptions;
 
     #[track_caller]
     fn build_test_dictionary(
@@ -691,4 +1634,504 @@ mod tests {
         assert_eq!(tokens.next().unwrap().surface(), "言語");
         assert!(tokens.next().is_none());
     }
+
+    #[test]
+    fn test_tokenize_nbest_with_options() {
+        let lexicon_csv = "自然,0,0,1,sizen
+自然,0,0,2,sizen2
+言語,0,0,4,gengo
+処理,0,0,3,shori
+自然言語,0,0,6,sizengengo
+言語処理,0,0,5,gengoshori";
+        let matrix_def = "1 1\n0 0 0";
+        let char_def = "DEFAULT 0 1 0";
+        let unk_def = "DEFAULT,0,0,100,*";
+
+        let dict = build_test_dictionary(
+            lexicon_csv.as_bytes(),
+            matrix_def.as_bytes(),
+            char_def.as_bytes(),
+            unk_def.as_bytes(),
+        );
+
+        let tokenizer = Tokenizer::new(dict);
+        let mut worker = tokenizer.new_worker();
+        worker.reset_sentence("自然言語処理");
+
+        let costs = |worker: &Worker| {
+            (0..worker.num_nbest_paths())
+                .map(|i| worker.path_cost(i).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        worker.tokenize_nbest(10);
+        assert_eq!(costs(&worker), [6, 7, 8, 9, 9]);
+
+        let options = NbestOptions::new().cost_beam(2);
+        worker.tokenize_nbest_with_options(10, &options);
+        assert_eq!(costs(&worker), [6, 7, 8]);
+
+        let options = NbestOptions::new().distinct_segmentation(true);
+        worker.tokenize_nbest_with_options(10, &options);
+        assert_eq!(costs(&worker), [6, 8, 9]);
+        let surfaces: Vec<_> = worker
+            .nbest_token_iter(2)
+            .unwrap()
+            .map(|t| t.surface())
+            .collect();
+        assert_eq!(surfaces, ["自然言語", "処理"]);
+
+        let options = NbestOptions::new().cost_beam(2).distinct_segmentation(true);
+        worker.tokenize_nbest_with_options(usize::MAX, &options);
+        assert_eq!(costs(&worker), [6, 8]);
+
+        worker.tokenize_nbest_with_options(1, &options);
+        assert_eq!(costs(&worker), [6]);
+    }
+
+    #[test]
+    fn test_tokenize_with_constraints() {
+        let lexicon_csv = "自然,0,0,1,sizen
+言語,0,0,4,gengo
+処理,0,0,3,shori
+自然言語,0,0,6,sizengengo
+言語処理,0,0,5,gengoshori";
+        let matrix_def = "1 1\n0 0 0";
+        let char_def = "DEFAULT 0 1 0";
+        let unk_def = "DEFAULT,0,0,100,*";
+
+        let dict = build_test_dictionary(
+            lexicon_csv.as_bytes(),
+            matrix_def.as_bytes(),
+            char_def.as_bytes(),
+            unk_def.as_bytes(),
+        );
+
+        let tokenizer = Tokenizer::new(dict);
+        let mut worker = tokenizer.new_worker();
+        worker.reset_sentence("自然言語処理");
+
+        let surfaces = |worker: &Worker| {
+            worker
+                .token_iter()
+                .map(|t| t.surface().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // Required boundary
+        let mut constraints = Constraints::new();
+        constraints.add_boundary(4);
+        worker.tokenize_with_constraints(&constraints).unwrap();
+        assert_eq!(surfaces(&worker), ["自然", "言語", "処理"]);
+
+        // Forced word with a feature pattern
+        let mut constraints = Constraints::new();
+        constraints.add_word(0..4, Some("sizengengo"));
+        worker.tokenize_with_constraints(&constraints).unwrap();
+        assert_eq!(surfaces(&worker), ["自然言語", "処理"]);
+        assert_eq!(worker.token(0).feature(), "sizengengo");
+
+        // Forced word not in the dictionary
+        let mut constraints = Constraints::new();
+        constraints.add_word(0..3, None::<&str>);
+        worker.tokenize_with_constraints(&constraints).unwrap();
+        assert_eq!(surfaces(&worker), ["自然言", "語処理"]);
+        assert_eq!(worker.token(0).lex_type(), LexType::Unknown);
+
+        // Forbidden boundaries
+        let mut constraints = Constraints::new();
+        constraints.add_no_boundary(1..5);
+        worker.tokenize_with_constraints(&constraints).unwrap();
+        assert_eq!(surfaces(&worker), ["自然言語処理"]);
+
+        // Conflicting constraints
+        let mut constraints = Constraints::new();
+        constraints.add_boundary(3).add_no_boundary(2..5);
+        assert!(worker.tokenize_with_constraints(&constraints).is_err());
+        assert_eq!(worker.num_tokens(), 0);
+
+        // Without constraints
+        worker
+            .tokenize_with_constraints(&Constraints::new())
+            .unwrap();
+        assert_eq!(surfaces(&worker), ["自然", "言語処理"]);
+    }
+
+    #[test]
+    fn test_compute_marginals() {
+        let lexicon_csv = "自然,0,0,1,sizen
+言語,0,0,4,gengo
+処理,0,0,3,shori
+自然言語,0,0,6,sizengengo
+言語処理,0,0,5,gengoshori";
+        let matrix_def = "1 1\n0 0 0";
+        let char_def = "DEFAULT 0 1 0";
+        let unk_def = "DEFAULT,0,0,100,*";
+
+        let dict = build_test_dictionary(
+            lexicon_csv.as_bytes(),
+            matrix_def.as_bytes(),
+            char_def.as_bytes(),
+            unk_def.as_bytes(),
+        );
+
+        let tokenizer = Tokenizer::new(dict);
+        let mut worker = tokenizer.new_worker();
+        worker.reset_sentence("自然言語処理");
+        assert!(worker.compute_marginals(1.0).is_err());
+
+        worker.tokenize();
+        assert!(worker.compute_marginals(0.0).is_err());
+        assert!(worker.token(0).marginal_prob().is_none());
+
+        // Paths: 自然/言語処理 (6), 自然/言語/処理 (8), and 自然言語/処理 (9)
+        worker.compute_marginals(1.0).unwrap();
+        let z = (-6f64).exp() + (-8f64).exp() + (-9f64).exp();
+        let p0 = worker.token(0).marginal_prob().unwrap();
+        let p1 = worker.token(1).marginal_prob().unwrap();
+        assert!((p0 - ((-6f64).exp() + (-8f64).exp()) / z).abs() < 1e-9);
+        assert!((p1 - (-6f64).exp() / z).abs() < 1e-9);
+
+        let word_idx = WordIdx::new(LexType::System, 3); // 自然言語
+        let p = worker.marginal_prob(0..4, word_idx).unwrap();
+        assert!((p0 + p - 1.0).abs() < 1e-9);
+        assert!(worker.marginal_prob(0..3, word_idx).is_none());
+
+        // A higher temperature gives a flatter distribution.
+        worker.compute_marginals(100.0).unwrap();
+        assert!(worker.token(1).marginal_prob().unwrap() < p1);
+
+        worker.reset_sentence("自然言語処理");
+        assert!(worker.marginal_prob(0..4, word_idx).is_none());
+    }
+
+    #[test]
+    fn test_write_lattice() {
+        let lexicon_csv = "自然,0,0,1,sizen
+言語,0,0,4,gengo
+処理,0,0,3,shori
+自然言語,0,0,6,sizengengo
+言語処理,0,0,5,gengoshori";
+        let matrix_def = "1 1\n0 0 0";
+        let char_def = "DEFAULT 0 1 0";
+        let unk_def = "DEFAULT,0,0,100,*";
+
+        let dict = build_test_dictionary(
+            lexicon_csv.as_bytes(),
+            matrix_def.as_bytes(),
+            char_def.as_bytes(),
+            unk_def.as_bytes(),
+        );
+
+        let tokenizer = Tokenizer::new(dict);
+        let mut worker = tokenizer.new_worker();
+        worker.reset_sentence("自然言語処理");
+        assert!(worker.write_lattice_dot(vec![]).is_err());
+
+        worker.tokenize();
+
+        // BOS, 自然, 自然言語, 言語, 言語処理, 処理, and EOS
+        let mut dot = vec![];
+        worker.write_lattice_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph lattice {\n"));
+        assert!(dot.contains("  n4 [label=\"言語処理\\ngengoshori\\nword_cost=5 total_cost=6\""));
+        assert_eq!(dot.matches(" -> ").count(), 8);
+        assert_eq!(dot.matches("color=red").count(), 7);
+
+        let mut json = vec![];
+        worker.write_lattice_json(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(
+            json.starts_with(
+                "{\"sentence\":\"自然言語処理\",\"nodes\":[{\"id\":0,\"kind\":\"bos\""
+            )
+        );
+        assert!(json.contains(
+            "{\"id\":5,\"kind\":\"word\",\"surface\":\"処理\",\"range_char\":[4,6],\
+             \"feature\":\"shori\",\"lex_type\":\"system\",\"word_id\":2,\"left_id\":0,\
+             \"right_id\":0,\"word_cost\":3,\"penalty\":0,\"total_cost\":8,\"best\":false}"
+        ));
+        assert!(json.contains("{\"from\":1,\"to\":4,\"cost\":0,\"best\":true}"));
+        assert!(json.contains("{\"from\":2,\"to\":5,\"cost\":0,\"best\":false}"));
+        assert!(json.ends_with("{\"from\":5,\"to\":6,\"cost\":0,\"best\":false}]}\n"));
+
+        worker.reset_sentence("");
+        let mut json = vec![];
+        worker.write_lattice_json(&mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "{\"sentence\":\"\",\"nodes\":[{\"id\":0,\"kind\":\"bos\",\"total_cost\":0,\"best\":true},\
+             {\"id\":1,\"kind\":\"eos\",\"total_cost\":0,\"best\":true}],\"edges\":[]}\n"
+        );
+    }
+
+    #[test]
+    fn test_explain() {
+        let lexicon_csv = "自然,0,0,1,sizen
+言語,0,0,4,gengo
+処理,0,0,3,shori
+自然言語,0,0,6,sizengengo
+言語処理,0,0,5,gengoshori";
+        let matrix_def = "1 1\n0 0 0";
+        let char_def = "DEFAULT 0 1 0";
+        let unk_def = "DEFAULT,0,0,100,*";
+
+        let dict = build_test_dictionary(
+            lexicon_csv.as_bytes(),
+            matrix_def.as_bytes(),
+            char_def.as_bytes(),
+            unk_def.as_bytes(),
+        );
+
+        let tokenizer = Tokenizer::new(dict);
+        let mut worker = tokenizer.new_worker();
+        worker.reset_sentence("自然言語処理");
+
+        let explanation = worker.explain_surfaces(&["自然言語", "処理"]).unwrap();
+        assert_eq!(explanation.best.total_cost, 6);
+        assert_eq!(explanation.alternative.total_cost, 9);
+        assert_eq!(explanation.cost_difference(), 3);
+
+        let words = &explanation.alternative.words;
+        assert_eq!(words.len(), 2);
+        assert_eq!(words[0].surface, "自然言語");
+        assert_eq!(words[0].feature, "sizengengo");
+        assert_eq!(words[0].range_char, 0..4);
+        assert_eq!(words[0].connection_cost, 0);
+        assert_eq!(words[0].word_cost, 6);
+        assert_eq!(words[1].surface, "処理");
+        assert_eq!(words[1].total_cost, 9);
+        assert_eq!(explanation.alternative.eos_connection_cost, 0);
+
+        // The best tokenization is stored.
+        assert_eq!(worker.num_tokens(), 2);
+        assert_eq!(worker.token(1).surface(), "言語処理");
+        let best = &explanation.best.words;
+        assert_eq!(best[1].total_cost, worker.token(1).total_cost());
+
+        let explanation = worker
+            .explain(&[(0..2, Some("sizen")), (2..4, None), (4..6, None)])
+            .unwrap();
+        assert_eq!(explanation.alternative.total_cost, 8);
+        assert!(
+            explanation
+                .to_string()
+                .contains("[alternative]\n自然\tsizen\t")
+        );
+
+        assert!(worker.explain_surfaces(&["自然", "言語"]).is_err());
+        assert!(worker.explain_surfaces(&["自然", "言語", "処置"]).is_err());
+        assert!(
+            worker
+                .explain(&[(0..2, None::<&str>), (3..6, None)])
+                .is_err()
+        );
+
+        // The unknown word spanning the whole sentence is given only to the alternative,
+        // and costs less than the best path.
+        let dict = build_test_dictionary(
+            lexicon_csv.as_bytes(),
+            matrix_def.as_bytes(),
+            char_def.as_bytes(),
+            "DEFAULT,0,0,1,*".as_bytes(),
+        );
+        let tokenizer = Tokenizer::new(dict);
+        let mut worker = tokenizer.new_worker();
+        worker.reset_sentence("自然言語処理");
+        let explanation = worker.explain_surfaces(&["自然言語処理"]).unwrap();
+        assert_eq!(explanation.alternative.words[0].lex_type, LexType::Unknown);
+        assert_eq!(explanation.best.total_cost, 6);
+        assert_eq!(explanation.alternative.total_cost, 1);
+        assert_eq!(explanation.cost_difference(), -5);
+    }
+
+    struct XorShift(u64);
+
+    impl rand_core::TryRng for XorShift {
+        type Error = std::convert::Infallible;
+
+        fn try_next_u32(&mut self) -> std::result::Result<u32, Self::Error> {
+            self.try_next_u64().map(|x| (x >> 32) as u32)
+        }
+        fn try_next_u64(&mut self) -> std::result::Result<u64, Self::Error> {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            Ok(self.0)
+        }
+        fn try_fill_bytes(&mut self, dst: &mut [u8]) -> std::result::Result<(), Self::Error> {
+            for b in dst {
+                *b = self.try_next_u64()? as u8;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sample() {
+        let lexicon_csv = "自然,0,0,1,sizen
+言語,0,0,4,gengo
+処理,0,0,3,shori
+自然言語,0,0,6,sizengengo
+言語処理,0,0,5,gengoshori";
+        let matrix_def = "1 1\n0 0 0";
+        let char_def = "DEFAULT 0 1 0";
+        let unk_def = "DEFAULT,0,0,100,*";
+
+        let dict = build_test_dictionary(
+            lexicon_csv.as_bytes(),
+            matrix_def.as_bytes(),
+            char_def.as_bytes(),
+            unk_def.as_bytes(),
+        );
+
+        let tokenizer = Tokenizer::new(dict);
+        let mut worker = tokenizer.new_worker();
+        let mut rng = XorShift(0x2545f4914f6cdd1d);
+        worker.reset_sentence("自然言語処理");
+        assert!(worker.sample(&mut rng, -1.0).is_err());
+
+        // Paths: 自然/言語処理 (6), 自然/言語/処理 (8), and 自然言語/処理 (9)
+        let mut counts = [0; 3];
+        for _ in 0..10000 {
+            worker.sample(&mut rng, 1.0).unwrap();
+            let surfaces: Vec<_> = worker.token_iter().map(|t| t.surface()).collect();
+            match surfaces.as_slice() {
+                ["自然", "言語処理"] => counts[0] += 1,
+                ["自然", "言語", "処理"] => counts[1] += 1,
+                ["自然言語", "処理"] => counts[2] += 1,
+                _ => panic!("unexpected tokenization: {surfaces:?}"),
+            }
+        }
+        let z = (-6f64).exp() + (-8f64).exp() + (-9f64).exp();
+        for (count, cost) in counts.into_iter().zip([6f64, 8., 9.]) {
+            let expected = (-cost).exp() / z;
+            assert!((f64::from(count) / 10000. - expected).abs() < 0.02);
+        }
+
+        // Tokenization after sampling outputs only the best path.
+        worker.tokenize();
+        let surfaces: Vec<_> = worker.token_iter().map(|t| t.surface()).collect();
+        assert_eq!(surfaces, ["自然", "言語処理"]);
+
+        worker.reset_sentence("");
+        worker.sample(&mut rng, 1.0).unwrap();
+        assert_eq!(worker.num_tokens(), 0);
+    }
+
+    #[test]
+    fn test_search_mode() {
+        let lexicon_csv = "自然,0,0,1,sizen
+言語,0,0,4,gengo
+処理,0,0,3,shori
+自然言語処理,0,0,6,sizengengoshori";
+        let matrix_def = "1 1\n0 0 0";
+        let char_def = "DEFAULT 0 1 0\nKANJI 0 0 2\n0x4E00..0x9FFF KANJI";
+        let unk_def = "DEFAULT,0,0,100,*\nKANJI,0,0,100,*";
+
+        let dict = build_test_dictionary(
+            lexicon_csv.as_bytes(),
+            matrix_def.as_bytes(),
+            char_def.as_bytes(),
+            unk_def.as_bytes(),
+        );
+        let tokenizer = Tokenizer::new(dict);
+        let ranges = |tokenizer: &Tokenizer, sentence: &str| {
+            let mut worker = tokenizer.new_worker();
+            worker.reset_sentence(sentence);
+            worker.tokenize();
+            worker
+                .token_iter()
+                .map(|t| (t.range_char(), t.total_cost()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(ranges(&tokenizer, "自然言語処理"), [(0..6, 6)]);
+
+        // The penalty of 自然言語処理 is (6 - 2) * 3 = 12.
+        let penalty = SearchPenalty::new().category("KANJI", 2, 3);
+        let tokenizer = tokenizer
+            .mode(TokenizeMode::Search(penalty.clone()))
+            .unwrap();
+        assert_eq!(
+            ranges(&tokenizer, "自然言語処理"),
+            [(0..2, 1), (2..4, 5), (4..6, 8)]
+        );
+        // The best of the N-best paths is the same as the 1-best path, including its cost.
+        let mut worker = tokenizer.new_worker();
+        worker.reset_sentence("自然言語処理");
+        worker.tokenize_nbest(3);
+        let nbest_ranges: Vec<_> = worker
+            .nbest_token_iter(0)
+            .unwrap()
+            .map(|t| t.range_char())
+            .collect();
+        assert_eq!(nbest_ranges, [0..2, 2..4, 4..6]);
+        assert_eq!(worker.path_cost(0), Some(8));
+        assert!(worker.path_cost(1).unwrap() >= 8);
+
+        // Explanations also include the penalties.
+        worker.reset_sentence("自然言語処理");
+        let explanation = worker.explain_surfaces(&["自然言語処理"]).unwrap();
+        assert_eq!(explanation.best.total_cost, 8);
+        assert_eq!(explanation.alternative.words[0].penalty, 12);
+        assert_eq!(explanation.alternative.total_cost, 18);
+        assert_eq!(explanation.cost_difference(), 10);
+        // Unknown words are also penalized, but words of other categories are not.
+        assert_eq!(ranges(&tokenizer, "自然abcdef"), [(0..2, 1), (2..8, 101)]);
+
+        // Words in user lexicons are never penalized, also in explanations.
+        let user_tokenizer = Tokenizer::new(build_test_dictionary(
+            lexicon_csv.as_bytes(),
+            matrix_def.as_bytes(),
+            char_def.as_bytes(),
+            unk_def.as_bytes(),
+        ))
+        .user_lexicon_from_reader("言語処理,0,0,2,user".as_bytes())
+        .unwrap()
+        .mode(TokenizeMode::Search(penalty.clone()))
+        .unwrap();
+        assert_eq!(
+            ranges(&user_tokenizer, "自然言語処理"),
+            [(0..2, 1), (2..6, 3)]
+        );
+        let mut worker = user_tokenizer.new_worker();
+        worker.reset_sentence("自然言語処理");
+        let explanation = worker.explain_surfaces(&["自然", "言語処理"]).unwrap();
+        assert_eq!(explanation.best.words[1].lex_type, LexType::User);
+        assert_eq!(explanation.best.words[1].penalty, 0);
+        let LatticeKind::For1Best(lattice) = &worker.lattice else {
+            unreachable!();
+        };
+        assert_eq!(explanation.best.total_cost, lattice.eos().unwrap().min_cost);
+
+        let tokenizer = tokenizer.mode(TokenizeMode::Extended(penalty)).unwrap();
+        assert_eq!(
+            ranges(&tokenizer, "処理自然言語処理"),
+            [(0..2, 3), (2..8, 9), (2..4, 4), (4..6, 8), (6..8, 11)]
+        );
+        // The best path of the search mode is explained, and the tokens of the both modes
+        // are stored after that.
+        let mut worker = tokenizer.new_worker();
+        worker.reset_sentence("処理自然言語処理");
+        let explanation = worker.explain_surfaces(&["処理", "自然言語処理"]).unwrap();
+        assert_eq!(explanation.best.words.len(), 4);
+        assert_eq!(explanation.best.total_cost, 11);
+        assert_eq!(explanation.cost_difference(), 10);
+        assert_eq!(worker.num_tokens(), 5);
+
+        // Tokens appended to preceding ones are never merged into them.
+        worker.reset_sentence("処理");
+        worker.tokenize();
+        assert_eq!(worker.num_tokens(), 1);
+        worker.tokenize_continued(BOS_EOS_CONNECTION_ID);
+        assert_eq!(worker.num_tokens(), 2);
+
+        let tokenizer = tokenizer.mode(TokenizeMode::Normal).unwrap();
+        assert_eq!(ranges(&tokenizer, "自然言語処理"), [(0..6, 6)]);
+
+        let penalty = SearchPenalty::new().category("KATAKANA", 2, 3);
+        assert!(tokenizer.mode(TokenizeMode::Search(penalty)).is_err());
+    }
 }
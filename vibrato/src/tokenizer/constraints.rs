@@ -0,0 +1,313 @@
+//! Constraints for partial tokenization.
+//!
+//! This module provides an equivalent of MeCab's partial parsing (`-p`).
+//! A [`Constraints`] instance describes positions where a token boundary must or
+//! must not exist, and spans that must be covered by a single token whose feature
+//! matches a given pattern. Nodes violating the constraints are not inserted into
+//! the lattice.
+use std::ops::Range;
+
+use crate::errors::{Result, VibratoError};
+use crate::utils;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum BoundaryState {
+    #[default]
+    Free,
+    Required,
+    Forbidden,
+}
+
+/// A span that must be covered by a single token.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct ForcedWord {
+    range: Range<usize>,
+    pattern: Option<Vec<String>>,
+}
+
+/// Set of constraints for partial tokenization.
+///
+/// All positions are specified in characters.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use vibrato_rkyv::{SystemDictionaryBuilder, Tokenizer};
+/// use vibrato_rkyv::tokenizer::constraints::Constraints;
+///
+/// let lexicon_csv = "京都,0,0,1,名詞,地名\n東京,0,0,1,名詞,地名\n東京都,0,0,1,名詞,地名\n都,0,0,3,名詞,一般";
+/// let dict = SystemDictionaryBuilder::from_readers(
+///     lexicon_csv.as_bytes(),
+///     "1 1\n0 0 0".as_bytes(),
+///     "DEFAULT 0 1 0".as_bytes(),
+///     "DEFAULT,0,0,100,*".as_bytes(),
+/// )?;
+///
+/// let tokenizer = Tokenizer::from_inner(dict);
+/// let mut worker = tokenizer.new_worker();
+///
+/// let mut constraints = Constraints::new();
+/// constraints.add_boundary(4);
+///
+/// worker.reset_sentence("京都東京都");
+/// worker.tokenize_with_constraints(&constraints)?;
+/// assert_eq!(worker.num_tokens(), 3);
+/// assert_eq!(worker.token(1).surface(), "東京");
+/// assert_eq!(worker.token(2).surface(), "都");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Constraints {
+    boundaries: Vec<usize>,
+    no_boundaries: Vec<Range<usize>>,
+    words: Vec<ForcedWord>,
+}
+
+impl Constraints {
+    /// Creates an empty set of constraints.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requires a token boundary at character position `pos`.
+    pub fn add_boundary(&mut self, pos: usize) -> &mut Self {
+        self.boundaries.push(pos);
+        self
+    }
+
+    /// Forbids token boundaries strictly inside `range`.
+    ///
+    /// For example, `3..7` forbids boundaries at positions 4, 5, and 6.
+    pub fn add_no_boundary(&mut self, range: Range<usize>) -> &mut Self {
+        self.no_boundaries.push(range);
+        self
+    }
+
+    /// Requires `range` to be a single token.
+    ///
+    /// If `pattern` is given, the feature of the token must match it.
+    /// A pattern is a CSV row compared field by field with the feature,
+    /// where `*` matches any value. Fields beyond the length of the pattern are ignored.
+    ///
+    /// If no word in the dictionary matches, an unknown word generated from the
+    /// character category of the first character is used instead.
+    /// The same applies to spans between required boundaries that no word fits.
+    pub fn add_word<S>(&mut self, range: Range<usize>, pattern: Option<S>) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.words.push(ForcedWord {
+            range,
+            pattern: pattern.map(|p| utils::parse_csv_row(p.as_ref())),
+        });
+        self
+    }
+
+    /// Checks if no constraint is specified.
+    pub fn is_empty(&self) -> bool {
+        self.boundaries.is_empty() && self.no_boundaries.is_empty() && self.words.is_empty()
+    }
+
+    /// Removes all the constraints.
+    pub fn clear(&mut self) {
+        self.boundaries.clear();
+        self.no_boundaries.clear();
+        self.words.clear();
+    }
+}
+
+/// Constraints resolved against a sentence of a specific length.
+#[derive(Default)]
+pub(crate) struct ConstraintTable {
+    states: Vec<BoundaryState>,
+    // The smallest position j >= i such that states[j] is Required.
+    next_required: Vec<usize>,
+    // Indices to `words` by starting positions.
+    word_starts: Vec<Option<usize>>,
+    words: Vec<ForcedWord>,
+}
+
+impl ConstraintTable {
+    /// Resolves `constraints` for a sentence of `len_char` characters.
+    pub fn reset(&mut self, constraints: &Constraints, len_char: usize) -> Result<()> {
+        self.states.clear();
+        self.states.resize(len_char + 1, BoundaryState::Free);
+        self.word_starts.clear();
+        self.word_starts.resize(len_char + 1, None);
+        self.words.clear();
+
+        self.states[0] = BoundaryState::Required;
+        self.states[len_char] = BoundaryState::Required;
+
+        for &pos in &constraints.boundaries {
+            if len_char < pos {
+                return Err(VibratoError::invalid_argument(
+                    "constraints",
+                    format!("boundary {pos} exceeds the sentence length {len_char}"),
+                ));
+            }
+            self.set_state(pos, BoundaryState::Required)?;
+        }
+        for range in &constraints.no_boundaries {
+            if range.start > range.end || len_char < range.end {
+                return Err(VibratoError::invalid_argument(
+                    "constraints",
+                    format!("invalid range {range:?} for the sentence length {len_char}"),
+                ));
+            }
+            for pos in range.start + 1..range.end {
+                self.set_state(pos, BoundaryState::Forbidden)?;
+            }
+        }
+        for word in &constraints.words {
+            let range = &word.range;
+            if range.start >= range.end || len_char < range.end {
+                return Err(VibratoError::invalid_argument(
+                    "constraints",
+                    format!("invalid word range {range:?} for the sentence length {len_char}"),
+                ));
+            }
+            if self.word_starts[range.start].is_some() {
+                return Err(VibratoError::invalid_argument(
+                    "constraints",
+                    format!("multiple words start at position {}", range.start),
+                ));
+            }
+            self.set_state(range.start, BoundaryState::Required)?;
+            self.set_state(range.end, BoundaryState::Required)?;
+            for pos in range.start + 1..range.end {
+                self.set_state(pos, BoundaryState::Forbidden)?;
+            }
+            self.word_starts[range.start] = Some(self.words.len());
+            self.words.push(word.clone());
+        }
+
+        self.next_required.clear();
+        self.next_required.resize(len_char + 1, len_char);
+        let mut next = len_char;
+        for pos in (0..=len_char).rev() {
+            if self.states[pos] == BoundaryState::Required {
+                next = pos;
+            }
+            self.next_required[pos] = next;
+        }
+        Ok(())
+    }
+
+    fn set_state(&mut self, pos: usize, state: BoundaryState) -> Result<()> {
+        let cur = &mut self.states[pos];
+        if *cur != BoundaryState::Free && *cur != state {
+            return Err(VibratoError::invalid_argument(
+                "constraints",
+                format!("conflicting constraints at position {pos}"),
+            ));
+        }
+        *cur = state;
+        Ok(())
+    }
+
+    /// Checks if a word at `start_char..end_char` is consistent with the boundary constraints.
+    ///
+    /// The feature constraint of a forced word is checked by [`Self::matches_feature()`].
+    #[inline(always)]
+    pub fn allows(&self, start_char: usize, end_char: usize) -> bool {
+        if self.states[start_char] == BoundaryState::Forbidden
+            || self.states[end_char] == BoundaryState::Forbidden
+        {
+            return false;
+        }
+        if self.next_required[start_char + 1] < end_char {
+            return false;
+        }
+        match self.word_starts[start_char] {
+            Some(i) => self.words[i].range.end == end_char,
+            None => true,
+        }
+    }
+
+    /// Checks if a word starting at `start_char` must satisfy a feature pattern.
+    #[inline(always)]
+    pub fn has_pattern(&self, start_char: usize) -> bool {
+        self.word_starts[start_char].is_some_and(|i| self.words[i].pattern.is_some())
+    }
+
+    /// Gets the nearest position after `start_char` where a boundary is required.
+    ///
+    /// For a forced word starting at `start_char`, this is its end position.
+    #[inline(always)]
+    pub fn next_boundary(&self, start_char: usize) -> usize {
+        self.next_required[start_char + 1]
+    }
+
+    /// Checks if `feature` matches the pattern of the forced word starting at `start_char`.
+    pub fn matches_feature(&self, start_char: usize, feature: &str) -> bool {
+        let Some(pattern) =
+            self.word_starts[start_char].and_then(|i| self.words[i].pattern.as_ref())
+        else {
+            return true;
+        };
+        let fields = utils::parse_csv_row(feature);
+        for (i, p) in pattern.iter().enumerate() {
+            if p != "*" && fields.get(i) != Some(p) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows() {
+        let mut constraints = Constraints::new();
+        constraints
+            .add_boundary(2)
+            .add_no_boundary(3..6)
+            .add_word(6..8, Some("名詞,*"));
+
+        let mut table = ConstraintTable::default();
+        table.reset(&constraints, 10).unwrap();
+
+        assert!(table.allows(0, 2));
+        assert!(!table.allows(0, 3));
+        assert!(table.allows(2, 3));
+        assert!(!table.allows(2, 4));
+        assert!(table.allows(3, 6));
+        assert!(!table.allows(3, 7));
+        assert!(table.allows(6, 8));
+        assert!(!table.allows(6, 7));
+        assert!(!table.allows(7, 8));
+        assert!(table.allows(8, 10));
+
+        assert_eq!(table.next_boundary(0), 2);
+        assert_eq!(table.next_boundary(3), 6);
+        assert_eq!(table.next_boundary(6), 8);
+
+        assert!(table.has_pattern(6));
+        assert!(table.matches_feature(6, "名詞,固有名詞,地名"));
+        assert!(!table.matches_feature(6, "動詞,一般"));
+    }
+
+    #[test]
+    fn test_conflict() {
+        let mut constraints = Constraints::new();
+        constraints.add_boundary(4).add_no_boundary(2..6);
+
+        let mut table = ConstraintTable::default();
+        assert!(table.reset(&constraints, 10).is_err());
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let mut constraints = Constraints::new();
+        constraints.add_word(8..12, None::<&str>);
+
+        let mut table = ConstraintTable::default();
+        assert!(table.reset(&constraints, 10).is_err());
+    }
+}
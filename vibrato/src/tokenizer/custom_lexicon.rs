@@ -0,0 +1,88 @@
+//! Custom lexicons participating in lattice construction.
+//!
+//! A custom lexicon provides words from an arbitrary lookup structure at runtime,
+//! such as a catalogue held in a hash map, without compiling a lexicon file.
+//! Its words are identified with [`LexType::Custom`](crate::dictionary::LexType::Custom)
+//! and the word ids given by the lexicon.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::collections::HashMap;
+//!
+//! use vibrato_rkyv::dictionary::{LexType, WordParam};
+//! use vibrato_rkyv::tokenizer::custom_lexicon::CustomLexicon;
+//! use vibrato_rkyv::{SystemDictionaryBuilder, Tokenizer};
+//!
+//! struct Catalogue {
+//!     ids: HashMap<String, u32>,
+//!     features: Vec<String>,
+//!     max_len: usize,
+//! }
+//!
+//! impl CustomLexicon for Catalogue {
+//!     fn common_prefix_search(&self, chars: &[char], f: &mut dyn FnMut(usize, u32)) {
+//!         let mut surface = String::new();
+//!         for (i, &c) in chars.iter().take(self.max_len).enumerate() {
+//!             surface.push(c);
+//!             if let Some(&word_id) = self.ids.get(&surface) {
+//!                 f(i + 1, word_id);
+//!             }
+//!         }
+//!     }
+//!
+//!     fn word_param(&self, _word_id: u32) -> WordParam {
+//!         WordParam::new(0, 0, -100)
+//!     }
+//!
+//!     fn word_feature(&self, word_id: u32) -> &str {
+//!         &self.features[word_id as usize]
+//!     }
+//! }
+//!
+//! let dict = SystemDictionaryBuilder::from_readers(
+//!     "東京,0,0,1,tokyo".as_bytes(),
+//!     "1 1\n0 0 0".as_bytes(),
+//!     "DEFAULT 0 1 0".as_bytes(),
+//!     "DEFAULT,0,0,100,*".as_bytes(),
+//! )?;
+//! let catalogue = Catalogue {
+//!     ids: HashMap::from([("ヴィブラート".to_string(), 0)]),
+//!     features: vec!["product,vibrato".to_string()],
+//!     max_len: 6,
+//! };
+//! let tokenizer = Tokenizer::from_inner(dict).custom_lexicon(catalogue);
+//!
+//! let mut worker = tokenizer.new_worker();
+//! worker.reset_sentence("東京ヴィブラート");
+//! worker.tokenize();
+//! let t = worker.token(1);
+//! assert_eq!(t.surface(), "ヴィブラート");
+//! assert_eq!(t.lex_type(), LexType::Custom);
+//! assert_eq!(t.feature(), "product,vibrato");
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::dictionary::WordParam;
+
+/// Source of words looked up at runtime in lattice construction.
+///
+/// Word ids must keep referring to the same words while tokens of them are in use,
+/// since the parameters and features of tokens are looked up with their ids.
+/// An implementation updated at runtime, e.g., with interior mutability,
+/// should therefore only append new words.
+pub trait CustomLexicon: Send + Sync {
+    /// Finds the words that are prefixes of `chars`, which is the suffix of a sentence
+    /// from a position, calling `f` with the length in characters and the id of each word.
+    ///
+    /// Words of length zero or exceeding `chars` are ignored.
+    fn common_prefix_search(&self, chars: &[char], f: &mut dyn FnMut(usize, u32));
+
+    /// Gets the parameter of the word.
+    ///
+    /// The connection ids are interpreted in the same way as ones in user lexicons.
+    /// Words with connection ids out of range of the dictionary are ignored.
+    fn word_param(&self, word_id: u32) -> WordParam;
+
+    /// Gets the feature string of the word.
+    fn word_feature(&self, word_id: u32) -> &str;
+}
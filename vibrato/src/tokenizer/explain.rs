@@ -0,0 +1,146 @@
+//! Cost breakdowns for explaining tokenization results.
+use std::fmt;
+use std::ops::Range;
+
+use crate::common::BOS_EOS_CONNECTION_ID;
+use crate::dictionary::LexType;
+use crate::dictionary::connector::ConnectorCost;
+use crate::tokenizer::worker::Worker;
+
+/// Costs related to a word in a path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WordBreakdown {
+    /// Surface string of the word.
+    pub surface: String,
+    /// Feature string of the word.
+    pub feature: String,
+    /// Position range of the word in characters.
+    pub range_char: Range<usize>,
+    /// Lexicon type where the word is from.
+    pub lex_type: LexType,
+    /// Left connection ID of the word.
+    pub left_id: u16,
+    /// Right connection ID of the word.
+    pub right_id: u16,
+    /// Connection cost from the previous word (or BOS) to this word.
+    pub connection_cost: i32,
+    /// Word cost of the word.
+    pub word_cost: i16,
+    /// Penalty of the word in the search mode, which is 0 in the normal mode.
+    pub penalty: i32,
+    /// Total cost from BOS to this word.
+    pub total_cost: i32,
+}
+
+/// Costs of a path from BOS to EOS.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathBreakdown {
+    /// Words in the path.
+    pub words: Vec<WordBreakdown>,
+    /// Connection cost from the last word (or BOS) to EOS.
+    pub eos_connection_cost: i32,
+    /// Total cost of the path.
+    pub total_cost: i32,
+}
+
+impl PathBreakdown {
+    /// Assembles the breakdown of the tokens stored in `worker`.
+    pub(crate) fn from_worker<C>(worker: &Worker, connector: &C) -> Self
+    where
+        C: ConnectorCost,
+    {
+        let mut words = Vec::with_capacity(worker.num_tokens());
+        let mut right_id = BOS_EOS_CONNECTION_ID;
+        let mut total_cost = 0;
+        for t in worker.token_iter() {
+            let connection_cost = connector.cost(right_id, t.left_id());
+            let word_cost = t.word_cost();
+            let range_char = t.range_char();
+            let penalty = worker.tokenizer.search_penalty_cost(
+                &worker.sent,
+                t.word_idx(),
+                range_char.start,
+                range_char.end,
+            );
+            total_cost += connection_cost + i32::from(word_cost) + penalty;
+            right_id = t.right_id();
+            words.push(WordBreakdown {
+                surface: t.surface().to_string(),
+                feature: t.feature().to_string(),
+                range_char,
+                lex_type: t.lex_type(),
+                left_id: t.left_id(),
+                right_id,
+                connection_cost,
+                word_cost,
+                penalty,
+                total_cost,
+            });
+        }
+        let eos_connection_cost = connector.cost(right_id, BOS_EOS_CONNECTION_ID);
+        Self {
+            words,
+            eos_connection_cost,
+            total_cost: total_cost + eos_connection_cost,
+        }
+    }
+}
+
+impl fmt::Display for PathBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut right_id = BOS_EOS_CONNECTION_ID;
+        for w in &self.words {
+            write!(
+                f,
+                "{}\t{}\tconnection({},{})={}\tword={}",
+                w.surface, w.feature, right_id, w.left_id, w.connection_cost, w.word_cost,
+            )?;
+            if w.penalty != 0 {
+                write!(f, "\tpenalty={}", w.penalty)?;
+            }
+            writeln!(f, "\ttotal={}", w.total_cost)?;
+            right_id = w.right_id;
+        }
+        writeln!(
+            f,
+            "EOS\tconnection({},{})={}\ttotal={}",
+            right_id, BOS_EOS_CONNECTION_ID, self.eos_connection_cost, self.total_cost,
+        )
+    }
+}
+
+/// Comparison of the best path and an alternative segmentation.
+///
+/// In the search mode, the costs include the penalties of the words, and the best path
+/// is that of the search mode even in
+/// [`TokenizeMode::Extended`](crate::tokenizer::search::TokenizeMode::Extended).
+///
+/// It is created by [`Worker::explain()`] and [`Worker::explain_surfaces()`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Explanation {
+    /// Breakdown of the best path found by the Viterbi search.
+    pub best: PathBreakdown,
+    /// Breakdown of the best path among those following the alternative segmentation.
+    pub alternative: PathBreakdown,
+}
+
+impl Explanation {
+    /// Gets how much the alternative costs more than the best path.
+    ///
+    /// The value is negative if the alternative includes an unknown word given only to
+    /// satisfy the segmentation where no other candidate fits it, which is absent from
+    /// the lattice of the best path and can cost less than the words there.
+    pub const fn cost_difference(&self) -> i32 {
+        self.alternative.total_cost - self.best.total_cost
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[best]")?;
+        write!(f, "{}", self.best)?;
+        writeln!(f, "[alternative]")?;
+        write!(f, "{}", self.alternative)?;
+        writeln!(f, "difference={}", self.cost_difference())
+    }
+}
@@ -0,0 +1,318 @@
+//! Exporters of the lattice for debugging.
+use std::io::Write;
+
+use crate::dictionary::LexType;
+use crate::dictionary::connector::ConnectorCost;
+use crate::errors::Result;
+use crate::sentence::Sentence;
+use crate::tokenizer::Tokenizer;
+use crate::tokenizer::lattice::{Lattice, Node};
+
+use crate::common::BOS_EOS_CONNECTION_ID;
+
+/// A node in the exported graph.
+struct GraphNode<'a> {
+    surface: &'a str,
+    start_char: usize,
+    end_char: usize,
+    node: &'a Node,
+    feature: &'a str,
+    word_cost: i16,
+    penalty: i32,
+}
+
+/// An edge in the exported graph.
+struct GraphEdge {
+    from: usize,
+    to: usize,
+    cost: i32,
+    best: bool,
+}
+
+/// Graph representation of the lattice.
+///
+/// Node 0 is BOS, and the last node is EOS.
+/// Word nodes are numbered in the order of their end positions.
+struct Graph<'a> {
+    nodes: Vec<Option<GraphNode<'a>>>,
+    best: Vec<bool>,
+    edges: Vec<GraphEdge>,
+    eos_cost: i32,
+}
+
+impl<'a> Graph<'a> {
+    fn new<C>(
+        sent: &'a Sentence,
+        lattice: Option<&'a Lattice>,
+        tokenizer: &'a Tokenizer,
+        connector: &C,
+    ) -> Self
+    where
+        C: ConnectorCost,
+    {
+        let mut graph = Self {
+            nodes: vec![None],
+            best: vec![true],
+            edges: vec![],
+            eos_cost: 0,
+        };
+        let Some(lattice) = lattice else {
+            graph.nodes.push(None);
+            graph.best.push(true);
+            return graph;
+        };
+
+        // offsets[i] is the graph id of the first node ending at i.
+        let mut offsets = Vec::with_capacity(lattice.len_char() + 1);
+        offsets.push(0);
+        for end_char in 1..=lattice.len_char() {
+            offsets.push(graph.nodes.len());
+            for node in lattice.nodes(end_char) {
+                let word_idx = node.word_idx();
+                let byte_range = sent.byte_position(node.start_word)..sent.byte_position(end_char);
+                graph.nodes.push(Some(GraphNode {
+                    surface: &sent.raw()[byte_range],
+                    start_char: node.start_word,
+                    end_char,
+                    node,
+                    feature: tokenizer.word_feature(word_idx),
+                    word_cost: tokenizer.word_param(word_idx).word_cost,
+                    penalty: tokenizer.search_penalty_cost(
+                        sent,
+                        word_idx,
+                        node.start_word,
+                        end_char,
+                    ),
+                }));
+                graph.best.push(false);
+            }
+        }
+        let eos_id = graph.nodes.len();
+        graph.nodes.push(None);
+        graph.best.push(true);
+
+        // Marks the best path.
+        let eos = lattice.eos().unwrap();
+        graph.eos_cost = eos.min_cost;
+        let (mut end_node, mut min_idx) = (eos.start_node, eos.min_idx);
+        while end_node != 0 {
+            graph.best[offsets[end_node] + usize::from(min_idx)] = true;
+            let node = &lattice.nodes(end_node)[usize::from(min_idx)];
+            (end_node, min_idx) = (node.start_node, node.min_idx);
+        }
+
+        for end_char in 1..=lattice.len_char() {
+            for (i, node) in lattice.nodes(end_char).iter().enumerate() {
+                let to = offsets[end_char] + i;
+                for (j, left_node) in lattice.nodes(node.start_node).iter().enumerate() {
+                    let from = offsets[node.start_node] + j;
+                    graph.edges.push(GraphEdge {
+                        from,
+                        to,
+                        cost: connector.cost(left_node.right_id, node.left_id),
+                        best: graph.best[to] && j == usize::from(node.min_idx),
+                    });
+                }
+            }
+        }
+        for (j, left_node) in lattice.nodes(eos.start_node).iter().enumerate() {
+            graph.edges.push(GraphEdge {
+                from: offsets[eos.start_node] + j,
+                to: eos_id,
+                cost: connector.cost(left_node.right_id, BOS_EOS_CONNECTION_ID),
+                best: j == usize::from(eos.min_idx),
+            });
+        }
+        graph
+    }
+}
+
+const fn lex_type_name(lex_type: LexType) -> &'static str {
+    match lex_type {
+        LexType::System => "system",
+        LexType::User => "user",
+        LexType::Unknown => "unknown",
+        LexType::Custom => "custom",
+    }
+}
+
+/// Writes the lattice in the Graphviz DOT format.
+///
+/// If `lattice` is `None`, only BOS and EOS are written.
+pub fn write_dot<W, C>(
+    mut wtr: W,
+    sent: &Sentence,
+    lattice: Option<&Lattice>,
+    tokenizer: &Tokenizer,
+    connector: &C,
+) -> Result<()>
+where
+    W: Write,
+    C: ConnectorCost,
+{
+    let graph = Graph::new(sent, lattice, tokenizer, connector);
+
+    writeln!(wtr, "digraph lattice {{")?;
+    writeln!(wtr, "  rankdir=LR;")?;
+    writeln!(wtr, "  node [shape=box];")?;
+    for (id, node) in graph.nodes.iter().enumerate() {
+        let label = match node {
+            Some(n) if n.penalty != 0 => format!(
+                "{}\\n{}\\nword_cost={} penalty={} total_cost={}",
+                escape_dot(n.surface),
+                escape_dot(n.feature),
+                n.word_cost,
+                n.penalty,
+                n.node.min_cost,
+            ),
+            Some(n) => format!(
+                "{}\\n{}\\nword_cost={} total_cost={}",
+                escape_dot(n.surface),
+                escape_dot(n.feature),
+                n.word_cost,
+                n.node.min_cost,
+            ),
+            None if id == 0 => "BOS".to_string(),
+            None => format!("EOS\\ntotal_cost={}", graph.eos_cost),
+        };
+        let style = if graph.best[id] {
+            ", color=red, penwidth=2"
+        } else {
+            ""
+        };
+        writeln!(wtr, "  n{id} [label=\"{label}\"{style}];")?;
+    }
+    for edge in &graph.edges {
+        let style = if edge.best {
+            ", color=red, penwidth=2"
+        } else {
+            ""
+        };
+        writeln!(
+            wtr,
+            "  n{} -> n{} [label=\"{}\"{style}];",
+            edge.from, edge.to, edge.cost
+        )?;
+    }
+    writeln!(wtr, "}}")?;
+    Ok(())
+}
+
+/// Writes the lattice as a JSON object in a line.
+///
+/// If `lattice` is `None`, only BOS and EOS are written.
+pub fn write_json<W, C>(
+    mut wtr: W,
+    sent: &Sentence,
+    lattice: Option<&Lattice>,
+    tokenizer: &Tokenizer,
+    connector: &C,
+) -> Result<()>
+where
+    W: Write,
+    C: ConnectorCost,
+{
+    let graph = Graph::new(sent, lattice, tokenizer, connector);
+
+    write!(
+        wtr,
+        "{{\"sentence\":\"{}\",\"nodes\":[",
+        escape_json(sent.raw())
+    )?;
+    for (id, node) in graph.nodes.iter().enumerate() {
+        if id != 0 {
+            write!(wtr, ",")?;
+        }
+        match node {
+            Some(n) => {
+                let word_idx = n.node.word_idx();
+                write!(
+                    wtr,
+                    "{{\"id\":{id},\"kind\":\"word\",\"surface\":\"{}\",\"range_char\":[{},{}],\
+                     \"feature\":\"{}\",\"lex_type\":\"{}\",\"word_id\":{},\"left_id\":{},\
+                     \"right_id\":{},\"word_cost\":{},\"penalty\":{},\"total_cost\":{},\"best\":{}}}",
+                    escape_json(n.surface),
+                    n.start_char,
+                    n.end_char,
+                    escape_json(n.feature),
+                    lex_type_name(word_idx.lex_type),
+                    word_idx.word_id,
+                    n.node.left_id,
+                    n.node.right_id,
+                    n.word_cost,
+                    n.penalty,
+                    n.node.min_cost,
+                    graph.best[id],
+                )?;
+            }
+            None if id == 0 => {
+                write!(
+                    wtr,
+                    "{{\"id\":0,\"kind\":\"bos\",\"total_cost\":0,\"best\":true}}"
+                )?;
+            }
+            None => {
+                write!(
+                    wtr,
+                    "{{\"id\":{id},\"kind\":\"eos\",\"total_cost\":{},\"best\":true}}",
+                    graph.eos_cost
+                )?;
+            }
+        }
+    }
+    write!(wtr, "],\"edges\":[")?;
+    for (i, edge) in graph.edges.iter().enumerate() {
+        if i != 0 {
+            write!(wtr, ",")?;
+        }
+        write!(
+            wtr,
+            "{{\"from\":{},\"to\":{},\"cost\":{},\"best\":{}}}",
+            edge.from, edge.to, edge.cost, edge.best
+        )?;
+    }
+    writeln!(wtr, "]}}")?;
+    Ok(())
+}
+
+fn escape_dot(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if u32::from(c) < 0x20 => escaped.push_str(&format!("\\u{:04x}", u32::from(c))),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape_dot("a\"b\\c"), "a\\\"b\\\\c");
+        assert_eq!(escape_json("a\"b\\c\n\u{1}"), "a\\\"b\\\\c\\n\\u0001");
+    }
+}
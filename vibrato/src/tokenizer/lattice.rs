@@ -8,6 +8,8 @@ use crate::common::{BOS_EOS_CONNECTION_ID, MAX_SENTENCE_LENGTH};
 
 const MAX_COST: i32 = i32::MAX;
 const INVALID_IDX: u16 = u16::MAX;
+/// Index representing the end of a linked list of paths.
+pub const INVALID_PATH: u32 = u32::MAX;
 
 /// A node in the lattice.
 #[derive(Debug, Clone, Copy)]
@@ -21,18 +23,19 @@ pub struct Node {
     pub right_id: u16,
     pub min_idx: u16,
     pub min_cost: i32,
-    /// A raw pointer to the head of the linked list of paths connecting from the left.
-    pub lpath: *const Path, // null if no path
+    /// The index of the head of the linked list of paths connecting from the left,
+    /// which is only used in [`LatticeNBest`].
+    pub lpath: u32, // INVALID_PATH if no path
 }
 
 /// Represents a connection between two nodes in the lattice.
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct Path {
-    /// A raw pointer to the left node (closer to BOS).
-    pub lnode: *const Node,
-    /// The next path originating from the right node (linked list).
-    pub lnext: *const Path,
+    /// The index of the left node (closer to BOS).
+    pub lnode: u32,
+    /// The index of the next path originating from the right node (linked list).
+    pub lnext: u32,
 }
 
 impl Default for Node {
@@ -46,7 +49,7 @@ impl Default for Node {
             right_id: 0,
             min_idx: 0,
             min_cost: i32::MAX,
-            lpath: std::ptr::null(),
+            lpath: INVALID_PATH,
         }
     }
 }
@@ -81,6 +84,7 @@ pub struct Lattice {
     ends: Vec<Vec<Node>>,
     eos: Option<Node>,
     len_char: usize, // needed for avoiding to free ends
+    bos_right_id: u16,
 }
 
 impl LatticeKind {
@@ -88,6 +92,7 @@ impl LatticeKind {
     pub fn prepare_for_1best(&mut self, len_char: usize) -> &mut Lattice {
         match self {
             LatticeKind::For1Best(l) => {
+                l.bos_right_id = BOS_EOS_CONNECTION_ID;
                 l.reset(len_char);
                 l
             }
@@ -147,13 +152,19 @@ impl Lattice {
             start_node: MAX_SENTENCE_LENGTH,
             start_word: MAX_SENTENCE_LENGTH,
             left_id: u16::MAX,
-            right_id: BOS_EOS_CONNECTION_ID,
+            right_id: self.bos_right_id,
             min_idx: INVALID_IDX,
             min_cost: 0,
-            lpath: std::ptr::null(),
+            lpath: INVALID_PATH,
         });
     }
 
+    /// Sets the right connection id of BOS used from the next reset, so that
+    /// the sentence is tokenized as a continuation of a node with the id.
+    pub fn set_bos_right_id(&mut self, right_id: u16) {
+        self.bos_right_id = right_id;
+    }
+
     pub fn insert_eos<C>(&mut self, start_node: usize, connector: &C)
     where
         C: ConnectorCost,
@@ -169,10 +180,11 @@ impl Lattice {
             right_id: u16::MAX,
             min_idx,
             min_cost,
-            lpath: std::ptr::null(),
+            lpath: INVALID_PATH,
         });
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn insert_node<C>(
         &mut self,
         start_node: usize,
@@ -180,6 +192,7 @@ impl Lattice {
         end_word: usize,
         word_idx: WordIdx,
         word_param: WordParam,
+        penalty: i32,
         connector: &C,
     ) where
         C: ConnectorCost,
@@ -195,8 +208,8 @@ impl Lattice {
             left_id: word_param.left_id,
             right_id: word_param.right_id,
             min_idx,
-            min_cost: min_cost + i32::from(word_param.word_cost),
-            lpath: std::ptr::null(),
+            min_cost: min_cost + i32::from(word_param.word_cost) + penalty,
+            lpath: INVALID_PATH,
         });
     }
 
@@ -230,6 +243,36 @@ impl Lattice {
         self.ends.get(i).map(|d| !d.is_empty()).unwrap_or(false)
     }
 
+    /// Gets the nodes ending at position `end_char`.
+    #[inline(always)]
+    pub fn nodes(&self, end_char: usize) -> &[Node] {
+        &self.ends[end_char]
+    }
+
+    #[inline(always)]
+    pub const fn eos(&self) -> Option<&Node> {
+        self.eos.as_ref()
+    }
+
+    /// Gets the word cost of `node` in the lattice.
+    ///
+    /// It is recovered from the difference of the minimum costs, so the value is
+    /// identical to the one used in the Viterbi search.
+    #[inline(always)]
+    pub fn word_cost<C>(&self, node: &Node, connector: &C) -> i32
+    where
+        C: ConnectorCost,
+    {
+        let left_node = &self.ends[node.start_node][usize::from(node.min_idx)];
+        node.min_cost - left_node.min_cost - connector.cost(left_node.right_id, node.left_id)
+    }
+
+    /// Checks if EOS has been inserted, i.e., the end of the sentence is reachable.
+    #[inline(always)]
+    pub const fn has_eos(&self) -> bool {
+        self.eos.is_some()
+    }
+
     pub fn append_top_nodes(&self, top_nodes: &mut Vec<(usize, Node)>) {
         let eos = self.eos.as_ref().unwrap();
         let mut end_node = eos.start_node;
@@ -241,6 +284,30 @@ impl Lattice {
         }
     }
 
+    /// Marks the positions that every path passes through with the same node,
+    /// i.e., only one node ends at the position and no node crosses it.
+    pub fn mark_sync_points(&self, marks: &mut Vec<bool>) {
+        let len_char = self.len_char();
+        marks.clear();
+        marks.resize(len_char + 1, false);
+
+        // Differences of the numbers of nodes crossing the positions.
+        let mut crossings = vec![0i32; len_char + 1];
+        for end_char in 1..=len_char {
+            for node in &self.ends[end_char] {
+                if node.start_node + 1 < end_char {
+                    crossings[node.start_node + 1] += 1;
+                    crossings[end_char] -= 1;
+                }
+            }
+        }
+        let mut num_crossings = 0;
+        for pos in 1..len_char {
+            num_crossings += crossings[pos];
+            marks[pos] = num_crossings == 0 && self.ends[pos].len() == 1;
+        }
+    }
+
     pub fn add_connid_counts(&self, counter: &mut ConnIdCounter) {
         for end_char in 1..=self.len_char() {
             for r_node in &self.ends[end_char] {
@@ -258,17 +325,21 @@ impl Lattice {
 }
 
 /// This implementation inspired by sudachi.rs.
+///
+/// Nodes and paths are stored in arenas and linked by their indices.
 #[derive(Default)]
 pub struct LatticeNBest {
-    arena: bumpalo::Bump,
-    ends: Vec<Vec<*mut Node>>,
-    eos: *mut Node,
+    nodes: Vec<Node>,
+    paths: Vec<Path>,
+    ends: Vec<Vec<u32>>,
+    eos: Option<u32>,
     len_char: usize, // needed for avoiding to free ends
 }
 
 impl LatticeNBest {
     pub fn reset(&mut self, len_char: usize) {
-        self.arena.reset();
+        self.nodes.clear();
+        self.paths.clear();
 
         let new_len = len_char + 1;
 
@@ -284,15 +355,33 @@ impl LatticeNBest {
             }
         }
 
-        self.eos = std::ptr::null_mut();
+        self.eos = None;
         self.len_char = len_char;
         self.insert_bos();
     }
 
+    /// Gets the node at the index.
+    #[inline(always)]
+    pub fn node(&self, node_idx: u32) -> &Node {
+        &self.nodes[node_idx as usize]
+    }
+
+    /// Gets the path at the index.
+    #[inline(always)]
+    pub fn path(&self, path_idx: u32) -> &Path {
+        &self.paths[path_idx as usize]
+    }
+
+    /// Gets the index of the EOS node.
+    #[inline(always)]
+    pub const fn eos_idx(&self) -> Option<u32> {
+        self.eos
+    }
+
     /// Gets the EOS node.
     #[inline(always)]
     pub fn eos_node(&self) -> Option<&Node> {
-        unsafe { self.eos.as_ref() }
+        self.eos.map(|eos| self.node(eos))
     }
 
     /// Returns the number of characters of the set sentence.
@@ -301,8 +390,50 @@ impl LatticeNBest {
         self.len_char
     }
 
+    #[inline(always)]
+    fn alloc_node(&mut self, node: Node) -> u32 {
+        let node_idx = self.nodes.len() as u32;
+        self.nodes.push(node);
+        node_idx
+    }
+
+    /// Allocates paths from the nodes ending at `start_node` to the right node,
+    /// returning the head of the linked list and the index of the best left node.
+    fn alloc_paths<C>(&mut self, start_node: usize, left_id: u16, connector: &C) -> (u32, u16, i32)
+    where
+        C: ConnectorCost,
+    {
+        let mut lpath = INVALID_PATH;
+        let mut min_cost = MAX_COST;
+        let mut min_idx = INVALID_IDX;
+
+        for (i, &lnode_idx) in self.ends[start_node].iter().enumerate() {
+            let lnode = &self.nodes[lnode_idx as usize];
+            if !lnode.is_connected_to_bos() {
+                continue;
+            }
+
+            let conn_cost = connector.cost(lnode.right_id, left_id);
+            let new_cost = lnode.min_cost.saturating_add(conn_cost);
+            // Depending on the order of tie-breaking, the result can be different from MeCab.
+            // Using <= (not <) will produce results identical to MeCab in most case (empirically).
+            if new_cost <= min_cost {
+                min_cost = new_cost;
+                min_idx = i as u16;
+            }
+
+            let path_idx = self.paths.len() as u32;
+            self.paths.push(Path {
+                lnode: lnode_idx,
+                lnext: lpath,
+            });
+            lpath = path_idx;
+        }
+        (lpath, min_idx, min_cost)
+    }
+
     fn insert_bos(&mut self) {
-        let bos_node = self.arena.alloc(Node {
+        let bos_idx = self.alloc_node(Node {
             word_id: u32::MAX,
             lex_type: LexType::default(),
             start_node: MAX_SENTENCE_LENGTH,
@@ -311,44 +442,29 @@ impl LatticeNBest {
             right_id: BOS_EOS_CONNECTION_ID,
             min_idx: INVALID_IDX,
             min_cost: 0,
-            lpath: std::ptr::null(),
+            lpath: INVALID_PATH,
         });
-        self.ends[0].push(bos_node);
+        self.ends[0].push(bos_idx);
     }
 
     pub fn insert_eos<C: ConnectorCost>(&mut self, start_node: usize, connector: &C) {
-        let eos_node = self.arena.alloc(Node {
+        let (lpath, min_idx, min_cost) =
+            self.alloc_paths(start_node, BOS_EOS_CONNECTION_ID, connector);
+        let eos_idx = self.alloc_node(Node {
             word_id: u32::MAX,
             lex_type: LexType::default(),
             start_node,
             start_word: self.len_char(),
             left_id: BOS_EOS_CONNECTION_ID,
             right_id: u16::MAX,
-            ..Default::default()
+            min_idx,
+            min_cost,
+            lpath,
         });
-
-        let mut min_cost = MAX_COST;
-        eos_node.lpath = std::ptr::null();
-
-        for (i, &lnode_ptr) in self.ends[start_node].iter().enumerate() {
-            let lnode = unsafe { &*lnode_ptr };
-            let conn_cost = connector.cost(lnode.right_id, BOS_EOS_CONNECTION_ID);
-            let new_cost = lnode.min_cost + conn_cost;
-
-            if new_cost <= min_cost {
-                min_cost = new_cost;
-                eos_node.min_idx = i as u16;
-            }
-            let new_path = self.arena.alloc(Path {
-                lnode: lnode_ptr,
-                lnext: eos_node.lpath,
-            });
-            eos_node.lpath = new_path;
-        }
-        eos_node.min_cost = min_cost;
-        self.eos = eos_node;
+        self.eos = Some(eos_idx);
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn insert_node<C>(
         &mut self,
         start_node_pos: usize,
@@ -356,6 +472,7 @@ impl LatticeNBest {
         end_word: usize,
         word_idx: WordIdx,
         word_param: WordParam,
+        penalty: i32,
         connector: &C,
     ) where
         C: ConnectorCost,
@@ -363,49 +480,29 @@ impl LatticeNBest {
         debug_assert!(start_node_pos <= start_word);
         debug_assert!(start_word < end_word);
 
-        let rnode_ptr = self.arena.alloc(Node {
+        let num_paths = self.paths.len();
+        let (lpath, min_idx, min_cost) =
+            self.alloc_paths(start_node_pos, word_param.left_id, connector);
+
+        if min_idx == INVALID_IDX {
+            // The node is unreachable, so the paths are never referred.
+            self.paths.truncate(num_paths);
+            return;
+        }
+        let rnode_idx = self.alloc_node(Node {
             word_id: word_idx.word_id,
             lex_type: word_idx.lex_type,
             start_node: start_node_pos,
             start_word,
             left_id: word_param.left_id,
             right_id: word_param.right_id,
-            ..Default::default()
+            min_idx,
+            min_cost: min_cost
+                .saturating_add(i32::from(word_param.word_cost))
+                .saturating_add(penalty),
+            lpath,
         });
-        let rnode = &mut *rnode_ptr;
-
-        let mut min_cost = MAX_COST;
-        let mut min_idx = INVALID_IDX;
-
-        rnode.lpath = std::ptr::null();
-
-        for (i, &lnode_ptr) in self.ends[start_node_pos].iter().enumerate() {
-            let lnode = unsafe { &*lnode_ptr };
-            if !lnode.is_connected_to_bos() {
-                continue;
-            }
-
-            let conn_cost = connector.cost(lnode.right_id, rnode.left_id);
-            let new_cost = lnode.min_cost.saturating_add(conn_cost);
-            // Depending on the order of tie-breaking, the result can be different from MeCab.
-            // Using <= (not <) will produce results identical to MeCab in most case (empirically).
-            if new_cost <= min_cost {
-                min_cost = new_cost;
-                min_idx = i as u16;
-            }
-
-            let new_path = self.arena.alloc(Path {
-                lnode: lnode_ptr,
-                lnext: rnode.lpath,
-            });
-            rnode.lpath = new_path;
-        }
-
-        if min_idx != INVALID_IDX {
-            rnode.min_idx = min_idx;
-            rnode.min_cost = min_cost.saturating_add(i32::from(word_param.word_cost));
-            self.ends[end_word].push(rnode_ptr);
-        }
+        self.ends[end_word].push(rnode_idx);
     }
 
     /// Checks if there exist at least one at the word end boundary
@@ -416,24 +513,23 @@ impl LatticeNBest {
 
     pub fn add_connid_counts(&self, counter: &mut ConnIdCounter) {
         for end_char in 1..=self.len_char() {
-            for &r_node_ptr in &self.ends[end_char] {
-                let r_node = unsafe { &*r_node_ptr };
+            for &r_node_idx in &self.ends[end_char] {
+                let r_node = self.node(r_node_idx);
                 let start_node = r_node.start_node;
 
-                for &l_node_ptr in &self.ends[start_node] {
-                    let l_node = unsafe { &*l_node_ptr };
+                for &l_node_idx in &self.ends[start_node] {
+                    let l_node = self.node(l_node_idx);
                     counter.add(r_node.left_id, l_node.right_id, 1);
                 }
             }
         }
 
-        if !self.eos.is_null() {
-            let r_node = unsafe { &*self.eos };
-            if let Some(last_nodes) = self.ends.get(r_node.start_node) {
-                for &l_node_ptr in last_nodes {
-                    let l_node = unsafe { &*l_node_ptr };
-                    counter.add(r_node.left_id, l_node.right_id, 1);
-                }
+        if let Some(r_node) = self.eos_node()
+            && let Some(last_nodes) = self.ends.get(r_node.start_node)
+        {
+            for &l_node_idx in last_nodes {
+                let l_node = self.node(l_node_idx);
+                counter.add(r_node.left_id, l_node.right_id, 1);
             }
         }
     }
@@ -0,0 +1,263 @@
+//! Marginal probabilities of lattice nodes.
+//!
+//! The probability of a path is defined as `exp(-cost / temperature) / Z`,
+//! and the marginal probability of a node is the sum of the probabilities of
+//! the paths passing through it, computed with the forward-backward algorithm.
+use std::ops::Range;
+
+use rand_core::Rng;
+
+use crate::dictionary::connector::ConnectorCost;
+use crate::dictionary::word_idx::WordIdx;
+use crate::tokenizer::lattice::{Lattice, Node};
+
+use crate::common::BOS_EOS_CONNECTION_ID;
+
+/// Forward and backward log-potentials of the nodes in [`Lattice`].
+///
+/// The values are stored in the same layout as the nodes of the lattice,
+/// i.e., indexed by the end position and the index in the position.
+#[derive(Default)]
+pub(crate) struct Marginals {
+    alpha: Vec<Vec<f64>>,
+    beta: Vec<Vec<f64>>,
+    log_z: f64,
+    computed: bool,
+    // Buffer for sampling
+    weights: Vec<f64>,
+}
+
+impl Marginals {
+    /// Invalidates the computed values.
+    #[inline(always)]
+    pub fn clear(&mut self) {
+        self.computed = false;
+    }
+
+    /// Runs the forward-backward algorithm over `lattice`.
+    ///
+    /// `lattice` must be the one whose EOS has been inserted.
+    pub fn compute<C>(&mut self, lattice: &Lattice, connector: &C, temperature: f64)
+    where
+        C: ConnectorCost,
+    {
+        self.forward(lattice, connector, temperature);
+        self.backward(lattice, connector, temperature);
+        self.computed = true;
+    }
+
+    fn forward<C>(&mut self, lattice: &Lattice, connector: &C, temperature: f64)
+    where
+        C: ConnectorCost,
+    {
+        debug_assert!(temperature > 0.0);
+        self.computed = false;
+        Self::reset_vec(&mut self.alpha, lattice, f64::NEG_INFINITY);
+
+        self.alpha[0][0] = 0.0;
+        for end_char in 1..=lattice.len_char() {
+            for (i, node) in lattice.nodes(end_char).iter().enumerate() {
+                let start_node = node.start_node;
+                let mut alpha = f64::NEG_INFINITY;
+                for (left_node, &left_alpha) in lattice
+                    .nodes(start_node)
+                    .iter()
+                    .zip(&self.alpha[start_node])
+                {
+                    let conn_cost = connector.cost(left_node.right_id, node.left_id);
+                    alpha = log_add_exp(alpha, left_alpha - f64::from(conn_cost) / temperature);
+                }
+                let word_cost = lattice.word_cost(node, connector);
+                self.alpha[end_char][i] = alpha - f64::from(word_cost) / temperature;
+            }
+        }
+    }
+
+    fn backward<C>(&mut self, lattice: &Lattice, connector: &C, temperature: f64)
+    where
+        C: ConnectorCost,
+    {
+        let eos = lattice.eos().unwrap();
+        Self::reset_vec(&mut self.beta, lattice, f64::NEG_INFINITY);
+
+        let mut log_z = f64::NEG_INFINITY;
+        for (i, left_node) in lattice.nodes(eos.start_node).iter().enumerate() {
+            let conn_cost = connector.cost(left_node.right_id, BOS_EOS_CONNECTION_ID);
+            let beta = -f64::from(conn_cost) / temperature;
+            self.beta[eos.start_node][i] = beta;
+            log_z = log_add_exp(log_z, self.alpha[eos.start_node][i] + beta);
+        }
+        for end_char in (1..=lattice.len_char()).rev() {
+            for (i, node) in lattice.nodes(end_char).iter().enumerate() {
+                let beta = self.beta[end_char][i];
+                if beta == f64::NEG_INFINITY {
+                    continue;
+                }
+                let word_cost = lattice.word_cost(node, connector);
+                let beta = beta - f64::from(word_cost) / temperature;
+                let start_node = node.start_node;
+                for (j, left_node) in lattice.nodes(start_node).iter().enumerate() {
+                    let conn_cost = connector.cost(left_node.right_id, node.left_id);
+                    let left_beta = &mut self.beta[start_node][j];
+                    *left_beta = log_add_exp(*left_beta, beta - f64::from(conn_cost) / temperature);
+                }
+            }
+        }
+        self.log_z = log_z;
+    }
+
+    /// Samples a path from the distribution over `lattice` with forward-filtering
+    /// backward-sampling, appending the nodes to `top_nodes` in the same manner as
+    /// [`Lattice::append_top_nodes()`].
+    ///
+    /// `lattice` must be the one whose EOS has been inserted.
+    pub fn sample<C, R>(
+        &mut self,
+        lattice: &Lattice,
+        connector: &C,
+        temperature: f64,
+        rng: &mut R,
+        top_nodes: &mut Vec<(usize, Node)>,
+    ) where
+        C: ConnectorCost,
+        R: Rng + ?Sized,
+    {
+        self.forward(lattice, connector, temperature);
+
+        let eos = lattice.eos().unwrap();
+        let mut end_node = eos.start_node;
+        let mut left_id = eos.left_id;
+        while end_node != 0 {
+            let nodes = lattice.nodes(end_node);
+            self.weights.clear();
+            let mut max_weight = f64::NEG_INFINITY;
+            for (left_node, &left_alpha) in nodes.iter().zip(&self.alpha[end_node]) {
+                let conn_cost = connector.cost(left_node.right_id, left_id);
+                let weight = left_alpha - f64::from(conn_cost) / temperature;
+                max_weight = max_weight.max(weight);
+                self.weights.push(weight);
+            }
+            let mut sum = 0.0;
+            for weight in &mut self.weights {
+                *weight = (*weight - max_weight).exp();
+                sum += *weight;
+            }
+
+            // Falls back to the last node against rounding errors.
+            let mut r = gen_unit(rng) * sum;
+            let mut idx = nodes.len() - 1;
+            for (i, &weight) in self.weights.iter().enumerate() {
+                if r < weight {
+                    idx = i;
+                    break;
+                }
+                r -= weight;
+            }
+
+            let node = &nodes[idx];
+            top_nodes.push((end_node, *node));
+            (end_node, left_id) = (node.start_node, node.left_id);
+        }
+    }
+
+    fn reset_vec(data: &mut Vec<Vec<f64>>, lattice: &Lattice, value: f64) {
+        let len = lattice.len_char() + 1;
+        data.resize_with(len, Vec::new);
+        for (end_char, v) in data.iter_mut().take(len).enumerate() {
+            v.clear();
+            v.resize(lattice.nodes(end_char).len(), value);
+        }
+    }
+
+    /// Gets the marginal probability of the node of `word_idx` spanning `range_char`.
+    ///
+    /// Returns `None` if the values have not been computed or the node does not exist.
+    pub fn prob(
+        &self,
+        lattice: &Lattice,
+        range_char: Range<usize>,
+        word_idx: WordIdx,
+    ) -> Option<f64> {
+        if !self.computed
+            || range_char.start >= range_char.end
+            || lattice.len_char() < range_char.end
+        {
+            return None;
+        }
+        let i = lattice
+            .nodes(range_char.end)
+            .iter()
+            .position(|n| n.start_word == range_char.start && n.word_idx() == word_idx)?;
+        self.prob_at(range_char.end, i)
+    }
+
+    /// Gets the marginal probability of the `i`-th node ending at `end_char`.
+    ///
+    /// Returns `None` if the values have not been computed.
+    pub fn prob_at(&self, end_char: usize, i: usize) -> Option<f64> {
+        if !self.computed {
+            return None;
+        }
+        let log_prob = self.alpha[end_char][i] + self.beta[end_char][i] - self.log_z;
+        Some(log_prob.exp().min(1.0))
+    }
+}
+
+/// Generates a uniform random number in `[0, 1)`.
+#[inline(always)]
+fn gen_unit<R>(rng: &mut R) -> f64
+where
+    R: Rng + ?Sized,
+{
+    // Uses the upper 53 bits, the precision of f64.
+    (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Computes `ln(exp(x) + exp(y))` avoiding overflow.
+#[inline(always)]
+fn log_add_exp(x: f64, y: f64) -> f64 {
+    let (hi, lo) = if x < y { (y, x) } else { (x, y) };
+    if lo == f64::NEG_INFINITY {
+        return hi;
+    }
+    hi + (lo - hi).exp().ln_1p()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_add_exp() {
+        assert_eq!(
+            log_add_exp(f64::NEG_INFINITY, f64::NEG_INFINITY),
+            f64::NEG_INFINITY
+        );
+        assert_eq!(log_add_exp(f64::NEG_INFINITY, 1.5), 1.5);
+        assert!((log_add_exp(0.0, 0.0) - 2f64.ln()).abs() < 1e-12);
+        assert!((log_add_exp(1000.0, 1000.0) - (1000.0 + 2f64.ln())).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_gen_unit() {
+        struct ConstRng(u64);
+
+        impl rand_core::TryRng for ConstRng {
+            type Error = std::convert::Infallible;
+
+            fn try_next_u32(&mut self) -> Result<u32, Self::Error> {
+                Ok(self.0 as u32)
+            }
+            fn try_next_u64(&mut self) -> Result<u64, Self::Error> {
+                Ok(self.0)
+            }
+            fn try_fill_bytes(&mut self, dst: &mut [u8]) -> Result<(), Self::Error> {
+                dst.fill(0);
+                Ok(())
+            }
+        }
+
+        assert_eq!(gen_unit(&mut ConstRng(0)), 0.0);
+        assert!(gen_unit(&mut ConstRng(u64::MAX)) < 1.0);
+    }
+}
@@ -3,10 +3,8 @@ use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 use std::rc::Rc;
 
-use super::lattice::Node;
-use crate::dictionary::DictionaryInnerRef;
 use crate::dictionary::connector::ConnectorCost;
-use crate::tokenizer::lattice::LatticeNBest;
+use crate::tokenizer::lattice::{INVALID_PATH, LatticeNBest, Node};
 
 // The following structs are designed to reconstruct paths from the A* search result.
 // A path is stored as a linked list, which is pointed to by a QueueItem.
@@ -17,8 +15,8 @@ use crate::tokenizer::lattice::LatticeNBest;
 /// It forms a linked list from the end of the sentence to the beginning.
 #[derive(Debug)]
 struct SearchPath {
-    /// The node at the current position in the path.
-    node: *const Node,
+    /// The index of the node at the current position in the path.
+    node: u32,
     /// A pointer to the next node in the path (towards the BOS).
     prev: Option<Rc<SearchPath>>,
     /// The total cost from the EOS to this node (backward cost).
@@ -56,20 +54,17 @@ impl Ord for QueueItem {
 /// Generator for N-best tokenization results.
 pub struct NbestGenerator<'a> {
     queue: BinaryHeap<QueueItem>,
+    lattice: &'a LatticeNBest,
     connector: &'a dyn ConnectorCost,
-    dictionary: DictionaryInnerRef<'a>,
 }
 
 impl<'a> NbestGenerator<'a> {
-    pub fn new(
-        lattice: &'a LatticeNBest,
-        connector: &'a dyn ConnectorCost,
-        dictionary: DictionaryInnerRef<'a>,
-    ) -> Self {
+    pub fn new(lattice: &'a LatticeNBest, connector: &'a dyn ConnectorCost) -> Self {
         let mut queue = BinaryHeap::new();
-        if let Some(eos_node) = lattice.eos_node() {
+        if let Some(eos_idx) = lattice.eos_idx() {
+            let eos_node = lattice.node(eos_idx);
             let initial_path = Rc::new(SearchPath {
-                node: eos_node as *const Node,
+                node: eos_idx,
                 prev: None,
                 backward_cost: 0,
             });
@@ -80,57 +75,63 @@ impl<'a> NbestGenerator<'a> {
         }
         Self {
             queue,
+            lattice,
             connector,
-            dictionary,
         }
     }
 }
 
 impl<'a> Iterator for NbestGenerator<'a> {
-    type Item = (Vec<*const Node>, i32);
+    type Item = (Vec<Node>, i32);
 
     fn next(&mut self) -> Option<Self::Item> {
         while let Some(item) = self.queue.pop() {
             let current_path = &item.path;
-            let current_node = unsafe { &*current_path.node };
+            let current_node = self.lattice.node(current_path.node);
 
             // If we reached the BOS, a full path has been found.
             if current_node.is_bos() {
                 let mut path_nodes = Vec::new();
                 let mut p = Some(Rc::clone(current_path));
                 while let Some(seg) = p {
-                    let node = unsafe { &*seg.node };
+                    let node = self.lattice.node(seg.node);
                     if !node.is_bos() && !node.is_eos() {
-                        path_nodes.push(seg.node);
+                        path_nodes.push(*node);
                     }
                     p = seg.prev.clone();
                 }
                 return Some((path_nodes, item.priority));
             }
 
-            let mut lpath_ptr = current_node.lpath;
+            // The cost of the node itself, including the penalty in the search mode, is
+            // the difference between its minimum cost and that of the best left path.
+            let mut best_left_cost = i32::MAX;
+            let mut lpath_idx = current_node.lpath;
+            while lpath_idx != INVALID_PATH {
+                let lpath = self.lattice.path(lpath_idx);
+                let prev_node = self.lattice.node(lpath.lnode);
+                let conn_cost = self
+                    .connector
+                    .cost(prev_node.right_id, current_node.left_id);
+                best_left_cost = best_left_cost.min(prev_node.min_cost + conn_cost);
+                lpath_idx = lpath.lnext;
+            }
+            let node_cost = current_node.min_cost - best_left_cost;
+
+            let mut lpath_idx = current_node.lpath;
             // Expand to previous nodes.
-            while !lpath_ptr.is_null() {
-                let lpath = unsafe { &*lpath_ptr };
-                let prev_node_ptr = lpath.lnode;
-                let prev_node = unsafe { &*prev_node_ptr };
+            while lpath_idx != INVALID_PATH {
+                let lpath = self.lattice.path(lpath_idx);
+                let prev_node = self.lattice.node(lpath.lnode);
 
                 let conn_cost = self
                     .connector
                     .cost(prev_node.right_id, current_node.left_id);
-                let word_cost = if current_node.is_bos() || current_node.is_eos() {
-                    0
-                } else {
-                    self.dictionary
-                        .word_param(current_node.word_idx())
-                        .word_cost
-                };
-                let new_backward_cost =
-                    current_path.backward_cost + conn_cost + i32::from(word_cost);
+                let new_backward_cost = current_path.backward_cost + conn_cost + node_cost;
                 let new_priority = new_backward_cost + prev_node.min_cost; // f(x) = g(x) + h(x)
 
                 let new_path = Rc::new(SearchPath {
-                    node: prev_node_ptr,
+                    node: lpath.lnode,
                     prev: Some(Rc::clone(current_path)),
                     backward_cost: new_backward_cost,
                 });
@@ -139,7 +140,7 @@ impl<'a> Iterator for NbestGenerator<'a> {
                     priority: new_priority,
                 });
 
-                lpath_ptr = lpath.lnext;
+                lpath_idx = lpath.lnext;
             }
         }
         None
@@ -0,0 +1,266 @@
+//! Search mode decompounding long words.
+//!
+//! In the search mode, words longer than a threshold are penalized by their lengths,
+//! so that long compounds such as `関西国際空港` are split into their components
+//! (`関西`, `国際`, `空港`), which is preferable for full-text search.
+//! The thresholds and penalties can be configured for each character category
+//! defined in `char.def`.
+//!
+//! # Examples
+//!
+//! ```
+//! use vibrato_rkyv::tokenizer::search::{SearchPenalty, TokenizeMode};
+//! use vibrato_rkyv::{SystemDictionaryBuilder, Tokenizer};
+//!
+//! let dict = SystemDictionaryBuilder::from_readers(
+//!     "関西,0,0,1,kansai\n国際,0,0,1,kokusai\n空港,0,0,1,kuko\n関西国際空港,0,0,1,kankuu".as_bytes(),
+//!     "1 1\n0 0 0".as_bytes(),
+//!     "DEFAULT 0 1 0\nKANJI 0 0 2\n0x4E00..0x9FFF KANJI".as_bytes(),
+//!     "DEFAULT,0,0,100,*\nKANJI,0,0,100,*".as_bytes(),
+//! )?;
+//! let tokenizer = Tokenizer::from_inner(dict);
+//!
+//! let surfaces = |tokenizer: &Tokenizer| {
+//!     let mut worker = tokenizer.new_worker();
+//!     worker.reset_sentence("関西国際空港");
+//!     worker.tokenize();
+//!     worker.token_iter().map(|t| t.surface().to_string()).collect::<Vec<_>>()
+//! };
+//! assert_eq!(surfaces(&tokenizer), ["関西国際空港"]);
+//!
+//! let tokenizer = tokenizer.mode(TokenizeMode::Search(SearchPenalty::kuromoji()))?;
+//! assert_eq!(surfaces(&tokenizer), ["関西", "国際", "空港"]);
+//!
+//! let tokenizer = tokenizer.mode(TokenizeMode::Extended(SearchPenalty::kuromoji()))?;
+//! assert_eq!(surfaces(&tokenizer), ["関西国際空港", "関西", "国際", "空港"]);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::dictionary::LexType;
+use crate::dictionary::word_idx::WordIdx;
+use crate::sentence::Sentence;
+use crate::tokenizer::unk_rule;
+
+/// Upper bound of the penalty for a word, which keeps the path costs from overflowing.
+const MAX_PENALTY: i32 = 1 << 24;
+
+/// Penalty for words longer than a threshold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LengthPenalty {
+    /// Maximum length in characters of words not penalized.
+    pub threshold: usize,
+    /// Cost added for each character exceeding the threshold.
+    pub penalty: i32,
+}
+
+impl LengthPenalty {
+    /// Creates a new penalty.
+    pub const fn new(threshold: usize, penalty: i32) -> Self {
+        Self { threshold, penalty }
+    }
+
+    /// Computes the penalty of a word of the length.
+    #[inline(always)]
+    pub fn cost(&self, len_char: usize) -> i32 {
+        if len_char <= self.threshold {
+            return 0;
+        }
+        i32::try_from(len_char - self.threshold)
+            .unwrap_or(i32::MAX)
+            .saturating_mul(self.penalty)
+            .clamp(-MAX_PENALTY, MAX_PENALTY)
+    }
+}
+
+/// Length penalties in the search mode for each character category.
+///
+/// The penalty of a category is applied to words consisting only of characters
+/// in the category. If a word matches several categories, the first one specified
+/// is used, and a word matching none of them is given the penalty of [`Self::other()`].
+/// Words in user lexicons and custom lexicons, and words injected by
+/// [`UnkRule::word()`](crate::tokenizer::unk_rule::UnkRule::word) are never penalized.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchPenalty {
+    categories: Vec<(String, LengthPenalty)>,
+    other: Option<LengthPenalty>,
+}
+
+impl SearchPenalty {
+    /// Creates a new configuration without any penalty.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the configuration of the search mode in Kuromoji,
+    /// which penalizes words of `KANJI` longer than 2 characters by 3000 per character
+    /// and the other words longer than 7 characters by 1700 per character.
+    pub fn kuromoji() -> Self {
+        Self::new().category("KANJI", 2, 3000).other(7, 1700)
+    }
+
+    /// Specifies the penalty of the category.
+    ///
+    /// # Arguments
+    ///
+    ///  - `category`: The name of the category defined in `char.def`.
+    ///  - `threshold`: The maximum length in characters of words not penalized.
+    ///  - `penalty`: The cost added for each character exceeding the threshold.
+    pub fn category<S>(mut self, category: S, threshold: usize, penalty: i32) -> Self
+    where
+        S: Into<String>,
+    {
+        let category = category.into();
+        let penalty = LengthPenalty::new(threshold, penalty);
+        match self.categories.iter_mut().find(|(c, _)| *c == category) {
+            Some((_, p)) => *p = penalty,
+            None => self.categories.push((category, penalty)),
+        }
+        self
+    }
+
+    /// Specifies the penalty of words not matching the specified categories.
+    pub const fn other(mut self, threshold: usize, penalty: i32) -> Self {
+        self.other = Some(LengthPenalty::new(threshold, penalty));
+        self
+    }
+
+    /// Gets the penalties of the categories.
+    #[inline(always)]
+    pub fn categories(&self) -> &[(String, LengthPenalty)] {
+        &self.categories
+    }
+}
+
+/// Mode of tokenization, specified with [`Tokenizer::mode()`](crate::Tokenizer::mode).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum TokenizeMode {
+    /// Outputs the best tokenization.
+    #[default]
+    Normal,
+
+    /// Outputs the best tokenization under the length penalties, which splits long compounds.
+    Search(SearchPenalty),
+
+    /// Outputs the tokens of both the normal and search modes. Tokens of compounds are
+    /// followed by the tokens of their components, whose positions overlap.
+    ///
+    /// Only [`Worker::tokenize()`](crate::tokenizer::worker::Worker::tokenize) outputs
+    /// both, and the other methods work in the same way as the search mode.
+    Extended(SearchPenalty),
+}
+
+impl TokenizeMode {
+    /// Gets the length penalties, or `None` in the normal mode.
+    #[inline(always)]
+    pub const fn penalty(&self) -> Option<&SearchPenalty> {
+        match self {
+            Self::Normal => None,
+            Self::Search(penalty) | Self::Extended(penalty) => Some(penalty),
+        }
+    }
+}
+
+/// Length penalties whose categories are resolved with a dictionary.
+#[derive(Clone, Debug)]
+pub(crate) struct CategoryPenalties {
+    // Pairs of the category id set and the penalty
+    categories: Vec<(u32, LengthPenalty)>,
+    other: Option<LengthPenalty>,
+    // Words not longer than this are never penalized.
+    min_threshold: usize,
+}
+
+impl CategoryPenalties {
+    /// Resolves the categories with `cate_id`, skipping ones not defined.
+    pub(crate) fn new<F>(penalty: &SearchPenalty, cate_id: F) -> Self
+    where
+        F: Fn(&str) -> Option<u32>,
+    {
+        let categories: Vec<_> = penalty
+            .categories
+            .iter()
+            .filter_map(|(c, p)| cate_id(c).map(|id| (1 << id, *p)))
+            .collect();
+        let min_threshold = categories
+            .iter()
+            .map(|(_, p)| p)
+            .chain(penalty.other.as_ref())
+            .map(|p| p.threshold)
+            .min()
+            .unwrap_or(usize::MAX);
+        Self {
+            categories,
+            other: penalty.other,
+            min_threshold,
+        }
+    }
+
+    /// Computes the penalty of the word in the range of the sentence, which is 0 for words
+    /// never penalized.
+    #[inline(always)]
+    pub(crate) fn word_cost(
+        &self,
+        sent: &Sentence,
+        word_idx: WordIdx,
+        start_char: usize,
+        end_char: usize,
+    ) -> i32 {
+        let is_penalized = match word_idx.lex_type {
+            LexType::System => true,
+            // Words of UnkRule::word() have ids from FIRST_WORD_ID.
+            LexType::Unknown => word_idx.word_id < unk_rule::FIRST_WORD_ID,
+            LexType::User | LexType::Custom => false,
+        };
+        if is_penalized {
+            self.cost(sent, start_char, end_char)
+        } else {
+            0
+        }
+    }
+
+    /// Computes the penalty of a word in the range of the sentence by its length.
+    #[inline(always)]
+    fn cost(&self, sent: &Sentence, start_char: usize, end_char: usize) -> i32 {
+        let len_char = end_char - start_char;
+        if len_char <= self.min_threshold {
+            return 0;
+        }
+        let cate_idset =
+            (start_char..end_char).fold(u32::MAX, |set, i| set & sent.char_info(i).cate_idset());
+        self.categories
+            .iter()
+            .find(|(set, _)| cate_idset & set != 0)
+            .map(|(_, p)| p)
+            .or(self.other.as_ref())
+            .map_or(0, |p| p.cost(len_char))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_length_penalty() {
+        let penalty = LengthPenalty::new(2, 3000);
+        assert_eq!(penalty.cost(1), 0);
+        assert_eq!(penalty.cost(2), 0);
+        assert_eq!(penalty.cost(3), 3000);
+        assert_eq!(penalty.cost(6), 12000);
+        assert_eq!(penalty.cost(usize::MAX), MAX_PENALTY);
+    }
+
+    #[test]
+    fn test_category_overwritten() {
+        let penalty = SearchPenalty::new()
+            .category("KANJI", 2, 3000)
+            .category("KATAKANA", 5, 1000)
+            .category("KANJI", 3, 2000);
+        assert_eq!(
+            penalty.categories(),
+            [
+                ("KANJI".to_string(), LengthPenalty::new(3, 2000)),
+                ("KATAKANA".to_string(), LengthPenalty::new(5, 1000)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! Streaming tokenization of large inputs.
+use std::collections::VecDeque;
+use std::io::Read;
+
+use crate::common::BOS_EOS_CONNECTION_ID;
+use crate::errors::{Result, VibratoError};
+use crate::token::TokenBuf;
+use crate::tokenizer::lattice::LatticeKind;
+use crate::tokenizer::worker::Worker;
+
+/// Number of bytes read from the reader at once.
+const READ_SIZE: usize = 1 << 16;
+
+/// Minimum number of characters following a split point in a chunk,
+/// which are used to find words crossing the point.
+const MIN_LOOKAHEAD: usize = 64;
+
+/// Default value of [`TokenStream::max_chunk_len()`].
+const DEFAULT_MAX_CHUNK_LEN: usize = 1 << 16;
+
+/// Iterator of tokens in a text read from a reader, created by
+/// [`Tokenizer::tokenize_stream()`](crate::Tokenizer::tokenize_stream).
+///
+/// The text is tokenized chunk by chunk so that a large input does not build
+/// an enormous lattice. A chunk is split at a position that every path in its lattice
+/// passes through with the same word, preferring the ends of sentences and lines,
+/// and the next chunk is tokenized as the continuation of the word. Therefore, the result
+/// is identical to the one of the whole input, unless a word crosses the split point
+/// beyond the lookahead of 64 characters. If no such position is found within
+/// [`Self::max_chunk_len()`] characters, the chunk is split at a token boundary
+/// of its best path, where the result may differ.
+///
+/// The positions of the resultant tokens are relative to the beginning of the stream,
+/// and their total costs are accumulated from the beginning of the stream.
+/// If the tokenizer has character filters, the positions are in the text before
+/// the filters are applied, which are applied to each chunk.
+///
+/// # Examples
+///
+/// ```
+/// use vibrato_rkyv::{SystemDictionaryBuilder, Tokenizer};
+///
+/// let dict = SystemDictionaryBuilder::from_readers(
+///     "京都,0,0,1,kyoto\n東京,0,0,1,tokyo\n。,0,0,1,period".as_bytes(),
+///     "1 1\n0 0 0".as_bytes(),
+///     "DEFAULT 0 1 0".as_bytes(),
+///     "DEFAULT,0,0,100,*".as_bytes(),
+/// )?;
+/// let tokenizer = Tokenizer::from_inner(dict);
+///
+/// let text = "京都。東京。".repeat(1000);
+/// let mut surfaces = vec![];
+/// for token in tokenizer.tokenize_stream(text.as_bytes()).max_chunk_len(100)? {
+///     let token = token?;
+///     assert_eq!(&text[token.range_byte.clone()], token.surface);
+///     surfaces.push(token.surface);
+/// }
+///
+/// let mut worker = tokenizer.new_worker();
+/// worker.reset_sentence(&text);
+/// worker.tokenize();
+/// assert!(worker.token_iter().map(|t| t.surface()).eq(&surfaces));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct TokenStream<R> {
+    worker: Worker,
+    rdr: R,
+    // Text read but not tokenized yet, and bytes not decoded yet.
+    text: String,
+    undecoded: Vec<u8>,
+    eof: bool,
+    failed: bool,
+    // Positions of the beginning of `text` in the stream.
+    offset_byte: usize,
+    offset_char: usize,
+    // Right connection id and total cost of the last token.
+    right_id: u16,
+    total_cost: i32,
+    tokens: VecDeque<TokenBuf>,
+    sync_points: Vec<bool>,
+    max_chunk_len: usize,
+}
+
+impl<R> TokenStream<R>
+where
+    R: Read,
+{
+    pub(crate) fn new(worker: Worker, rdr: R) -> Self {
+        Self {
+            worker,
+            rdr,
+            text: String::new(),
+            undecoded: vec![],
+            eof: false,
+            failed: false,
+            offset_byte: 0,
+            offset_char: 0,
+            right_id: BOS_EOS_CONNECTION_ID,
+            total_cost: 0,
+            tokens: VecDeque::new(),
+            sync_points: vec![],
+            max_chunk_len: DEFAULT_MAX_CHUNK_LEN,
+        }
+    }
+
+    /// Specifies the maximum number of characters in a chunk.
+    /// The default value is 65536.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when `max_chunk_len` is not greater than 64.
+    pub fn max_chunk_len(mut self, max_chunk_len: usize) -> Result<Self> {
+        if max_chunk_len <= MIN_LOOKAHEAD {
+            return Err(VibratoError::invalid_argument(
+                "max_chunk_len",
+                format!("must be greater than {MIN_LOOKAHEAD}."),
+            ));
+        }
+        self.max_chunk_len = max_chunk_len;
+        Ok(self)
+    }
+
+    /// Checks if the character is a preferred end of chunks.
+    #[inline(always)]
+    fn is_boundary(c: char) -> bool {
+        matches!(c, '\n' | '。' | '．' | '！' | '？' | '.' | '!' | '?')
+    }
+
+    /// Reads a block from the reader, appending the decoded text.
+    fn read_block(&mut self) -> Result<()> {
+        let len = self.undecoded.len();
+        self.undecoded.resize(len + READ_SIZE, 0);
+        let num_read = loop {
+            match self.rdr.read(&mut self.undecoded[len..]) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.undecoded.truncate(len);
+                    return Err(e.into());
+                }
+            }
+        };
+        self.undecoded.truncate(len + num_read);
+        self.eof = num_read == 0;
+
+        let valid_len = match std::str::from_utf8(&self.undecoded) {
+            Ok(s) => s.len(),
+            // The end of the block may split a character.
+            Err(e) if e.error_len().is_none() && !self.eof => e.valid_up_to(),
+            Err(e) => return Err(e.into()),
+        };
+        // The bytes have been validated above.
+        self.text
+            .push_str(std::str::from_utf8(&self.undecoded[..valid_len]).unwrap());
+        self.undecoded.drain(..valid_len);
+        Ok(())
+    }
+
+    /// Checks if the text has a preferred split point with enough lookahead.
+    fn has_boundary(&self) -> bool {
+        self.text
+            .chars()
+            .rev()
+            .skip(MIN_LOOKAHEAD)
+            .any(Self::is_boundary)
+    }
+
+    /// Tokenizes the next chunk, pushing the resultant tokens.
+    fn tokenize_chunk(&mut self) -> Result<()> {
+        while !self.eof
+            && self.text.chars().nth(self.max_chunk_len).is_none()
+            && !self.has_boundary()
+        {
+            self.read_block()?;
+        }
+        if self.text.is_empty() {
+            return Ok(());
+        }
+
+        let chunk_len = self
+            .text
+            .char_indices()
+            .nth(self.max_chunk_len)
+            .map_or(self.text.len(), |(i, _)| i);
+        let is_last = self.eof && chunk_len == self.text.len();
+
+        if self.worker.tokenizer.refresh_dictionary() {
+            // Connection ids of the previous dictionary are meaningless.
+            self.right_id = BOS_EOS_CONNECTION_ID;
+        }
+        self.worker.reset_sentence(&self.text[..chunk_len]);
+        self.worker.tokenize_continued(self.right_id);
+
+        let end_char = if is_last {
+            self.worker.sent.len_char()
+        } else {
+            self.find_split_point()
+        };
+
+        let mut consumed = (0, 0);
+        for token in self.worker.token_iter() {
+            if token.range_char().end > end_char {
+                break;
+            }
+            let mut buf = token.to_buf();
+            let range_byte = token.original_range_byte();
+            let range_char = token.original_range_char();
+            consumed = (range_byte.end, range_char.end);
+            buf.range_byte = self.offset_byte + range_byte.start..self.offset_byte + range_byte.end;
+            buf.range_char = self.offset_char + range_char.start..self.offset_char + range_char.end;
+            buf.total_cost = buf.total_cost.saturating_add(self.total_cost);
+            self.right_id = buf.right_id;
+            self.tokens.push_back(buf);
+        }
+        if let Some(last) = self.tokens.back() {
+            self.total_cost = last.total_cost;
+        }
+
+        if is_last || self.worker.num_tokens() == 0 {
+            // The chunk has no token, e.g., all the characters are removed by filters.
+            consumed = (chunk_len, self.text[..chunk_len].chars().count());
+        }
+        self.text.drain(..consumed.0);
+        self.offset_byte += consumed.0;
+        self.offset_char += consumed.1;
+        Ok(())
+    }
+
+    /// Finds the position to split the tokenized chunk in characters.
+    fn find_split_point(&mut self) -> usize {
+        let LatticeKind::For1Best(lattice) = &self.worker.lattice else {
+            unreachable!();
+        };
+        lattice.mark_sync_points(&mut self.sync_points);
+
+        let chars = self.worker.sent.chars();
+        let last = chars.len().saturating_sub(MIN_LOOKAHEAD);
+        let candidates = (1..=last).rev().filter(|&i| self.sync_points[i]);
+        if let Some(pos) = candidates
+            .clone()
+            .find(|&i| Self::is_boundary(chars[i - 1]))
+        {
+            return pos;
+        }
+        if let Some(pos) = candidates.clone().next() {
+            return pos;
+        }
+        // Splits at the end of the second last token, which may change the result.
+        let num_tokens = self.worker.num_tokens();
+        if num_tokens >= 2 {
+            self.worker.token(num_tokens - 2).range_char().end
+        } else {
+            chars.len()
+        }
+    }
+}
+
+impl<R> Iterator for TokenStream<R>
+where
+    R: Read,
+{
+    type Item = Result<TokenBuf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.tokens.is_empty() && !self.failed {
+            if self.eof && self.text.is_empty() {
+                return None;
+            }
+            if let Err(e) = self.tokenize_chunk() {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        }
+        self.tokens.pop_front().map(Ok)
+    }
+}
@@ -0,0 +1,272 @@
+//! Pluggable generators of unknown words.
+//!
+//! By default, unknown words are generated from the character categories in `char.def`
+//! and the entries in `unk.def` in the same manner as MeCab. An [`UnkGenerator`]
+//! replaces the algorithm with another strategy, such as a character n-gram model or
+//! a rule grouping katakana with long vowel marks, while still referring to the
+//! character categories of the dictionary through [`UnkInput`].
+//!
+//! # Examples
+//!
+//! ```
+//! use vibrato_rkyv::dictionary::{LexType, WordParam};
+//! use vibrato_rkyv::tokenizer::unk_generator::{UnkGenerator, UnkInput};
+//! use vibrato_rkyv::{SystemDictionaryBuilder, Tokenizer};
+//!
+//! // Groups katakana followed by long vowel marks, which are not KATAKANA in char.def.
+//! struct KatakanaGenerator;
+//!
+//! impl UnkGenerator for KatakanaGenerator {
+//!     fn gen_unk_words(&self, input: &UnkInput<'_>, f: &mut dyn FnMut(usize, u32)) {
+//!         let katakana = 1 << input.cate_id("KATAKANA").unwrap();
+//!         let chars = input.chars();
+//!         let start = input.start_char();
+//!         if input.cate_idset(start) & katakana == 0 {
+//!             if !input.has_matched() {
+//!                 f(start + 1, 0);
+//!             }
+//!             return;
+//!         }
+//!         let mut end = start + 1;
+//!         while end < chars.len()
+//!             && (input.cate_idset(end) & katakana != 0 || chars[end] == 'ー')
+//!         {
+//!             end += 1;
+//!         }
+//!         f(end, 1);
+//!     }
+//!
+//!     fn word_param(&self, word_id: u32) -> WordParam {
+//!         WordParam::new(0, 0, if word_id == 0 { 1000 } else { 100 })
+//!     }
+//!
+//!     fn word_feature(&self, word_id: u32) -> &str {
+//!         ["symbol", "katakana"][word_id as usize]
+//!     }
+//! }
+//!
+//! let dict = SystemDictionaryBuilder::from_readers(
+//!     "が,0,0,1,particle".as_bytes(),
+//!     "1 1\n0 0 0".as_bytes(),
+//!     "DEFAULT 0 1 0\nKATAKANA 1 1 0\n0x30A1..0x30FA KATAKANA".as_bytes(),
+//!     "DEFAULT,0,0,100,*\nKATAKANA,0,0,100,*".as_bytes(),
+//! )?;
+//! let tokenizer = Tokenizer::from_inner(dict).unk_generator(KatakanaGenerator)?;
+//!
+//! let mut worker = tokenizer.new_worker();
+//! worker.reset_sentence("コーヒーが");
+//! worker.tokenize();
+//! let t = worker.token(0);
+//! assert_eq!(t.surface(), "コーヒー");
+//! assert_eq!(t.lex_type(), LexType::Unknown);
+//! assert_eq!(t.feature(), "katakana");
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use crate::dictionary::LexType;
+use crate::dictionary::lexicon::WordParam;
+use crate::dictionary::unknown::{ArchivedUnkHandler, UnkHandler};
+use crate::dictionary::word_idx::WordIdx;
+use crate::sentence::Sentence;
+use crate::tokenizer::Tokenizer;
+
+/// Generator of unknown words called at each position in lattice construction.
+///
+/// Generated words are identified with [`LexType::Unknown`] and the word ids given by
+/// the generator, which must be less than 65536. Words with greater ids are ignored.
+/// As with [`CustomLexicon`](crate::tokenizer::custom_lexicon::CustomLexicon), word ids
+/// must keep referring to the same words while tokens of them are in use.
+pub trait UnkGenerator: Send + Sync {
+    /// Generates unknown words starting at [`UnkInput::start_char()`], calling `f` with
+    /// the ending position in characters and the id of each word.
+    ///
+    /// Words ending at or before the starting position or beyond the sentence are ignored.
+    /// If no word is generated at a position that no other word starts from, the position
+    /// cannot be passed through.
+    fn gen_unk_words(&self, input: &UnkInput<'_>, f: &mut dyn FnMut(usize, u32));
+
+    /// Generates unknown words spanning exactly from [`UnkInput::start_char()`] to
+    /// `end_char`, calling `f` with the id of each word.
+    ///
+    /// This is called under constraints rejecting all the candidates at a position.
+    /// By default, the words of [`Self::gen_unk_words()`] ending at `end_char` are used,
+    /// regardless of [`UnkInput::has_matched()`].
+    fn gen_unk_words_in_span(&self, input: &UnkInput<'_>, end_char: usize, f: &mut dyn FnMut(u32)) {
+        let input = UnkInput {
+            has_matched: false,
+            ..*input
+        };
+        self.gen_unk_words(&input, &mut |end, word_id| {
+            if end == end_char {
+                f(word_id);
+            }
+        });
+    }
+
+    /// Gets the parameter of the word.
+    ///
+    /// The connection ids are interpreted in the same way as ones in user lexicons.
+    /// Words with connection ids out of range of the dictionary are ignored.
+    fn word_param(&self, word_id: u32) -> WordParam;
+
+    /// Gets the feature string of the word.
+    fn word_feature(&self, word_id: u32) -> &str;
+}
+
+/// Input sentence and state at a position given to [`UnkGenerator::gen_unk_words()`].
+pub struct UnkInput<'a> {
+    pub(crate) tokenizer: &'a Tokenizer,
+    pub(crate) sent: &'a Sentence,
+    pub(crate) start_char: usize,
+    pub(crate) has_matched: bool,
+}
+
+impl UnkInput<'_> {
+    /// Gets the characters of the whole sentence.
+    #[inline(always)]
+    pub fn chars(&self) -> &[char] {
+        self.sent.chars()
+    }
+
+    /// Gets the starting position of the words to be generated, in characters.
+    #[inline(always)]
+    pub const fn start_char(&self) -> usize {
+        self.start_char
+    }
+
+    /// Checks if any word in the lexicons starts at the position.
+    #[inline(always)]
+    pub const fn has_matched(&self) -> bool {
+        self.has_matched
+    }
+
+    /// Gets the maximum grouping length specified by
+    /// [`Tokenizer::max_grouping_len()`], or `None` for the infinity length.
+    #[inline(always)]
+    pub const fn max_grouping_len(&self) -> Option<usize> {
+        self.tokenizer.max_grouping_len
+    }
+
+    /// Gets the set of the category ids of the character at the position,
+    /// where the bit of each category id is set.
+    ///
+    /// # Panics
+    ///
+    /// It will panic if the position is out of range of the sentence.
+    #[inline(always)]
+    pub fn cate_idset(&self, pos_char: usize) -> u32 {
+        self.sent.char_info(pos_char).cate_idset()
+    }
+
+    /// Gets the number of the characters from the position that can be grouped
+    /// with the character at the position, i.e., sharing a category with it.
+    ///
+    /// # Panics
+    ///
+    /// It will panic if the position is out of range of the sentence.
+    #[inline(always)]
+    pub fn groupable(&self, pos_char: usize) -> usize {
+        self.sent.groupable(pos_char)
+    }
+
+    /// Gets the id of the character category defined in the dictionary (i.e., char.def).
+    pub fn cate_id(&self, category: &str) -> Option<u32> {
+        self.tokenizer.cate_id(category)
+    }
+}
+
+/// The default generator following `unk.def`.
+impl UnkGenerator for UnkHandler {
+    fn gen_unk_words(&self, input: &UnkInput<'_>, f: &mut dyn FnMut(usize, u32)) {
+        self.gen_unk_words(
+            input.sent,
+            input.start_char,
+            input.has_matched,
+            input.max_grouping_len(),
+            |w| f(w.end_char(), w.word_idx().word_id),
+        );
+    }
+
+    fn gen_unk_words_in_span(&self, input: &UnkInput<'_>, end_char: usize, f: &mut dyn FnMut(u32)) {
+        self.gen_unk_words_in_span(input.sent, input.start_char, end_char, |w| {
+            f(w.word_idx().word_id)
+        });
+    }
+
+    fn word_param(&self, word_id: u32) -> WordParam {
+        self.word_param(WordIdx::new(LexType::Unknown, word_id))
+    }
+
+    fn word_feature(&self, word_id: u32) -> &str {
+        self.word_feature(WordIdx::new(LexType::Unknown, word_id))
+    }
+}
+
+/// The default generator following `unk.def`.
+impl UnkGenerator for ArchivedUnkHandler {
+    fn gen_unk_words(&self, input: &UnkInput<'_>, f: &mut dyn FnMut(usize, u32)) {
+        self.gen_unk_words(
+            input.sent,
+            input.start_char,
+            input.has_matched,
+            input.max_grouping_len(),
+            |w| f(w.end_char(), w.word_idx().word_id),
+        );
+    }
+
+    fn gen_unk_words_in_span(&self, input: &UnkInput<'_>, end_char: usize, f: &mut dyn FnMut(u32)) {
+        self.gen_unk_words_in_span(input.sent, input.start_char, end_char, |w| {
+            f(w.word_idx().word_id)
+        });
+    }
+
+    fn word_param(&self, word_id: u32) -> WordParam {
+        self.word_param(WordIdx::new(LexType::Unknown, word_id))
+    }
+
+    fn word_feature(&self, word_id: u32) -> &str {
+        self.word_feature(WordIdx::new(LexType::Unknown, word_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::dictionary::{DictionaryInnerRef, SystemDictionaryBuilder};
+
+    #[test]
+    fn test_default_generator() {
+        let dict = SystemDictionaryBuilder::from_readers(
+            "".as_bytes(),
+            "1 1\n0 0 0".as_bytes(),
+            "DEFAULT 0 1 0\nALPHA 1 1 2\n0x0061..0x007A ALPHA".as_bytes(),
+            "DEFAULT,0,0,100,*\nALPHA,0,0,200,a\nALPHA,0,0,300,b".as_bytes(),
+        )
+        .unwrap();
+        let tokenizer = Tokenizer::from_inner(dict);
+        let mut sent = Sentence::new();
+        sent.set_sentence("abc");
+        let DictionaryInnerRef::Owned(dict) = tokenizer.dictionary() else {
+            unreachable!();
+        };
+        sent.compile(dict.char_prop());
+
+        let input = UnkInput {
+            tokenizer: &tokenizer,
+            sent: &sent,
+            start_char: 0,
+            has_matched: false,
+        };
+        let generator: &dyn UnkGenerator = dict.unk_handler();
+        let mut words = vec![];
+        generator.gen_unk_words(&input, &mut |end_char, word_id| {
+            words.push((end_char, word_id))
+        });
+        assert_eq!(words, [(3, 1), (3, 2), (1, 1), (1, 2), (2, 1), (2, 2)]);
+        let mut word_ids = vec![];
+        generator.gen_unk_words_in_span(&input, 2, &mut |word_id| word_ids.push(word_id));
+        assert_eq!(word_ids, [1, 2]);
+        assert_eq!(generator.word_param(2), WordParam::new(0, 0, 300));
+        assert_eq!(generator.word_feature(2), "b");
+    }
+}
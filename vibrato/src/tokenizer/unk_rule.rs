@@ -0,0 +1,321 @@
+//! Rules injecting unknown words for spans matching regular expressions.
+//!
+//! Unknown words are usually grouped by the character categories in `char.def`,
+//! so URLs, email addresses, and decimal numbers such as `3.14` are split into
+//! many tokens. A rule injects a candidate word spanning each match of its pattern
+//! into the lattice, which competes with the other candidates by its cost.
+//!
+//! # Examples
+//!
+//! ```
+//! use vibrato_rkyv::tokenizer::unk_rule::UnkRule;
+//! use vibrato_rkyv::{SystemDictionaryBuilder, Tokenizer};
+//!
+//! let dict = SystemDictionaryBuilder::from_readers(
+//!     "円,0,0,1,yen".as_bytes(),
+//!     "1 1\n0 0 0".as_bytes(),
+//!     "DEFAULT 0 1 0\nNUMERIC 1 1 0\n0x0030..0x0039 NUMERIC".as_bytes(),
+//!     "DEFAULT,0,0,100,*\nNUMERIC,0,0,100,number".as_bytes(),
+//! )?;
+//! let tokenizer = Tokenizer::from_inner(dict)
+//!     .unk_rule(UnkRule::word(r"\d+\.\d+", 0, 0, -100, "decimal")?)?;
+//!
+//! let mut worker = tokenizer.new_worker();
+//! worker.reset_sentence("3.14円");
+//! worker.tokenize();
+//! let tokens: Vec<_> = worker
+//!     .token_iter()
+//!     .map(|t| (t.surface().to_string(), t.feature().to_string()))
+//!     .collect();
+//! assert_eq!(tokens, [("3.14".into(), "decimal".into()), ("円".into(), "yen".into())]);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use regex::Regex;
+
+use crate::dictionary::LexType;
+use crate::dictionary::lexicon::WordParam;
+use crate::dictionary::word_idx::WordIdx;
+use crate::errors::{Result, VibratoError};
+use crate::sentence::Sentence;
+use crate::utils::FromU32;
+
+/// Rule injecting unknown words for spans matching a regular expression.
+///
+/// The words are injected at the non-overlapping matches of the pattern found
+/// from the beginning of each sentence. Empty matches are ignored.
+/// See [`Tokenizer::unk_rule()`](crate::Tokenizer::unk_rule).
+#[derive(Clone, Debug)]
+pub struct UnkRule {
+    regex: Regex,
+    kind: UnkRuleKind,
+}
+
+#[derive(Clone, Debug)]
+enum UnkRuleKind {
+    Word { param: WordParam, feature: String },
+    Category(String),
+}
+
+impl UnkRule {
+    /// Creates a rule injecting a word with the parameters.
+    ///
+    /// The connection ids are interpreted in the same way as ones in user lexicons.
+    /// The word is never penalized in the search mode.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when the pattern is invalid.
+    pub fn word<S>(
+        pattern: &str,
+        left_id: u16,
+        right_id: u16,
+        word_cost: i16,
+        feature: S,
+    ) -> Result<Self>
+    where
+        S: Into<String>,
+    {
+        Ok(Self {
+            regex: Self::compile(pattern)?,
+            kind: UnkRuleKind::Word {
+                param: WordParam::new(left_id, right_id, word_cost),
+                feature: feature.into(),
+            },
+        })
+    }
+
+    /// Creates a rule injecting the unknown words of the category defined in `unk.def`,
+    /// which are output in the same manner as unknown words grouped by `char.def`.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when the pattern is invalid.
+    pub fn category<S>(pattern: &str, category: S) -> Result<Self>
+    where
+        S: Into<String>,
+    {
+        Ok(Self {
+            regex: Self::compile(pattern)?,
+            kind: UnkRuleKind::Category(category.into()),
+        })
+    }
+
+    /// Gets the pattern of the rule.
+    #[inline(always)]
+    pub fn pattern(&self) -> &str {
+        self.regex.as_str()
+    }
+
+    /// Gets the category of the rule, or `None` if the rule injects a word with parameters.
+    #[inline(always)]
+    pub fn category_name(&self) -> Option<&str> {
+        match &self.kind {
+            UnkRuleKind::Word { .. } => None,
+            UnkRuleKind::Category(category) => Some(category),
+        }
+    }
+
+    /// Gets the parameter of the word injected by the rule.
+    #[inline(always)]
+    pub(crate) const fn word_param(&self) -> Option<WordParam> {
+        match &self.kind {
+            UnkRuleKind::Word { param, .. } => Some(*param),
+            UnkRuleKind::Category(_) => None,
+        }
+    }
+
+    /// Replaces the parameter of the word injected by the rule, e.g., with the one whose
+    /// connection ids are mapped.
+    pub(crate) fn set_word_param(&mut self, word_param: WordParam) {
+        if let UnkRuleKind::Word { param, .. } = &mut self.kind {
+            *param = word_param;
+        }
+    }
+
+    fn compile(pattern: &str) -> Result<Regex> {
+        Regex::new(pattern).map_err(|e| VibratoError::invalid_argument("pattern", e.to_string()))
+    }
+}
+
+/// Words injected at a match of a rule.
+pub(crate) struct UnkRuleMatch<'a> {
+    pub start_char: usize,
+    pub end_char: usize,
+    pub words: &'a [(WordIdx, WordParam)],
+}
+
+/// The first word id of the words of [`UnkRule::word()`].
+pub(crate) const FIRST_WORD_ID: u32 = 1 << 16;
+
+/// Rules attached to a tokenizer, whose words are resolved with a dictionary.
+///
+/// Words of [`UnkRule::word()`] are identified with [`LexType::Unknown`], and their ids
+/// start from [`FIRST_WORD_ID`], beyond the ids of unknown words in the dictionary and
+/// ones given by an [`UnkGenerator`](crate::tokenizer::unk_generator::UnkGenerator).
+#[derive(Clone, Default)]
+pub(crate) struct UnkRules {
+    rules: Vec<UnkRule>,
+    // Words injected by each rule
+    words: Vec<Vec<(WordIdx, WordParam)>>,
+    // Pairs of the feature and the parameter of the words of UnkRule::word()
+    entries: Vec<(String, WordParam)>,
+}
+
+impl UnkRules {
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Checks if any rule injects the unknown words of a category.
+    #[inline(always)]
+    pub fn has_category(&self) -> bool {
+        self.rules.iter().any(|rule| rule.category_name().is_some())
+    }
+
+    /// Appends a rule.
+    ///
+    /// # Arguments
+    ///
+    ///  - `rule`: The rule, whose connection ids have been mapped.
+    ///  - `category_word_ids`: The function to get the word ids of unknown words in a category.
+    pub fn push<F>(&mut self, rule: UnkRule, category_word_ids: F) -> Result<()>
+    where
+        F: Fn(&str) -> Option<Vec<(WordIdx, WordParam)>>,
+    {
+        self.rules.push(rule);
+        self.resolve(category_word_ids).inspect_err(|_| {
+            self.rules.pop();
+        })
+    }
+
+    /// Resolves the words of the rules, skipping categories not defined.
+    pub fn resolve<F>(&mut self, category_word_ids: F) -> Result<()>
+    where
+        F: Fn(&str) -> Option<Vec<(WordIdx, WordParam)>>,
+    {
+        let mut words = Vec::with_capacity(self.rules.len());
+        let mut entries = vec![];
+        for rule in &self.rules {
+            match &rule.kind {
+                UnkRuleKind::Word { param, feature } => {
+                    let word_id = u32::try_from(entries.len())
+                        .ok()
+                        .and_then(|i| FIRST_WORD_ID.checked_add(i))
+                        .ok_or_else(|| VibratoError::invalid_argument("rule", "too many rules."))?;
+                    words.push(vec![(WordIdx::new(LexType::Unknown, word_id), *param)]);
+                    entries.push((feature.clone(), *param));
+                }
+                UnkRuleKind::Category(category) => {
+                    words.push(category_word_ids(category).unwrap_or_default());
+                }
+            }
+        }
+        self.words = words;
+        self.entries = entries;
+        Ok(())
+    }
+
+    /// Gets the feature and parameter of the word injected by a rule.
+    ///
+    /// Returns `None` if the word is not from the rules.
+    #[inline(always)]
+    pub fn get(&self, word_idx: WordIdx) -> Option<(&str, WordParam)> {
+        if word_idx.lex_type != LexType::Unknown || word_idx.word_id < FIRST_WORD_ID {
+            return None;
+        }
+        self.entries
+            .get(usize::from_u32(word_idx.word_id - FIRST_WORD_ID))
+            .map(|(feature, param)| (feature.as_str(), *param))
+    }
+
+    /// Finds the matches of the rules in the sentence, in ascending order of their
+    /// starting positions.
+    pub fn find<'a>(&'a self, sent: &Sentence) -> Vec<UnkRuleMatch<'a>> {
+        let mut matches = vec![];
+        for (rule, words) in self.rules.iter().zip(&self.words) {
+            if words.is_empty() {
+                continue;
+            }
+            for m in rule.regex.find_iter(sent.raw()) {
+                if m.is_empty() {
+                    continue;
+                }
+                matches.push(UnkRuleMatch {
+                    start_char: sent.char_position(m.start()),
+                    end_char: sent.char_position(m.end()),
+                    words,
+                });
+            }
+        }
+        // The order of rules is kept for matches at the same position.
+        matches.sort_by_key(|m| m.start_char);
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::dictionary::character::CharProperty;
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!(UnkRule::word("(", 0, 0, 0, "*").is_err());
+        assert!(UnkRule::category("[a-", "ALPHA").is_err());
+    }
+
+    #[test]
+    fn test_find() {
+        let mut rules = UnkRules::default();
+        let category_word_ids = |c: &str| {
+            (c == "ALPHA").then(|| vec![(WordIdx::new(LexType::Unknown, 1), WordParam::default())])
+        };
+        let word = UnkRule::word(r"\d+(\.\d+)?", 1, 2, 3, "number").unwrap();
+        rules.push(word, category_word_ids).unwrap();
+        let category = UnkRule::category(r"[a-z]+", "ALPHA").unwrap();
+        rules.push(category, category_word_ids).unwrap();
+        let undefined = UnkRule::category(r"x*", "UNDEFINED").unwrap();
+        rules.push(undefined, category_word_ids).unwrap();
+
+        let char_prop = CharProperty::from_reader("DEFAULT 0 1 0".as_bytes()).unwrap();
+        let mut sent = Sentence::new();
+        sent.set_sentence("円3.14とpi");
+        sent.compile(&char_prop);
+        let matches: Vec<_> = rules
+            .find(&sent)
+            .iter()
+            .map(|m| (m.start_char, m.end_char, m.words.to_vec()))
+            .collect();
+        assert_eq!(
+            matches,
+            [
+                (
+                    1,
+                    5,
+                    vec![(
+                        WordIdx::new(LexType::Unknown, FIRST_WORD_ID),
+                        WordParam::new(1, 2, 3)
+                    )],
+                ),
+                (
+                    6,
+                    8,
+                    vec![(WordIdx::new(LexType::Unknown, 1), WordParam::default())],
+                ),
+            ]
+        );
+
+        assert_eq!(
+            rules.get(WordIdx::new(LexType::Unknown, FIRST_WORD_ID)),
+            Some(("number", WordParam::new(1, 2, 3)))
+        );
+        assert_eq!(rules.get(WordIdx::new(LexType::Unknown, 1)), None);
+        assert_eq!(
+            rules.get(WordIdx::new(LexType::Unknown, FIRST_WORD_ID + 1)),
+            None
+        );
+        assert_eq!(rules.get(WordIdx::new(LexType::User, FIRST_WORD_ID)), None);
+    }
+}
@@ -0,0 +1,71 @@
+//! User lexicons attached to a tokenizer at runtime.
+use std::sync::Arc;
+
+use crate::dictionary::LexType;
+use crate::dictionary::lexicon::Lexicon;
+use crate::dictionary::word_idx::WordIdx;
+use crate::errors::{Result, VibratoError};
+
+/// User lexicons attached to a tokenizer without rebuilding the dictionary.
+///
+/// Words in the lexicons are identified with [`LexType::User`], and their ids follow
+/// those of the user lexicon in the dictionary, i.e., the ids of the `i`-th lexicon
+/// start from the end of the `(i-1)`-th one.
+#[derive(Clone, Default)]
+pub(crate) struct UserLexicons {
+    // Pairs of the first word id and the lexicon, in ascending order of the ids.
+    lexicons: Vec<(u32, Arc<Lexicon>)>,
+}
+
+impl UserLexicons {
+    /// Appends a lexicon.
+    ///
+    /// `first_id` is the first word id when no lexicon has been attached yet,
+    /// that is, the number of words in the user lexicon of the dictionary.
+    pub fn push(&mut self, lexicon: Lexicon, first_id: u32) -> Result<()> {
+        let first_id = self
+            .lexicons
+            .last()
+            .map_or(Some(first_id), |(id, lex)| Self::end_id(*id, lex))
+            .ok_or_else(Self::too_many_words)?;
+        Self::end_id(first_id, &lexicon).ok_or_else(Self::too_many_words)?;
+        self.lexicons.push((first_id, Arc::new(lexicon)));
+        Ok(())
+    }
+
+    /// Gets the iterator of the first word ids and the lexicons.
+    #[inline(always)]
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &Lexicon)> {
+        self.lexicons.iter().map(|(id, lex)| (*id, lex.as_ref()))
+    }
+
+    /// Gets the lexicon containing the word and the index in the lexicon.
+    ///
+    /// Returns `None` if the word is not from the attached lexicons.
+    #[inline(always)]
+    pub fn get(&self, word_idx: WordIdx) -> Option<(&Lexicon, WordIdx)> {
+        if word_idx.lex_type != LexType::User {
+            return None;
+        }
+        self.lexicons
+            .iter()
+            .rev()
+            .find(|(id, _)| *id <= word_idx.word_id)
+            .map(|(id, lex)| {
+                (
+                    lex.as_ref(),
+                    WordIdx::new(LexType::User, word_idx.word_id - id),
+                )
+            })
+    }
+
+    fn end_id(first_id: u32, lexicon: &Lexicon) -> Option<u32> {
+        u32::try_from(lexicon.num_words())
+            .ok()
+            .and_then(|n| first_id.checked_add(n))
+    }
+
+    fn too_many_words() -> VibratoError {
+        VibratoError::invalid_argument("user_lexicon_rdr", "includes too many words.")
+    }
+}
@@ -1,24 +1,45 @@
 //! Provider of a routine for tokenization.
+use std::collections::HashSet;
+use std::io::Write;
+use std::ops::Range;
+
+use rand_core::Rng;
+
+use crate::char_filter::FilteredSentence;
+use crate::common::BOS_EOS_CONNECTION_ID;
 use crate::dictionary::connector::ConnectorView;
 use crate::dictionary::mapper::{ConnIdCounter, ConnIdProbs};
-use crate::dictionary::{ConnectorKindRef, DictionaryInnerRef};
+use crate::dictionary::word_idx::WordIdx;
+use crate::dictionary::{ConnectorKindRef, DictionaryInnerRef, LexType};
+use crate::errors::{Result, VibratoError};
 use crate::sentence::Sentence;
-use crate::token::{NbestTokenIter, Token, TokenIter};
+use crate::token::{LatticeNodeIter, NbestTokenIter, Token, TokenIter};
 use crate::tokenizer::Tokenizer;
+use crate::tokenizer::constraints::{ConstraintTable, Constraints};
+use crate::tokenizer::explain::{Explanation, PathBreakdown};
+use crate::tokenizer::export;
 use crate::tokenizer::lattice::{Lattice, LatticeKind, Node};
+use crate::tokenizer::marginal::Marginals;
 use crate::tokenizer::nbest_generator::NbestGenerator;
+use crate::tokenizer::search::TokenizeMode;
+use crate::utils::FromU32;
 
 /// Provider of a routine for tokenization.
 ///
 /// It holds the internal data structures used in tokenization,
 /// which can be reused to avoid unnecessary memory reallocation.
+/// A worker is [`Send`], so it can be moved to another thread or an asynchronous task.
 pub struct Worker {
     pub(crate) tokenizer: Tokenizer,
     pub(crate) sent: Sentence,
+    pub(crate) filtered: FilteredSentence,
     pub(crate) lattice: LatticeKind,
     pub(crate) top_nodes: Vec<(usize, Node)>,
     pub(crate) counter: Option<ConnIdCounter>,
-    pub(crate) nbest_paths: Vec<(Vec<*const Node>, i32)>,
+    pub(crate) nbest_paths: Vec<(Vec<Node>, i32)>,
+    pub(crate) constraint_table: ConstraintTable,
+    pub(crate) marginals: Marginals,
+    pub(crate) granularity: Granularity,
 }
 
 impl Worker {
@@ -27,21 +48,104 @@ impl Worker {
         Self {
             tokenizer,
             sent: Sentence::new(),
+            filtered: FilteredSentence::default(),
             lattice: LatticeKind::For1Best(Lattice::default()),
             top_nodes: vec![],
             counter: None,
             nbest_paths: Vec::with_capacity(0),
+            constraint_table: ConstraintTable::default(),
+            marginals: Marginals::default(),
+            granularity: Granularity::Coarse,
+        }
+    }
+
+    /// Sets the granularity of the resultant tokens, which takes effect from the next
+    /// tokenization.
+    ///
+    /// [`Granularity::Fine`] splits the tokens of words having sub-words in the dictionary,
+    /// which is applied to the results of [`Self::tokenize()`],
+    /// [`Self::tokenize_with_constraints()`], and [`Self::sample()`].
+    /// See [`SubWords`](crate::dictionary::SubWords) for the details.
+    pub fn set_granularity(&mut self, granularity: Granularity) {
+        self.granularity = granularity;
+    }
+
+    /// Gets the granularity of the resultant tokens.
+    #[inline(always)]
+    pub const fn granularity(&self) -> Granularity {
+        self.granularity
+    }
+
+    /// Splits the resultant tokens from `start` into their sub-words
+    /// in [`Granularity::Fine`].
+    fn split_into_sub_words(&mut self, start: usize) {
+        if self.granularity == Granularity::Coarse {
+            return;
         }
+        let Some(sub_words) = self.tokenizer.sub_words() else {
+            return;
+        };
+        let has_sub_words = |node: &Node| {
+            node.lex_type == LexType::System && !sub_words.get(node.word_id).is_empty()
+        };
+        if !self.top_nodes[start..]
+            .iter()
+            .any(|(_, node)| has_sub_words(node))
+        {
+            return;
+        }
+        // The tokens are stored in the reverse order, so the sub-words are pushed
+        // from the last one.
+        let mut split = Vec::with_capacity(self.top_nodes.len() - start);
+        for &(end_word, node) in &self.top_nodes[start..] {
+            if !has_sub_words(&node) {
+                split.push((end_word, node));
+                continue;
+            }
+            let mut end = end_word;
+            for &(word_id, len_char) in sub_words.get(node.word_id).iter().rev() {
+                let start_word = end - usize::from_u32(len_char);
+                let param = self
+                    .tokenizer
+                    .word_param(WordIdx::new(LexType::System, word_id));
+                split.push((
+                    end,
+                    Node {
+                        word_id,
+                        lex_type: LexType::System,
+                        start_node: if start_word == node.start_word {
+                            node.start_node
+                        } else {
+                            start_word
+                        },
+                        start_word,
+                        left_id: param.left_id,
+                        right_id: param.right_id,
+                        ..node
+                    },
+                ));
+                end = start_word;
+            }
+        }
+        self.top_nodes.truncate(start);
+        self.top_nodes.append(&mut split);
     }
 
     /// Resets the input sentence to be tokenized.
+    ///
+    /// The character filters of the tokenizer are applied to the sentence.
     pub fn reset_sentence<S>(&mut self, input: S)
     where
         S: AsRef<str>,
     {
+        self.tokenizer.refresh_dictionary();
         self.sent.clear();
         self.top_nodes.clear();
-        let input = input.as_ref();
+        self.marginals.clear();
+        let mut input = input.as_ref();
+        if !self.tokenizer.char_filters().is_empty() {
+            input = self.filtered.apply(input, self.tokenizer.char_filters());
+        }
         if !input.is_empty() {
             self.sent.set_sentence(input);
             match self.tokenizer.dictionary() {
@@ -55,16 +159,352 @@ impl Worker {
         }
     }
 
+    /// Switches to the latest dictionary if the tokenizer follows a
+    /// [`DictionaryHandle`](crate::dictionary::DictionaryHandle) and it has been swapped.
+    ///
+    /// The sentence is compiled again with the new dictionary.
+    fn sync_dictionary(&mut self) {
+        if self.tokenizer.refresh_dictionary() && !self.sent.chars().is_empty() {
+            let input = self.original_sentence().to_string();
+            self.reset_sentence(input);
+        }
+    }
+
+    /// Gets the sentence before the character filters are applied.
+    #[inline(always)]
+    pub(crate) fn original_sentence(&self) -> &str {
+        if self.tokenizer.char_filters().is_empty() {
+            self.sent.raw()
+        } else {
+            self.filtered.original()
+        }
+    }
+
+    /// Maps a byte range in the filtered sentence to the one in the original sentence.
+    #[inline(always)]
+    pub(crate) fn original_range_byte(&self, range_byte: Range<usize>) -> Range<usize> {
+        if self.tokenizer.char_filters().is_empty() {
+            range_byte
+        } else {
+            self.filtered.original_range_byte(range_byte)
+        }
+    }
+
+    /// Maps a range in the filtered sentence to the character range in the original one.
+    #[inline(always)]
+    pub(crate) fn original_range_char(
+        &self,
+        range_char: Range<usize>,
+        range_byte: Range<usize>,
+    ) -> Range<usize> {
+        if self.tokenizer.char_filters().is_empty() {
+            range_char
+        } else {
+            let range_byte = self.filtered.original_range_byte(range_byte);
+            self.filtered.original_range_char(range_byte)
+        }
+    }
+
     /// Tokenizes the input sentence set in `state`,
     /// returning the result through `state`.
+    ///
+    /// In [`TokenizeMode::Extended`], the tokens of both the normal and search modes are
+    /// output in the order of their starting positions, where a longer token comes first.
     pub fn tokenize(&mut self) {
+        self.top_nodes.clear();
+        self.tokenize_continued(BOS_EOS_CONNECTION_ID);
+    }
+
+    /// Tokenizes the input sentence as a continuation of a preceding token whose
+    /// right connection id is `right_id`.
+    ///
+    /// The resultant tokens are appended to the existing ones, and their total costs are
+    /// relative to the preceding token.
+    pub(crate) fn tokenize_continued(&mut self, right_id: u16) {
+        self.sync_dictionary();
+        self.marginals.clear();
         if self.sent.chars().is_empty() {
             return;
         }
+        let start = self.top_nodes.len();
+        let is_extended = matches!(self.tokenizer.tokenize_mode(), TokenizeMode::Extended(_));
+        if is_extended {
+            let lattice_1best = self.lattice.prepare_for_1best(self.sent.len_char());
+            lattice_1best.set_bos_right_id(right_id);
+            self.tokenizer
+                .build_lattice_with_penalty(&self.sent, lattice_1best, None, None);
+            lattice_1best.append_top_nodes(&mut self.top_nodes);
+        }
         let lattice_1best = self.lattice.prepare_for_1best(self.sent.len_char());
+        lattice_1best.set_bos_right_id(right_id);
 
-        self.tokenizer.build_lattice(&self.sent, lattice_1best);
+        self.tokenizer
+            .build_lattice(&self.sent, lattice_1best, None);
+        lattice_1best.append_top_nodes(&mut self.top_nodes);
+
+        if is_extended {
+            // Merges the tokens of the both modes, which are stored in the reverse order.
+            // The preceding tokens are kept as they are.
+            let mut appended = self.top_nodes.split_off(start);
+            appended.sort_by(|(end_a, a), (end_b, b)| {
+                b.start_word.cmp(&a.start_word).then(end_a.cmp(end_b))
+            });
+            appended.dedup_by(|(end_a, a), (end_b, b)| {
+                end_a == end_b && a.start_word == b.start_word && a.word_idx() == b.word_idx()
+            });
+            self.top_nodes.append(&mut appended);
+        }
+        self.split_into_sub_words(start);
+    }
+
+    /// Tokenizes the input sentence under the given constraints,
+    /// returning the result through `state`.
+    ///
+    /// Only tokenizations satisfying `constraints` are considered.
+    /// See [`Constraints`] for the available constraints.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when
+    ///  - the constraints are out of the sentence or conflict with each other, or
+    ///  - no tokenization satisfies the constraints.
+    pub fn tokenize_with_constraints(&mut self, constraints: &Constraints) -> Result<()> {
+        self.sync_dictionary();
+        self.top_nodes.clear();
+        self.marginals.clear();
+        self.constraint_table
+            .reset(constraints, self.sent.len_char())?;
+        if self.sent.chars().is_empty() {
+            return Ok(());
+        }
+        let lattice_1best = self.lattice.prepare_for_1best(self.sent.len_char());
+
+        self.tokenizer
+            .build_lattice(&self.sent, lattice_1best, Some(&self.constraint_table));
+        if !lattice_1best.has_eos() {
+            return Err(VibratoError::invalid_argument(
+                "constraints",
+                "no tokenization satisfies the constraints",
+            ));
+        }
         lattice_1best.append_top_nodes(&mut self.top_nodes);
+        self.split_into_sub_words(0);
+        Ok(())
+    }
+
+    /// Samples a tokenization of the input sentence from the lattice distribution,
+    /// returning the result through `state` in the same manner as [`Self::tokenize()`].
+    ///
+    /// The probability of a tokenization is proportional to `exp(-cost / temperature)`,
+    /// so a higher temperature gives more diverse results. This is useful for
+    /// subword regularization.
+    ///
+    /// Note that [`Token::total_cost()`](crate::token::Token::total_cost) of a sampled token
+    /// is still the minimum cost from BOS to the token.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when `temperature` is not a positive finite number.
+    pub fn sample<R>(&mut self, rng: &mut R, temperature: f64) -> Result<()>
+    where
+        R: Rng + ?Sized,
+    {
+        if !(temperature.is_finite() && temperature > 0.0) {
+            return Err(VibratoError::invalid_argument(
+                "temperature",
+                "must be a positive finite number",
+            ));
+        }
+        self.top_nodes.clear();
+        self.marginals.clear();
+        if self.sent.chars().is_empty() {
+            return Ok(());
+        }
+        let lattice_1best = self.lattice.prepare_for_1best(self.sent.len_char());
+
+        self.tokenizer
+            .build_lattice(&self.sent, lattice_1best, None);
+        match self.tokenizer.dictionary().connector() {
+            ConnectorKindRef::Archived(connector) => self.marginals.sample(
+                lattice_1best,
+                connector,
+                temperature,
+                rng,
+                &mut self.top_nodes,
+            ),
+            ConnectorKindRef::Owned(connector) => self.marginals.sample(
+                lattice_1best,
+                connector,
+                temperature,
+                rng,
+                &mut self.top_nodes,
+            ),
+        }
+        self.split_into_sub_words(0);
+        Ok(())
+    }
+
+    /// Computes the marginal probabilities of the words in the lattice,
+    /// like MeCab's `-m` option.
+    ///
+    /// The probability of a tokenization is proportional to `exp(-cost / temperature)`,
+    /// so a higher temperature gives a flatter distribution.
+    /// This must be called after [`Self::tokenize()`] or [`Self::tokenize_with_constraints()`],
+    /// and the results can be accessed via [`Token::marginal_prob()`](crate::token::Token::marginal_prob)
+    /// and [`Self::marginal_prob()`].
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when
+    ///  - `temperature` is not a positive finite number, or
+    ///  - the sentence has not been tokenized.
+    pub fn compute_marginals(&mut self, temperature: f64) -> Result<()> {
+        if !(temperature.is_finite() && temperature > 0.0) {
+            return Err(VibratoError::invalid_argument(
+                "temperature",
+                "must be a positive finite number",
+            ));
+        }
+        if self.sent.chars().is_empty() {
+            return Ok(());
+        }
+        let LatticeKind::For1Best(lattice) = &self.lattice else {
+            return Err(VibratoError::invalid_state(
+                "The sentence has not been tokenized.",
+                "call tokenize() before computing marginals",
+            ));
+        };
+        if self.top_nodes.is_empty() || !lattice.has_eos() {
+            return Err(VibratoError::invalid_state(
+                "The sentence has not been tokenized.",
+                "call tokenize() before computing marginals",
+            ));
+        }
+        match self.tokenizer.dictionary().connector() {
+            ConnectorKindRef::Archived(connector) => {
+                self.marginals.compute(lattice, connector, temperature)
+            }
+            ConnectorKindRef::Owned(connector) => {
+                self.marginals.compute(lattice, connector, temperature)
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the marginal probability of the word `word_idx` spanning `range_char`.
+    ///
+    /// Any candidate in the lattice can be queried, not only those in the best path.
+    /// Returns `None` if [`Self::compute_marginals()`] has not been called
+    /// for the current sentence or the candidate does not exist in the lattice.
+    pub fn marginal_prob(&self, range_char: Range<usize>, word_idx: WordIdx) -> Option<f64> {
+        match &self.lattice {
+            LatticeKind::For1Best(lattice) => self.marginals.prob(lattice, range_char, word_idx),
+            LatticeKind::ForNBest(_) => None,
+        }
+    }
+
+    /// Explains why the input sentence is tokenized as it is, comparing the costs of
+    /// the best path with those of an alternative segmentation.
+    ///
+    /// `words` specifies the alternative segmentation as consecutive spans in characters
+    /// covering the whole sentence. Each span can have a feature pattern in the same
+    /// format as [`Constraints::add_word()`]; if not, the cheapest word in the span is used.
+    ///
+    /// After this, the best tokenization is stored as with [`Self::tokenize()`].
+    /// The costs are always explained at [`Granularity::Coarse`].
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when
+    ///  - the spans are not consecutive or do not cover the sentence, or
+    ///  - no tokenization follows the alternative segmentation.
+    pub fn explain<S>(&mut self, words: &[(Range<usize>, Option<S>)]) -> Result<Explanation>
+    where
+        S: AsRef<str>,
+    {
+        let mut constraints = Constraints::new();
+        let mut end_char = 0;
+        for (range, pattern) in words {
+            if range.start != end_char {
+                return Err(VibratoError::invalid_argument(
+                    "words",
+                    format!("the span {range:?} does not start at {end_char}"),
+                ));
+            }
+            constraints.add_word(range.clone(), pattern.as_ref());
+            end_char = range.end;
+        }
+        if end_char != self.sent.len_char() {
+            return Err(VibratoError::invalid_argument(
+                "words",
+                "the spans do not cover the whole sentence",
+            ));
+        }
+
+        let granularity = std::mem::replace(&mut self.granularity, Granularity::Coarse);
+        let explanation = self.explain_constraints(&constraints);
+        self.granularity = granularity;
+        explanation
+    }
+
+    fn explain_constraints(&mut self, constraints: &Constraints) -> Result<Explanation> {
+        self.tokenize_with_constraints(constraints)?;
+        let alternative = self.path_breakdown();
+        // The best path is found in the same lattice as the alternative, without merging
+        // the tokens of the normal mode in the extended mode.
+        self.tokenize_with_constraints(&Constraints::new())?;
+        let best = self.path_breakdown();
+        if matches!(self.tokenizer.tokenize_mode(), TokenizeMode::Extended(_)) {
+            self.tokenize();
+        }
+        Ok(Explanation { best, alternative })
+    }
+
+    /// Explains why the input sentence is tokenized as it is, comparing the costs of
+    /// the best path with those of an alternative segmentation given as surfaces.
+    ///
+    /// This is a shorthand of [`Self::explain()`] without feature patterns.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when
+    ///  - the concatenation of `surfaces` is not identical to the sentence, or
+    ///  - no tokenization follows the alternative segmentation.
+    pub fn explain_surfaces<S>(&mut self, surfaces: &[S]) -> Result<Explanation>
+    where
+        S: AsRef<str>,
+    {
+        let raw = self.sent.raw();
+        let mut words = Vec::with_capacity(surfaces.len());
+        let mut start_byte = 0;
+        let mut start_char = 0;
+        for surface in surfaces {
+            let surface = surface.as_ref();
+            if surface.is_empty() || !raw[start_byte..].starts_with(surface) {
+                return Err(VibratoError::invalid_argument(
+                    "surfaces",
+                    format!("{surface:?} does not match the sentence at byte {start_byte}"),
+                ));
+            }
+            let end_char = start_char + surface.chars().count();
+            words.push((start_char..end_char, None::<&str>));
+            start_byte += surface.len();
+            start_char = end_char;
+        }
+        if start_byte != raw.len() {
+            return Err(VibratoError::invalid_argument(
+                "surfaces",
+                "the surfaces do not cover the whole sentence",
+            ));
+        }
+        self.explain(&words)
+    }
+
+    fn path_breakdown(&self) -> PathBreakdown {
+        match self.tokenizer.dictionary().connector() {
+            ConnectorKindRef::Archived(connector) => PathBreakdown::from_worker(self, connector),
+            ConnectorKindRef::Owned(connector) => PathBreakdown::from_worker(self, connector),
+        }
     }
 
     /// Tokenizes the sentence and stores the top N-best results internally.
@@ -72,8 +512,19 @@ impl Worker {
     /// After calling this, the results can be accessed via `num_nbest_paths()`,
     /// `path_cost(path_idx)`, and `nbest_token_iter(path_idx)`.
     pub fn tokenize_nbest(&mut self, n: usize) {
+        self.tokenize_nbest_with_options(n, &NbestOptions::default());
+    }
+
+    /// Tokenizes the sentence and stores at most `n` best results filtered by `options`.
+    ///
+    /// The results are accessed in the same manner as [`Self::tokenize_nbest()`].
+    /// Passing `usize::MAX` as `n` with [`NbestOptions::cost_beam()`] stores
+    /// all the paths within the beam.
+    pub fn tokenize_nbest_with_options(&mut self, n: usize, options: &NbestOptions) {
+        self.sync_dictionary();
         self.nbest_paths.clear();
-        if self.sent.chars().is_empty() {
+        self.marginals.clear();
+        if self.sent.chars().is_empty() || n == 0 {
             return;
         }
         let lattice_nbest = self.lattice.prepare_for_nbest(self.sent.len_char());
@@ -81,18 +532,33 @@ impl Worker {
         self.tokenizer
             .build_lattice_nbest(&self.sent, lattice_nbest);
 
-        let dict_ref = self.tokenizer.dictionary();
-        let connector_ref = dict_ref.connector();
+        let generator = match self.tokenizer.dictionary().connector() {
+            ConnectorKindRef::Archived(connector) => NbestGenerator::new(lattice_nbest, connector),
+            ConnectorKindRef::Owned(connector) => NbestGenerator::new(lattice_nbest, connector),
+        };
 
-        let generator = match connector_ref {
-            ConnectorKindRef::Archived(connector) => {
-                NbestGenerator::new(lattice_nbest, connector, dict_ref)
+        // Paths are generated in ascending order of their costs.
+        let mut best_cost = None;
+        let mut seen_boundaries = HashSet::new();
+        for (path, cost) in generator {
+            let best_cost = *best_cost.get_or_insert(cost);
+            if options
+                .cost_beam
+                .is_some_and(|theta| best_cost.saturating_add(theta) < cost)
+            {
+                break;
             }
-            ConnectorKindRef::Owned(connector) => {
-                NbestGenerator::new(lattice_nbest, connector, dict_ref)
+            if options.distinct_segmentation {
+                let boundaries: Vec<usize> = path.iter().map(|node| node.start_word).collect();
+                if !seen_boundaries.insert(boundaries) {
+                    continue;
+                }
             }
-        };
-        self.nbest_paths = generator.take(n).collect();
+            self.nbest_paths.push((path, cost));
+            if self.nbest_paths.len() == n {
+                break;
+            }
+        }
     }
 
     /// Gets the number of resultant tokens.
@@ -114,6 +580,89 @@ impl Worker {
         TokenIter::new(self)
     }
 
+    /// Creates an iterator of all the candidate nodes in the lattice, like MeCab's `-a` option.
+    ///
+    /// It yields nodes from the system and user lexicons and unknown words,
+    /// including those not in the best path. The lattice is available after
+    /// [`Self::tokenize()`], [`Self::tokenize_with_constraints()`], or [`Self::sample()`];
+    /// otherwise, the iterator is empty.
+    #[inline(always)]
+    pub fn lattice_nodes(&self) -> LatticeNodeIter<'_> {
+        LatticeNodeIter::new(self, self.tokenized_lattice())
+    }
+
+    /// Writes the lattice in the Graphviz DOT format, highlighting the best path.
+    ///
+    /// Nodes are labeled with their surfaces, features, word costs, and total costs,
+    /// and edges are labeled with their connection costs.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when
+    ///  - the sentence has not been tokenized by [`Self::tokenize()`], or
+    ///  - writing fails.
+    pub fn write_lattice_dot<W>(&self, wtr: W) -> Result<()>
+    where
+        W: Write,
+    {
+        let lattice = self.exported_lattice()?;
+        let tokenizer = &self.tokenizer;
+        match tokenizer.dictionary().connector() {
+            ConnectorKindRef::Archived(c) => {
+                export::write_dot(wtr, &self.sent, lattice, tokenizer, c)
+            }
+            ConnectorKindRef::Owned(c) => export::write_dot(wtr, &self.sent, lattice, tokenizer, c),
+        }
+    }
+
+    /// Writes the lattice as a single-line JSON object.
+    ///
+    /// The object has `sentence`, `nodes`, and `edges` fields.
+    /// The first and last nodes are BOS and EOS, respectively,
+    /// and nodes and edges in the best path have `"best": true`.
+    ///
+    /// # Errors
+    ///
+    /// [`VibratoError`] is returned when
+    ///  - the sentence has not been tokenized by [`Self::tokenize()`], or
+    ///  - writing fails.
+    pub fn write_lattice_json<W>(&self, wtr: W) -> Result<()>
+    where
+        W: Write,
+    {
+        let lattice = self.exported_lattice()?;
+        let tokenizer = &self.tokenizer;
+        match tokenizer.dictionary().connector() {
+            ConnectorKindRef::Archived(c) => {
+                export::write_json(wtr, &self.sent, lattice, tokenizer, c)
+            }
+            ConnectorKindRef::Owned(c) => {
+                export::write_json(wtr, &self.sent, lattice, tokenizer, c)
+            }
+        }
+    }
+
+    /// Gets the 1-best lattice if it has been built for the current sentence.
+    #[inline(always)]
+    fn tokenized_lattice(&self) -> Option<&Lattice> {
+        match &self.lattice {
+            LatticeKind::For1Best(lattice) if !self.top_nodes.is_empty() => Some(lattice),
+            _ => None,
+        }
+    }
+
+    fn exported_lattice(&self) -> Result<Option<&Lattice>> {
+        if self.sent.chars().is_empty() {
+            return Ok(None);
+        }
+        self.tokenized_lattice().map(Some).ok_or_else(|| {
+            VibratoError::invalid_state(
+                "The sentence has not been tokenized.",
+                "call tokenize() before exporting the lattice",
+            )
+        })
+    }
+
     /// Returns an iterator over the tokens in the N-best path at `path_idx`.
     pub fn nbest_token_iter(&self, path_idx: usize) -> Option<NbestTokenIter<'_>> {
         if path_idx < self.nbest_paths.len() {
@@ -172,3 +721,49 @@ impl Worker {
         self.nbest_paths.get(path_idx).map(|(_, cost)| *cost)
     }
 }
+
+/// Granularity of the resultant tokens, specified with [`Worker::set_granularity()`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Granularity {
+    /// Outputs the words in the dictionary as they are.
+    #[default]
+    Coarse,
+
+    /// Outputs the sub-words of words decomposed in the dictionary,
+    /// e.g., short units of UniDic instead of long units.
+    Fine,
+}
+
+/// Options for [`Worker::tokenize_nbest_with_options()`].
+#[derive(Clone, Debug, Default)]
+pub struct NbestOptions {
+    cost_beam: Option<i32>,
+    distinct_segmentation: bool,
+}
+
+impl NbestOptions {
+    /// Creates options equivalent to [`Worker::tokenize_nbest()`].
+    pub const fn new() -> Self {
+        Self {
+            cost_beam: None,
+            distinct_segmentation: false,
+        }
+    }
+
+    /// Stops the search once a path costs more than the best one plus `theta`,
+    /// like MeCab's cost beam.
+    pub const fn cost_beam(mut self, theta: i32) -> Self {
+        self.cost_beam = Some(theta);
+        self
+    }
+
+    /// Deduplicates paths by their token boundaries only, e.g., paths that differ
+    /// only in part-of-speech tags are reduced to the cheapest one.
+    ///
+    /// Since duplicated paths are still enumerated internally, it is recommended to
+    /// combine this with [`Self::cost_beam()`] for long sentences.
+    pub const fn distinct_segmentation(mut self, yes: bool) -> Self {
+        self.distinct_segmentation = yes;
+        self
+    }
+}
@@ -1,4 +1,3 @@
-#[cfg(feature = "train")]
 use std::io::Write;
 
 use csv_core::ReadFieldResult;
@@ -17,7 +16,6 @@ impl FromU32 for usize {
     }
 }
 
-#[cfg(feature = "train")]
 pub fn quote_csv_cell<W>(mut wtr: W, mut data: &[u8]) -> std::io::Result<()>
 where
     W: Write,
@@ -42,24 +40,53 @@ pub fn parse_csv_row(row: &str) -> Vec<String> {
     let mut features = vec![];
     let mut rdr = csv_core::Reader::new();
     let mut bytes = row.as_bytes();
-    let mut output = [0; 4096];
+    let mut field = vec![];
     loop {
-        let (result, nin, nout) = rdr.read_field(bytes, &mut output);
-        let end = match result {
-            ReadFieldResult::InputEmpty => true,
-            ReadFieldResult::Field { .. } => false,
-            ReadFieldResult::End => true,
-            _ => unreachable!(),
-        };
-        features.push(std::str::from_utf8(&output[..nout]).unwrap().to_string());
+        let end = read_csv_field(&mut rdr, &mut bytes, &mut field);
+        features.push(String::from_utf8(field.clone()).unwrap());
         if end {
             break;
         }
-        bytes = &bytes[nin..];
     }
     features
 }
 
+/// Parses the row until the column at the index, returning its value.
+pub fn parse_csv_field(row: &str, index: usize) -> Option<String> {
+    let mut rdr = csv_core::Reader::new();
+    let mut bytes = row.as_bytes();
+    let mut field = vec![];
+    for i in 0.. {
+        let end = read_csv_field(&mut rdr, &mut bytes, &mut field);
+        if i == index {
+            return Some(String::from_utf8(field).unwrap());
+        }
+        if end {
+            break;
+        }
+    }
+    None
+}
+
+/// Reads the next field of the row into `field`, consuming the input.
+/// Returns `true` if the field is the last one.
+///
+/// Fields longer than the output buffer are read in several steps.
+fn read_csv_field(rdr: &mut csv_core::Reader, bytes: &mut &[u8], field: &mut Vec<u8>) -> bool {
+    let mut output = [0; 4096];
+    field.clear();
+    loop {
+        let (result, nin, nout) = rdr.read_field(bytes, &mut output);
+        *bytes = &bytes[nin..];
+        field.extend_from_slice(&output[..nout]);
+        match result {
+            ReadFieldResult::OutputFull => continue,
+            ReadFieldResult::Field { .. } => return false,
+            ReadFieldResult::InputEmpty | ReadFieldResult::End => return true,
+        }
+    }
+}
+
 #[cfg(test)]
 macro_rules! hashmap {
     ( $($k:expr => $v:expr,)* ) => {
@@ -99,4 +126,28 @@ mod tests {
             parse_csv_row("名詞,\"1,2-ジクロロエタン\"").as_slice()
         );
     }
+
+    #[test]
+    fn test_parse_csv_field() {
+        let row = "名詞,\"1,2-ジクロロエタン\",*";
+        assert_eq!(Some("名詞".to_string()), parse_csv_field(row, 0));
+        assert_eq!(
+            Some("1,2-ジクロロエタン".to_string()),
+            parse_csv_field(row, 1)
+        );
+        assert_eq!(Some("*".to_string()), parse_csv_field(row, 2));
+        assert_eq!(None, parse_csv_field(row, 3));
+    }
+
+    #[test]
+    fn test_parse_csv_long_field() {
+        let long = "あ".repeat(5000);
+        let row = format!("名詞,\"{long},*\",*");
+        assert_eq!(
+            vec!["名詞".to_string(), format!("{long},*"), "*".to_string()],
+            parse_csv_row(&row)
+        );
+        assert_eq!(Some(format!("{long},*")), parse_csv_field(&row, 1));
+        assert_eq!(Some("*".to_string()), parse_csv_field(&row, 2));
+    }
 }